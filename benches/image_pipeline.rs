@@ -0,0 +1,48 @@
+use image_to_tetris::approx_image::{self, bench, draw, resize_image};
+use image_to_tetris::cli::{Config, GlobalData};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use image::DynamicImage;
+
+const BOARD_WIDTH: usize = 32;
+const BOARD_HEIGHT: usize = 20;
+
+// a canned source and skin resized to fit the benchmark board, so results don't depend on `assets`
+fn setup() -> (DynamicImage, Config, GlobalData) {
+    let mut source_img = image::open("examples/sunset_1280.jpg").expect("could not load source image");
+    let skin = draw::BlockSkin::new("test_images/HqGYC5G - Imgur.png", 0).expect("could not load skin");
+    let mut glob = GlobalData::from_skins(vec![skin]);
+    draw::resize_skins(&mut glob.skins, source_img.width(), source_img.height(), BOARD_WIDTH, BOARD_HEIGHT).unwrap();
+    resize_image(&mut source_img, glob.skin_width(), glob.skin_height(), BOARD_WIDTH, BOARD_HEIGHT);
+
+    let config = Config {
+        board_width: BOARD_WIDTH,
+        board_height: BOARD_HEIGHT,
+        ..Config::default()
+    };
+    (source_img, config, glob)
+}
+
+fn image_pipeline(c: &mut Criterion) {
+    let (source_img, config, glob) = setup();
+
+    c.bench_function("average_pixel_grid", |b| {
+        b.iter(|| bench::pixel_grid(&source_img, glob.skin_width(), glob.skin_height()).unwrap());
+    });
+    c.bench_function("can_place", |b| {
+        b.iter(|| bench::count_placeable(BOARD_WIDTH * 4, BOARD_HEIGHT * 4).unwrap());
+    });
+    c.bench_function("avg_piece_pixel_diff", |b| {
+        b.iter(|| bench::score_all_pieces(&source_img, &config, &glob).unwrap());
+    });
+    c.bench_function("process_heap", |b| {
+        b.iter(|| approx_image::approx_board(&source_img, &config, &glob).unwrap());
+    });
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = image_pipeline
+}
+criterion_main!(benches);
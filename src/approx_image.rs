@@ -1,51 +1,258 @@
+mod animation;
+pub mod avg_grid;
+#[cfg(feature = "bench")]
+pub mod bench;
 pub mod draw;
 pub mod integration_test;
-mod board;
-mod piece;
+mod legend;
+pub mod live_preview;
+pub mod load;
+pub mod mask;
+pub mod panels;
+pub mod pipeline;
+pub mod playfield;
+pub mod preview;
+pub mod render;
+pub mod board;
+pub mod board_diff;
+mod confidence;
+pub mod cost;
+pub mod detail;
+pub mod board_dump;
+mod diff_cache;
+mod fill;
+pub mod pattern_skin;
+pub mod piece;
+pub mod preprocess;
+mod skin_regions;
+mod tie_break;
+pub mod traversal;
+
+use crate::cli::{Config, GlobalData, RngStream};
+use crate::summary::RunSummary;
+use crate::utils::CancelledError;
+use confidence::ConfidenceMap;
+use cost::{AvgPixelDiff, CostContext, PlacementCost};
+use detail::DetailMap;
+use mask::{Mask, MaskFill};
+use diff_cache::DiffCache;
+use draw::{BlockImage, BlockSkin, SkinnedBoard, letterbox_skins, resize_skins};
+use piece::{Cell, Piece, Orientation, TETROMINO_CHARS};
+use pipeline::{Pipeline, PipelineContext};
+use preview::PreviewWriter;
+use tie_break::{near_tie_threshold, Candidates};
+use traversal::CellHeap;
 
-use crate::cli::{Config, GlobalData};
-use board::EMPTY_CELL;
-use draw::{BlockSkin, SkinnedBoard, resize_skins};
-use piece::{Cell, Piece, Orientation};
-
-use std::collections::BinaryHeap;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use image::Rgba;
 use imageproc::image::{DynamicImage, GenericImageView};
+use rand::rngs::StdRng;
+use rayon::prelude::*;
+use thiserror::Error;
+use tracing::{debug, info, info_span, warn};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default)]
 pub enum PrioritizeColor {
     Yes,
+    #[default]
     No
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FillStyle {
+    // black and gray garbage
+    #[default]
+    Garbage,
+    // the single tetromino mino closest in color
+    NearestMino,
+    // the most common tetromino block among neighboring cells
+    Neighbor,
+    // leave the cells empty and transparent
+    Empty,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NoGarbage {
+    // leave the cells no tetromino fits in empty and transparent
+    Empty,
+    // fill them with tetrominoes that may overlap the placed pieces, drawing only their uncovered blocks
+    #[default]
+    Overlap,
+}
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SkinMode {
+    // the one skin that best matches the whole image is used everywhere
+    Single,
+    // every piece may pick any skin
+    #[default]
+    PerPiece,
+    // cells are clustered by color and each cluster is drawn with a single skin
+    PerRegion,
+    // the board is split into horizontal bands that are each drawn with a single skin
+    PerBand,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FitMode {
+    // cells are stretched so the board covers the whole source
+    #[default]
+    Stretch,
+    // cells keep the skins' aspect ratio and the source is padded with black bars to match the board
+    Letterbox,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SmallSource {
+    // refuse sources with fewer pixels than the board has cells
+    #[default]
+    Error,
+    // scale the source up by a whole factor, keeping its pixels sharp
+    Upscale,
+    // shrink the board so each cell covers at least one pixel
+    ShrinkBoard,
+}
+
+#[derive(Debug, Error)]
+pub enum SourceSizeError {
+    #[error("source image is {width}x{height} pixels, which is smaller than the {board_width}x{board_height} board; pass `--small-source upscale` or `--small-source shrink-board` to fit it")]
+    TooSmall { width: u32, height: u32, board_width: usize, board_height: usize },
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PieceRatio {
+    // pieces are picked purely by how well they match
+    #[default]
+    Any,
+    // like a 7-bag randomizer, a tetromino can only be used again once every other tetromino has caught up
+    Standard,
+}
+
+// used to weigh the importance of each diff
+const RED_WEIGHT: f64 = 1.0;
+const GREEN_WEIGHT: f64 = 1.7;
+const BLUE_WEIGHT: f64 = 0.8;
+
+// skin pixels times skins per candidate before a cell's candidates are scored in parallel
+const PARALLEL_SCORING_PIXELS: u32 = 1024;
+
 enum UseGarbage {
     Yes,
     No
 }
 
-pub fn run(source: &Path, output: &Path, config: &Config, glob: &mut GlobalData) {
-    println!("Approximating an image: {}", source.display());
+// state shared by every pass over the heap during a single approximation
+struct ApproxState<'a> {
+    config: &'a Config,
+    source_img: &'a DynamicImage,
+    avg_pixel_grid: Vec<Rgba<u8>>,
+    cache: DiffCache,
+    rng: StdRng,
+    // skin id each cell is restricted to, if skins are assigned per region
+    cell_skins: Option<Vec<usize>>,
+    filled_cells: usize,
+    preview: Option<PreviewWriter>,
+    // pieces and skins of the previous frame's board, indexed by each piece's cell
+    previous_pieces: Vec<Option<(Piece, usize)>>,
+    confidence: Option<ConfidenceMap>,
+    detail: Option<DetailMap>,
+    mask: Option<Mask>,
+}
 
-    let mut source_img = image::open(source).expect("could not load source image");
-    println!("Loaded {}x{} image", source_img.width(), source_img.height());
+// returns a summary of the run, with how close the result is to the source when `config.score` is set
+pub fn run(source: &Path, output: &Path, config: &Config, glob: &GlobalData) -> Result<RunSummary> {
+    let _span = info_span!("approx_image", source = %source.display()).entered();
+    info!("approximating an image");
 
-    // resize the skins globally if appropriate
+    let mut source_img = load::open(source).context("could not load source image")?;
+    info!(width = source_img.width(), height = source_img.height(), "loaded image");
+    let mut config = config.clone();
+    fit_small_image(&mut source_img, &mut config).context("source image does not fit the board")?;
+    let config = &config;
+
+    // resize the skins if appropriate
     let (image_width, image_height) = source_img.dimensions();
-    resize_skins(&mut glob.skins, image_width, image_height, config.board_width, config.board_height).unwrap();
-    println!("Resized skins to {}x{}", glob.skin_width(), glob.skin_height());
+    let glob = &glob.fitted(image_width, image_height, config)?;
+    debug!(width = glob.skin_width(), height = glob.skin_height(), "resized skins");
 
     // resize the source image if needed
-    resize_image(&mut source_img, glob.skin_width(), glob.skin_height(), config.board_width, config.board_height);
+    fit_image(&mut source_img, glob.skin_width(), glob.skin_height(), config);
 
-    let result_img = approx(&source_img, config, glob).expect("could not approximate image");
-    result_img.save(output).expect("could not save output image");
+    let mut pipeline = Pipeline::from_names(&config.stages)?;
+    if config.score {
+        pipeline.push("score")?;
+    }
+    let mut ctx = PipelineContext {
+        config,
+        glob,
+        output,
+        image: source_img,
+        board: None,
+        score: None,
+        summary: RunSummary::new("approx-image"),
+    };
+    pipeline.run(&mut ctx).context("could not approximate image")?;
+    // the partial board has already been saved by now
+    if config.cancel.is_cancelled() {
+        warn!("cancelled, saved the partially approximated image");
+        return Err(CancelledError.into());
+    }
+    if let Some(board) = &ctx.board {
+        ctx.summary.record_board(board)?;
+    }
+    ctx.summary.score = ctx.score;
+    Ok(ctx.summary)
 }
 
 // the source image will be changed in order to fit the scaling of the board
+// candidates are scored in skin id order, then piece and orientation order; on exact ties the first one wins,
+// and near-ties (`config.tie_epsilon`) are broken by an rng drawn from `config.seed`
+// so the result is reproducible for the same source, config, and skin order; `config.deterministic` fixes the skin order
+// if `config.cancel` is triggered, the partially filled board is drawn with unfilled cells left transparent
 pub fn approx(source_img: &DynamicImage, config: &Config, glob: &GlobalData) -> Result<DynamicImage> {
+    let board = approx_board(source_img, config, glob)?;
+    draw::draw(&board)
+}
+
+// approximates encoded image bytes and returns the result as png bytes
+// this avoids the filesystem entirely, so skins must already be loaded, e.g. with `BlockSkin::from_bytes`
+pub fn approx_png(image_bytes: &[u8], skins: draw::Skins, config: &Config) -> Result<Vec<u8>> {
+    if skins.is_empty() {
+        Err(draw::SkinLoadError::Empty)?;
+    }
+    approx_bytes(image_bytes, config, &GlobalData::from_skins(skins))
+}
+
+// same as `approx_png`, but reuses the skins already resized for this size, so repeated calls skip resizing them
+pub fn approx_bytes(image_bytes: &[u8], config: &Config, glob: &GlobalData) -> Result<Vec<u8>> {
+    let mut source_img = image::load_from_memory(image_bytes)?;
+    let mut config = config.clone();
+    fit_small_image(&mut source_img, &mut config)?;
+    let config = &config;
+    let (image_width, image_height) = source_img.dimensions();
+    let glob = glob.fitted(image_width, image_height, config)?;
+
+    fit_image(&mut source_img, glob.skin_width(), glob.skin_height(), config);
+    preprocess::apply(&mut source_img, config)?;
+    let result_img = approx(&source_img, config, &glob)?;
+
+    let mut png = Vec::new();
+    result_img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+    Ok(png)
+}
+
+// same as `approx`, but returns the filled board instead of drawing it
+pub fn approx_board<'a>(source_img: &DynamicImage, config: &Config, glob: &'a GlobalData) -> Result<SkinnedBoard<'a>> {
+    approx_board_seeded(source_img, config, glob, None)
+}
+
+// same as `approx_board`, but with `config.temporal_bias` set, pieces on `previous` are favored
+// this keeps consecutive video frames from flickering between near-equal choices
+pub fn approx_board_seeded<'a>(source_img: &DynamicImage, config: &Config, glob: &'a GlobalData, previous: Option<&SkinnedBoard>) -> Result<SkinnedBoard<'a>> {
     // initialize the board
     let mut board = SkinnedBoard::new(config.board_width, config.board_height, &glob.skins);
 
@@ -55,271 +262,438 @@ pub fn approx(source_img: &DynamicImage, config: &Config, glob: &GlobalData) ->
     // initialize average pixels for context reasons during approximation
     let avg_pixel_grid = average_pixel_grid(source_img, board.skins_width(), board.skins_height())?;
 
-    // init the heap and push the first row of cells into it
-    // the first row is the highest row in number because we are using a max heap
-    let mut heap = BinaryHeap::new();
+    // init the heap with every cell, which pops them in the order of `config.traversal`
+    let mut heap = CellHeap::new(config.traversal, board.board_width(), board.board_height(), &mut config.rng(RngStream::Traversal));
     for y in (0..board.board_height()).rev() {
         for x in 0..board.board_width() {
             heap.push(Cell { x, y });
         }
     }
 
+    let num_regions = config.skin_regions.unwrap_or(glob.skins.len());
+    let cell_skins = match config.skin_mode {
+        SkinMode::PerPiece if config.coarse_to_fine => coarse_skins(source_img, config, glob)?,
+        _ => skin_regions::assign_skins(config.skin_mode, num_regions, &avg_pixel_grid, board.board_width(), board.board_height(), &glob.skins),
+    };
+
+    let mut state = ApproxState {
+        config,
+        source_img,
+        avg_pixel_grid,
+        // per-cell block diffs are shared between all pieces covering a cell
+        cache: DiffCache::new(board.board_width(), board.board_height(), glob.skins.len(), config.garbage_levels > 1),
+        rng: config.rng(RngStream::TieBreak),
+        cell_skins,
+        filled_cells: 0,
+        preview: config.preview.as_ref().map(|preview| PreviewWriter::new(preview, board.board_width() * board.board_height())),
+        previous_pieces: previous.map(previous_pieces).unwrap_or_default(),
+        confidence: config.confidence_out.as_ref().map(|_| ConfidenceMap::new(board.board_width(), board.board_height())),
+        detail: config.detail_weight.map(|weight| DetailMap::new(source_img, weight)),
+        mask: config.mask.as_ref().map(|path| Mask::load(path, board.board_width(), board.board_height())).transpose()?,
+    };
+    if config.mask_fill == MaskFill::Garbage && config.no_garbage.is_none() {
+        fill::fill_masked(&mut board, &mut state)?;
+    }
+
     // perform the approximation
-    match config.prioritize_tetrominos {
-        PrioritizeColor::Yes => process_heap_prioritize(&mut heap, &mut board, source_img, &avg_pixel_grid)?,
-        PrioritizeColor::No => process_heap(&mut heap, &mut board, source_img, &avg_pixel_grid, &UseGarbage::Yes)?
+    match (config.no_garbage, config.prioritize_tetrominos) {
+        (Some(no_garbage), _) => process_heap_no_garbage(&mut heap, &mut board, &mut state, no_garbage)?,
+        (None, PrioritizeColor::Yes) => process_heap_prioritize(&mut heap, &mut board, &mut state)?,
+        (None, PrioritizeColor::No) => process_heap(&mut heap, &mut board, &mut state, &UseGarbage::Yes)?
+    }
+    if config.no_floating && !config.cancel.is_cancelled() {
+        fill::support_floating(&mut board, &mut state)?;
     }
 
-    // draw the board
-    draw::draw(&board)
+    if let (Some(confidence), Some(path)) = (&state.confidence, &config.confidence_out) {
+        confidence.save(path)?;
+    }
+    if let Some(live_preview) = &config.live_preview {
+        live_preview.show(draw::draw_preview(&board)?);
+    }
+
+    Ok(board)
 }
 
-fn process_heap_prioritize(heap: &mut BinaryHeap<Cell>, board: &mut SkinnedBoard, source_img: &DynamicImage, avg_pixel_grid: &[Rgba<u8>]) -> Result<()> {
-    // first try to not use garbage to avoid gray and black blocks
-    process_heap(heap, board, source_img, avg_pixel_grid, &UseGarbage::No)?;
+// approximates a half resolution board first, and keeps each cell to the skin picked for its area there
+// only one skin is scored per cell afterwards, so with many skins most of the search is skipped
+fn coarse_skins(source_img: &DynamicImage, config: &Config, glob: &GlobalData) -> Result<Option<Vec<usize>>> {
+    const SCALE: usize = 2;
+    if glob.skins.len() < 2 || config.board_width < SCALE || config.board_height < SCALE {
+        return Ok(None);
+    }
 
-    // then use garbage with the remaining unfilled cells
-    for y in (0..board.board_height()).rev() {
-        for x in 0..board.board_width() {
-            let cell = Cell { x, y };
-            if board.empty_at(&cell) {
-                heap.push(cell);
+    // the coarse board only needs to cover every cell, so it's a plain approximation without any outputs
+    let (coarse_width, coarse_height) = (config.board_width.div_ceil(SCALE), config.board_height.div_ceil(SCALE));
+    let coarse_config = Config {
+        board_width: coarse_width,
+        board_height: coarse_height,
+        prioritize_tetrominos: PrioritizeColor::No,
+        no_garbage: None,
+        coarse_to_fine: false,
+        preview: None,
+        live_preview: None,
+        confidence_out: None,
+        mask: None,
+        ..config.clone()
+    };
+    let coarse_img = source_img.resize_exact(
+        glob.skin_width() * u32::try_from(coarse_width)?,
+        glob.skin_height() * u32::try_from(coarse_height)?,
+        image::imageops::FilterType::Triangle,
+    );
+    let coarse = approx_board(&coarse_img, &coarse_config, glob)?;
+    debug!(width = coarse_width, height = coarse_height, "approximated coarse board");
+    if config.cancel.is_cancelled() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        (0..config.board_height)
+            .flat_map(|y| (0..config.board_width).map(move |x| Cell { x: x / SCALE, y: y / SCALE }))
+            .map(|cell| coarse.get_cells_skin(&cell))
+            .collect(),
+    ))
+}
+
+fn process_heap_prioritize(heap: &mut CellHeap, board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    // first try to not use garbage to avoid gray and black blocks
+    process_heap(heap, board, state, &UseGarbage::No)?;
+
+    // then fill the remaining unfilled cells
+    match state.config.fill_style {
+        FillStyle::Garbage => {
+            for y in (0..board.board_height()).rev() {
+                for x in 0..board.board_width() {
+                    let cell = Cell { x, y };
+                    if board.empty_at(&cell) {
+                        heap.push(cell);
+                    }
+                }
             }
+            process_heap(heap, board, state, &UseGarbage::Yes)?;
         }
+        FillStyle::NearestMino => fill::fill_nearest_mino(board, state)?,
+        FillStyle::Neighbor => fill::fill_dominant_neighbor(board, state)?,
+        FillStyle::Empty => (),
     }
-    process_heap(heap, board, source_img, avg_pixel_grid, &UseGarbage::Yes)?;
     Ok(())
 }
 
-pub fn resize_image(source_img: &mut DynamicImage, skin_width: u32, skin_height: u32, board_width: usize, board_height: usize) {
-    // resize the source image if needed
-    let resized_width = skin_width * u32::try_from(board_width).unwrap();
-    let resized_height = skin_height * u32::try_from(board_height).unwrap();
-    if resized_width != source_img.width() || resized_height != source_img.height() {
-        let resized_source_buffer = image::imageops::resize(source_img, resized_width, resized_height, image::imageops::FilterType::Lanczos3);
-        *source_img = image::DynamicImage::from(resized_source_buffer);
-    };
+fn process_heap_no_garbage(heap: &mut CellHeap, board: &mut SkinnedBoard, state: &mut ApproxState, no_garbage: NoGarbage) -> Result<()> {
+    process_heap(heap, board, state, &UseGarbage::No)?;
+    match no_garbage {
+        NoGarbage::Empty => (),
+        NoGarbage::Overlap => fill::fill_overlapping(board, state)?,
+    }
+    Ok(())
 }
 
-fn process_heap(heap: &mut BinaryHeap<Cell>, board: &mut SkinnedBoard, source_img: &DynamicImage, avg_pixel_grid: &[Rgba<u8>], use_garbage: &UseGarbage) -> Result<()> {
-    // for each cell at the top of the heap:
-    while let Some(cell) = heap.pop() {
-        // 1. check if the cell is unoccupied
-        if !board.empty_at(&cell) {
-            continue;
+impl ApproxState<'_> {
+    fn record_filled(&mut self, board: &SkinnedBoard, cells: &[Cell]) -> Result<()> {
+        self.filled_cells += cells.len();
+        self.cache.release(cells);
+        if let Some(preview) = &mut self.preview {
+            preview.update(board, self.filled_cells)?;
+        }
+        if let Some(live_preview) = &self.config.live_preview {
+            live_preview.update(|| draw::draw_preview(board))?;
         }
+        Ok(())
+    }
 
-        // 2. for each possible skin, piece, and orientation:
-        let mut best_piece: Option<Piece> = None;
-        let mut best_piece_diff = f64::MAX;
-        let mut best_skin_id: Option<usize> = None;
-
-        for skin in board.iter_skins() {
-            match use_garbage {
-                // try black or gray garbage
-                UseGarbage::Yes => {
-                    for piece in Piece::all_garbage(cell) {
-                        let diff = avg_piece_pixel_diff(&piece, board, skin, source_img, avg_pixel_grid)?;
-                        if diff < best_piece_diff {
-                            best_piece = Some(piece);
-                            best_piece_diff = diff;
-                            best_skin_id = Some(skin.id());
-                        }
-                    }
-                }
-                UseGarbage::No => (),
-            };
+    // lowers the diff of the piece the previous frame placed at the same spot with the same skin
+    fn bias_previous(&self, piece: &Piece, skin_id: usize, diff: f64) -> f64 {
+        let (Some(bias), false) = (self.config.temporal_bias, self.previous_pieces.is_empty()) else {
+            return diff;
+        };
+        let cell = piece.get_cell();
+        match &self.previous_pieces[cell.y * self.config.board_width + cell.x] {
+            Some((prev_piece, prev_skin_id)) if prev_piece == piece && *prev_skin_id == skin_id => diff * (1.0 - bias),
+            _ => diff,
+        }
+    }
 
-            // try placing pieces
-            for orientation in Orientation::all() {
-                for piece in Piece::all_normal(cell, orientation) {
-                    if board.board().can_place(&piece) {
-                        let diff = avg_piece_pixel_diff(&piece, board, skin, source_img, avg_pixel_grid)?;
-                        if diff < best_piece_diff {
-                            best_piece = Some(piece);
-                            best_piece_diff = diff;
-                            best_skin_id = Some(skin.id());
-                        }
-                    }
-                }
+    fn piece_allowed(&self, piece: &Piece, board: &SkinnedBoard) -> bool {
+        if self.config.banned_pieces.contains(&piece.get_char()) {
+            return false;
+        }
+        if self.mask.is_some() && piece.get_occupancy().is_ok_and(|cells| cells.iter().any(|cell| self.masked(cell))) {
+            return false;
+        }
+        match self.config.piece_ratio {
+            PieceRatio::Any => true,
+            PieceRatio::Standard => {
+                // garbage and banned tetrominos are outside of the bag
+                let counts = board.piece_counts();
+                let min_count = TETROMINO_CHARS
+                    .iter()
+                    .zip(&counts[2..])
+                    .filter(|(c, _)| !self.config.banned_pieces.contains(c))
+                    .map(|(_, count)| *count)
+                    .min()
+                    .unwrap_or(0);
+                let index = piece.block_index();
+                index < 2 || counts[index] <= min_count
             }
         }
+    }
 
-        if let Some(best_piece) = best_piece {
-            board.place(&best_piece, best_skin_id.expect("there must be a best skin"))?;
+    // whether the candidate's average color alone shows it can't beat `threshold`, so it doesn't need to be scored
+    // only the default cost has a known lower bound
+    fn pruned(&self, piece: &Piece, skin: &BlockSkin, board_width: usize, threshold: f64) -> Result<bool> {
+        if self.config.no_prune || self.config.cost.is_some() {
+            return Ok(false);
         }
+        let bound = cost::avg_pixel_lower_bound(piece, skin, &self.avg_pixel_grid, board_width)?;
+        Ok(self.bias_previous(piece, skin.id(), bound) > threshold)
     }
 
-    Ok(())
+    fn masked(&self, cell: &Cell) -> bool {
+        self.mask.as_ref().is_some_and(|mask| mask.masked(cell.x, cell.y))
+    }
+
+    fn skin_allowed(&self, cell: &Cell, skin_id: usize, board_width: usize) -> bool {
+        match &self.cell_skins {
+            Some(cell_skins) => cell_skins[cell.y * board_width + cell.x] == skin_id,
+            None => true,
+        }
+    }
 }
 
-fn average_pixel_grid(source_img: &DynamicImage, pixels_grid_width: u32, pixels_grid_height: u32) -> Result<Vec<Rgba<u8>>> {
-    // check pixels are evenly divided into the grid
-    let (pixels_w, pixels_h) = source_img.dimensions();
-    assert!(pixels_w % pixels_grid_width == 0, "Pixel width not evenly divided into the grid");
-    assert!(pixels_h % pixels_grid_height == 0, "Pixel height not evenly divided into the grid");
-
-    // now divide pixels into the grid and compute the average pixel for each
-    let pixels_per_grid = pixels_grid_width * pixels_grid_height;
-    let mut avg_pixels = Vec::new();
-
-    // for each grid in the image, calculate an average
-    for pixels_y_range in (0..pixels_h).step_by(pixels_grid_height as usize) {
-        for pixels_x_range in (0..pixels_w).step_by(pixels_grid_width as usize) {
-            let mut pixel_sum: [u32; 4]= [0, 0, 0, 0];
-
-            // calculate the sum using each pixel in the grid
-            for y in 0..pixels_grid_height {
-                for x in 0..pixels_grid_width {
-                    let pixel = source_img.get_pixel(pixels_x_range + x, pixels_y_range + y);
-                    pixel_sum[0] += u32::from(pixel[0]);
-                    pixel_sum[1] += u32::from(pixel[1]);
-                    pixel_sum[2] += u32::from(pixel[2]);
-                    pixel_sum[3] += u32::from(pixel[3]);
-                }
-            }
+fn previous_pieces(board: &SkinnedBoard) -> Vec<Option<(Piece, usize)>> {
+    let mut pieces = vec![None; board.board_width() * board.board_height()];
+    for piece in board.board().pieces() {
+        let cell = piece.get_cell();
+        pieces[cell.y * board.board_width() + cell.x] = Some((piece.clone(), board.get_cells_skin(&cell)));
+    }
+    pieces
+}
 
-            // divide by the number of pixels in the grid
-            let pixel_avg: Rgba<u8> = [
-                u8::try_from(pixel_sum[0] / pixels_per_grid)?,
-                u8::try_from(pixel_sum[1] / pixels_per_grid)?,
-                u8::try_from(pixel_sum[2] / pixels_per_grid)?,
-                u8::try_from(pixel_sum[3] / pixels_per_grid)?,
-            ].into();
+// makes sure every cell of the board covers at least one pixel of a `width` x `height` source
+// returns the size the source should be scaled to, and shrinks the board in `config` if `config.small_source` allows it
+pub fn fit_small_source(width: u32, height: u32, config: &mut Config) -> Result<(u32, u32)> {
+    let board_width = u32::try_from(config.board_width)?;
+    let board_height = u32::try_from(config.board_height)?;
+    if width >= board_width && height >= board_height {
+        return Ok((width, height));
+    }
 
-            avg_pixels.push(pixel_avg);
+    let too_small = SourceSizeError::TooSmall { width, height, board_width: config.board_width, board_height: config.board_height };
+    match config.small_source {
+        _ if width == 0 || height == 0 => Err(too_small)?,
+        SmallSource::Error => Err(too_small)?,
+        SmallSource::Upscale => {
+            let scale = board_width.div_ceil(width).max(board_height.div_ceil(height));
+            warn!(width, height, scale, "source is smaller than the board, upscaling it");
+            Ok((width * scale, height * scale))
+        }
+        SmallSource::ShrinkBoard => {
+            config.board_width = config.board_width.min(width as usize);
+            config.board_height = config.board_height.min(height as usize);
+            warn!(board_width = config.board_width, board_height = config.board_height, "source is smaller than the board, shrinking the board");
+            Ok((width, height))
         }
     }
+}
 
-    Ok(avg_pixels)
+// same as `fit_small_source`, but scales the image itself
+pub fn fit_small_image(source_img: &mut DynamicImage, config: &mut Config) -> Result<()> {
+    let (width, height) = fit_small_source(source_img.width(), source_img.height(), config)?;
+    if (width, height) != source_img.dimensions() {
+        *source_img = source_img.resize_exact(width, height, image::imageops::FilterType::Nearest);
+    }
+    Ok(())
 }
 
-fn avg_piece_pixel_diff(piece: &Piece, board: &SkinnedBoard, skin: &BlockSkin, source_img: &DynamicImage, avg_pixel_grid: &[Rgba<u8>]) -> Result<f64> {
-    // used to weigh the importance of each diff
-    const RED_WEIGHT: f64 = 1.0;
-    const GREEN_WEIGHT: f64 = 1.7;
-    const BLUE_WEIGHT: f64 = 0.8;
+// sizes the skins for the board according to `config.fit`
+pub fn fit_skins(skins: &mut draw::Skins, image_width: u32, image_height: u32, config: &Config) -> Result<()> {
+    match config.fit {
+        FitMode::Stretch => resize_skins(skins, image_width, image_height, config.board_width, config.board_height),
+        FitMode::Letterbox => letterbox_skins(skins, image_width, image_height, config.board_width, config.board_height),
+    }
+}
 
-    let mut curr_pixel_diff: f64 = 0.0;
-    let mut total_curr_pixels: u32 = 0;
+// the skin size `fit_skins` would resize to, without resizing anything
+pub fn fit_skin_size(skins: &draw::Skins, image_width: u32, image_height: u32, config: &Config) -> Result<(u32, u32)> {
+    match config.fit {
+        FitMode::Stretch => draw::stretch_skin_size(image_width, image_height, config.board_width, config.board_height),
+        FitMode::Letterbox => draw::letterbox_skin_size(skins, image_width, image_height, config.board_width, config.board_height),
+    }
+}
 
-    let mut context_pixel_diff: f64 = 0.0;
-    let mut total_context_pixels: u32 = 0;
+// fits the source image to the board according to `config.fit`
+pub fn fit_image(source_img: &mut DynamicImage, skin_width: u32, skin_height: u32, config: &Config) {
+    match config.fit {
+        FitMode::Stretch => resize_image(source_img, skin_width, skin_height, config.board_width, config.board_height),
+        FitMode::Letterbox => letterbox_image(source_img, skin_width, skin_height, config.board_width, config.board_height),
+    }
+}
 
-    let block_image = skin.block_image_from_piece(piece);
+// scales the source to fit inside the board without changing its aspect ratio, centering it between black bars
+pub fn letterbox_image(source_img: &mut DynamicImage, skin_width: u32, skin_height: u32, board_width: usize, board_height: usize) {
+    let board_pixel_width = skin_width * u32::try_from(board_width).unwrap();
+    let board_pixel_height = skin_height * u32::try_from(board_height).unwrap();
+    if (board_pixel_width, board_pixel_height) == source_img.dimensions() {
+        return;
+    }
 
-    let center_cell = piece.get_cell();
-    let occupancy = piece.get_occupancy()?;
-    let context_cells = find_context_cells(board, &occupancy, &center_cell)?;
+    let scaled = source_img.resize(board_pixel_width, board_pixel_height, image::imageops::FilterType::Lanczos3);
+    let mut canvas = image::RgbaImage::from_pixel(board_pixel_width, board_pixel_height, Rgba([0, 0, 0, 255]));
+    let x = (board_pixel_width - scaled.width()) / 2;
+    let y = (board_pixel_height - scaled.height()) / 2;
+    image::imageops::overlay(&mut canvas, &scaled, x.into(), y.into());
+    *source_img = DynamicImage::from(canvas);
+}
 
-    let avg_board_cell_pixel = block_image.get_average_pixel();
-    let avg_source_cell_pixel = find_average_source_cell_pixel(avg_pixel_grid, &occupancy, board);
-    for cell in occupancy {
-        // first analyze the context using average pixels
-        for context_cell in &context_cells {
-            let cell_char = board.board().get(&cell)?;
-            let skin_id = board.get_cells_skin(context_cell);
+pub fn resize_image(source_img: &mut DynamicImage, skin_width: u32, skin_height: u32, board_width: usize, board_height: usize) {
+    // resize the source image if needed
+    let resized_width = skin_width * u32::try_from(board_width).unwrap();
+    let resized_height = skin_height * u32::try_from(board_height).unwrap();
+    if resized_width != source_img.width() || resized_height != source_img.height() {
+        let resized_source_buffer = image::imageops::resize(source_img, resized_width, resized_height, image::imageops::FilterType::Lanczos3);
+        *source_img = image::DynamicImage::from(resized_source_buffer);
+    };
+}
 
-            let context_skin = board.get_skin(skin_id);
-            let context_block_image = context_skin.block_image_from_char(cell_char);
-            let avg_board_context_pixel = context_block_image.get_average_pixel();
+fn process_heap(heap: &mut CellHeap, board: &mut SkinnedBoard, state: &mut ApproxState, use_garbage: &UseGarbage) -> Result<()> {
+    // for each cell at the top of the heap:
+    while let Some(cell) = heap.pop() {
+        if state.config.cancel.is_cancelled() {
+            break;
+        }
 
-            let avg_source_context_pixel = avg_pixel_grid[context_cell.y * board.board_width() + context_cell.x];
+        // 1. check if the cell is unoccupied and not masked out
+        if !board.empty_at(&cell) || state.masked(&cell) {
+            continue;
+        }
 
-            let board_context_diff = subtract_pixels(avg_board_cell_pixel, avg_board_context_pixel);
-            let source_context_diff = subtract_pixels(avg_source_cell_pixel, avg_source_context_pixel);
+        // 2. for each possible skin, piece, and orientation:
+        let mut candidates = Candidates::new(state.config.tie_epsilon);
+        let placements = cell_placements(cell, board, state, use_garbage);
 
-            context_pixel_diff += f64::sqrt(
-                f64::from(board_context_diff[0] - source_context_diff[0]).powf(2.0) * RED_WEIGHT +
-                f64::from(board_context_diff[1] - source_context_diff[1]).powf(2.0) * GREEN_WEIGHT +
-                f64::from(board_context_diff[2] - source_context_diff[2]).powf(2.0) * BLUE_WEIGHT
-            );
-            total_context_pixels += 1;
+        if score_in_parallel(board) {
+            for (piece, skin_id, diff) in score_parallel(placements, board, state)? {
+                candidates.push(piece, skin_id, diff);
+            }
+        } else {
+            for (piece, skin_id) in placements {
+                let skin = board.get_skin(skin_id);
+                if state.pruned(&piece, skin, board.board_width(), candidates.threshold())? {
+                    continue;
+                }
+                let diff = placement_cost(&piece, board, skin, state)?;
+                let diff = state.bias_previous(&piece, skin_id, diff);
+                candidates.push(piece, skin_id, diff);
+            }
         }
 
-        // then analyze the individual cell to find the pixel difference between the current cells
-        for y in 0..skin.height() {
-            for x in 0..skin.width() {
-                let pixel_x = u32::try_from(cell.x)? * skin.width() + x;
-                let pixel_y = u32::try_from(cell.y)? * skin.height() + y;
-                let source_pixel = source_img.get_pixel(pixel_x, pixel_y);
-                let approx_pixel = block_image.get_pixel(x, y);
-                let curr_diff = subtract_pixels(source_pixel, approx_pixel);
-                curr_pixel_diff += 
-                    f64::from(curr_diff[0].pow(2)) * RED_WEIGHT +
-                    f64::from(curr_diff[1].pow(2)) * GREEN_WEIGHT +
-                    f64::from(curr_diff[2].pow(2)) * BLUE_WEIGHT
-                ;
-                total_curr_pixels += 1;
+        // 3. place the best piece, breaking near-ties if configured
+        if let Some((best_piece, best_skin_id)) = candidates.choose(&mut state.rng) {
+            if state.confidence.is_some() {
+                let diff = placement_cost(&best_piece, board, board.get_skin(best_skin_id), state)?;
+                let occupancy = best_piece.get_occupancy()?;
+                if let Some(confidence) = &mut state.confidence {
+                    confidence.record(&occupancy, diff);
+                }
             }
+            board.place(&best_piece, best_skin_id)?;
+            state.record_filled(board, &best_piece.get_occupancy()?)?;
         }
     }
 
-    // weight the context diff in comparison with the current diff
-    let avg_pixel_diff = 
-        if total_context_pixels != 0 {
-            curr_pixel_diff / f64::from(total_curr_pixels) + context_pixel_diff / f64::from(total_context_pixels)
-        } else {
-            curr_pixel_diff / f64::from(total_curr_pixels)
-        };
-
-
-    Ok(avg_pixel_diff)
+    Ok(())
 }
 
-fn find_context_cells(board: &SkinnedBoard, occupancy: &[Cell], center_cell: &Cell) -> Result<Vec<Cell>> {
-    const MIN_DX: i32 = 0;
-    const MIN_DY: i32 = 0;
-    const MAX_DX: i32 = 8;
-    const MAX_DY: i32 = 8;
-
-    // get the context cells
-    let mut context_cells: Vec<Cell> = Vec::new();
-    let mut dy: i32 = MIN_DY;
-    while dy < MAX_DY {
-        // compute and check the new y coordinate
-        let new_y = usize::try_from(i32::try_from(center_cell.y)? + dy);
-        let Ok(new_y) = new_y else {
-            dy += 1;
-            continue
-        };
+// every piece and skin that may go at `cell`, in the order they're scored: by skin, then garbage, then pieces
+fn cell_placements(cell: Cell, board: &SkinnedBoard, state: &ApproxState, use_garbage: &UseGarbage) -> Vec<(Piece, usize)> {
+    let mut placements = Vec::new();
+    for skin in board.iter_skins() {
+        if !state.skin_allowed(&cell, skin.id(), board.board_width()) {
+            continue;
+        }
 
-        let mut dx: i32 = MIN_DX;
-        while dx < MAX_DX {
-            // compute and check the new x coordinate
-            let new_x = usize::try_from(i32::try_from(center_cell.x)? + dx);
-            let Ok(new_x) = new_x else {
-                dx += 1;
-                continue
-            };
+        // try black or gray garbage
+        if let UseGarbage::Yes = use_garbage {
+            placements.extend(Piece::all_garbage(cell, state.config.garbage_levels).into_iter().map(|piece| (piece, skin.id())));
+        }
 
-            // only append contexts that are occupied with other pieces we already placed
-            let context_cell = Cell {x: new_x, y: new_y};
-            let context_char = board.board().get(&context_cell);
-            if context_char.is_ok() && context_char.expect("there must be a context char") != EMPTY_CELL && !occupancy.contains(&context_cell) {
-                context_cells.push(context_cell);
+        // try placing pieces
+        for orientation in Orientation::all() {
+            for piece in Piece::all_normal(cell, orientation) {
+                if board.board().can_place(&piece) && state.piece_allowed(&piece, board) {
+                    placements.push((piece, skin.id()));
+                }
             }
-            dx += 1;
         }
-
-        dy += 1;
     }
+    placements
+}
+
+// scores the placements across threads, returning the ones that weren't pruned in their original order
+// the best diff is shared between threads for pruning; any diff found so far is at least the final best,
+// so a pruned candidate could never have won and the same piece is picked however the work is scheduled
+fn score_parallel(placements: Vec<(Piece, usize)>, board: &SkinnedBoard, state: &ApproxState) -> Result<Vec<(Piece, usize, f64)>> {
+    let best_diff = AtomicU64::new(f64::MAX.to_bits());
+    let scored = placements
+        .into_par_iter()
+        .map(|(piece, skin_id)| {
+            let skin = board.get_skin(skin_id);
+            let threshold = near_tie_threshold(f64::from_bits(best_diff.load(Ordering::Relaxed)), state.config.tie_epsilon);
+            if state.pruned(&piece, skin, board.board_width(), threshold)? {
+                return Ok(None);
+            }
+            let diff = state.bias_previous(&piece, skin_id, placement_cost(&piece, board, skin, state)?);
+            // an Err only means another thread already found something better
+            let _ = best_diff.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |best| (diff < f64::from_bits(best)).then_some(diff.to_bits()));
+            Ok(Some((piece, skin_id, diff)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(scored.into_iter().flatten().collect())
+}
 
-    Ok(context_cells)
+// scoring a cell's candidates in parallel pays off once each one covers enough pixels
+// boards approximated inside a parallel job, like video frames, already keep every thread busy
+fn score_in_parallel(board: &SkinnedBoard) -> bool {
+    let work = board.skins_width() * board.skins_height() * u32::try_from(board.iter_skins().count()).unwrap_or(u32::MAX);
+    work >= PARALLEL_SCORING_PIXELS && rayon::current_num_threads() > 1 && rayon::current_thread_index().is_none()
 }
 
-fn find_average_source_cell_pixel(avg_pixel_grid: &[Rgba<u8>], occupancy: &Vec<Cell>, board: &SkinnedBoard) -> Rgba<u8> {
-    let mut pixel_sum: [u32; 4] = [0, 0, 0, 0];
+fn average_pixel_grid(source_img: &DynamicImage, pixels_grid_width: u32, pixels_grid_height: u32) -> Result<Vec<Rgba<u8>>> {
+    avg_grid::average_image_grid(source_img, pixels_grid_width, pixels_grid_height)
+}
 
-    for cell in occupancy {
-        let pixel = &avg_pixel_grid[cell.y * board.board_width() + cell.x];
-        pixel_sum[0] += u32::from(pixel[0]);
-        pixel_sum[1] += u32::from(pixel[1]);
-        pixel_sum[2] += u32::from(pixel[2]);
-        pixel_sum[3] += u32::from(pixel[3]);
+// scores a placement with the configured cost, or the default one
+fn placement_cost(piece: &Piece, board: &SkinnedBoard, skin: &BlockSkin, state: &ApproxState) -> Result<f64> {
+    let mut ctx = CostContext { avg_pixel_grid: &state.avg_pixel_grid, detail: state.detail.as_ref(), cache: &state.cache };
+    match &state.config.cost {
+        Some(cost) => cost.cost(piece, board, skin, state.source_img, &mut ctx),
+        None => AvgPixelDiff.cost(piece, board, skin, state.source_img, &mut ctx),
     }
+}
 
-    pixel_sum.map(|x| u8::try_from(x / u32::try_from(occupancy.len()).expect("there must be at least one")).expect("pixel should be in range")).into()
+// `detail` optionally weighs each pixel's diff by how much detail the source has there
+fn cell_pixel_diff(cell: &Cell, skin: &BlockSkin, block_image: &BlockImage, source_img: &DynamicImage, detail: Option<&DetailMap>) -> Result<f64> {
+    let mut pixel_diff: f64 = 0.0;
+    for y in 0..skin.height() {
+        for x in 0..skin.width() {
+            let pixel_x = u32::try_from(cell.x)? * skin.width() + x;
+            let pixel_y = u32::try_from(cell.y)? * skin.height() + y;
+            let source_pixel = source_img.get_pixel(pixel_x, pixel_y);
+            let approx_pixel = block_image.get_pixel(x, y);
+            let curr_diff = subtract_pixels(source_pixel, approx_pixel);
+            let diff =
+                f64::from(curr_diff[0].pow(2)) * RED_WEIGHT +
+                f64::from(curr_diff[1].pow(2)) * GREEN_WEIGHT +
+                f64::from(curr_diff[2].pow(2)) * BLUE_WEIGHT
+            ;
+            pixel_diff += match detail {
+                Some(detail) => diff * detail.weight(pixel_x, pixel_y),
+                None => diff,
+            };
+        }
+    }
+    Ok(pixel_diff)
 }
 
 fn subtract_pixels(a: Rgba<u8>, b: Rgba<u8>) -> [i32; 3] {
@@ -339,6 +713,8 @@ mod tests {
     use crate::approx_image::piece;
     use rayon::iter::{IntoParallelIterator, ParallelIterator};
     use super::*;
+    use crate::cli::test_glob;
+    use crate::approx_image::draw::test_skin;
 
     #[test]
     #[ignore]
@@ -351,7 +727,7 @@ mod tests {
             fs::create_dir(test_dir).expect("failed to create test directory");
         }
 
-        let skins = draw::create_skins();
+        let skins = draw::create_skins().unwrap();
         let all_piece_types: Vec<_> = piece::Orientation::all()
             .into_iter()
             .flat_map(|o| piece::Piece::all_normal(piece::Cell { x: 4, y: 4 }, o))
@@ -387,12 +763,246 @@ mod tests {
 
         let board_width = 19;
         let board_height = 17;
-        let mut glob = GlobalData::new();
+        let glob = GlobalData::new().unwrap();
         let config = Config {
             board_width,
             board_height,
             prioritize_tetrominos: PrioritizeColor::Yes,
+            ..Config::default()
+        };
+        run(&source, &output, &config, &glob).unwrap();
+    }
+
+    #[test]
+    fn test_cancelled_returns_partial_board() {
+        let glob = test_glob(4);
+
+        let config = Config {
+            board_width: 5,
+            board_height: 3,
+            ..Config::default()
+        };
+        config.cancel.cancel();
+
+        let source_img = DynamicImage::new_rgba8(20, 12);
+        let approx_img = approx(&source_img, &config, &glob).expect("cancelled approximation should still draw");
+        assert_eq!(approx_img.dimensions(), (20, 12));
+        assert!(approx_img.to_rgba8().pixels().all(|p| p[3] == 0));
+    }
+
+    #[test]
+    fn test_approx_png_from_bytes() {
+        let skin_bytes = fs::read("test_images/HqGYC5G - Imgur.png").unwrap();
+        let skins = vec![draw::BlockSkin::from_bytes(&skin_bytes, "skin", 0).unwrap()];
+
+        let mut source_png = Vec::new();
+        DynamicImage::new_rgb8(40, 24).write_to(&mut std::io::Cursor::new(&mut source_png), image::ImageFormat::Png).unwrap();
+
+        let config = Config {
+            board_width: 5,
+            board_height: 3,
+            ..Config::default()
         };
-        run(&source, &output, &config, &mut glob);
+        let png = approx_png(&source_png, skins, &config).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().dimensions(), (40, 24));
+    }
+
+    #[test]
+    fn test_approx_png_without_skins() {
+        let mut source_png = Vec::new();
+        DynamicImage::new_rgb8(40, 24).write_to(&mut std::io::Cursor::new(&mut source_png), image::ImageFormat::Png).unwrap();
+
+        let err = approx_png(&source_png, Vec::new(), &Config::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(draw::SkinLoadError::Empty)));
+    }
+
+    #[test]
+    fn test_temporal_bias_keeps_previous_pieces() {
+        let glob = test_glob(4);
+        let config = Config {
+            board_width: 6,
+            board_height: 4,
+            temporal_bias: Some(0.99),
+            ..Config::default()
+        };
+
+        let first = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(24, 16, |x, _| Rgba([u8::try_from(x * 10).unwrap(), 0, 0, 255])));
+        let second = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(24, 16, |_, y| Rgba([0, 0, u8::try_from(y * 15).unwrap(), 255])));
+        let previous = approx_board(&first, &config, &glob).unwrap();
+
+        // with a strong bias, the second frame should repeat the first frame's pieces
+        let seeded = approx_board_seeded(&second, &config, &glob, Some(&previous)).unwrap();
+        assert_eq!(seeded.board().pieces(), previous.board().pieces());
+
+        // without a previous frame, the second frame is approximated on its own
+        let unseeded = approx_board(&second, &config, &glob).unwrap();
+        assert_ne!(unseeded.board().pieces(), previous.board().pieces());
+    }
+
+    #[test]
+    fn test_piece_constraints() {
+        let glob = test_glob(4);
+        let source_img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(40, 32, |x, y| Rgba([u8::try_from(x * 6).unwrap(), 100, u8::try_from(y * 8).unwrap(), 255])));
+
+        let config = Config {
+            board_width: 10,
+            board_height: 8,
+            banned_pieces: vec!['S', 'Z'],
+            ..Config::default()
+        };
+        let counts = approx_board(&source_img, &config, &glob).unwrap().piece_counts();
+        assert_eq!((counts[7], counts[8]), (0, 0));
+
+        let config = Config {
+            board_width: 10,
+            board_height: 8,
+            piece_ratio: PieceRatio::Standard,
+            ..Config::default()
+        };
+        let counts = approx_board(&source_img, &config, &glob).unwrap().piece_counts();
+        let tetrominos = &counts[2..];
+        assert!(tetrominos.iter().max().unwrap() - tetrominos.iter().min().unwrap() <= 1);
+    }
+
+    #[test]
+    fn test_letterbox_keeps_aspect_ratio() {
+        let skin = test_skin();
+        let (skin_width, skin_height) = (skin.width(), skin.height());
+        let config = Config {
+            board_width: 4,
+            board_height: 2,
+            fit: FitMode::Letterbox,
+            ..Config::default()
+        };
+
+        // a wide source is limited by its height, so the cells stay the skin's shape
+        let mut skins = vec![skin];
+        fit_skins(&mut skins, 400, 100, &config).unwrap();
+        let cell_height = skins[0].height();
+        assert_eq!(cell_height, 50);
+        assert_eq!(skins[0].width() * skin_height, cell_height * skin_width);
+
+        // the source is wider than the board, so it is centered between black bars above and below
+        let mut source_img = DynamicImage::from(image::RgbaImage::from_pixel(400, 100, Rgba([255, 255, 255, 255])));
+        fit_image(&mut source_img, skins[0].width(), skins[0].height(), &config);
+        let (width, height) = source_img.dimensions();
+        assert_eq!((width, height), (skins[0].width() * 4, cell_height * 2));
+        assert_eq!(source_img.get_pixel(width / 2, 0), Rgba([0, 0, 0, 255]));
+        assert_eq!(source_img.get_pixel(width / 2, height / 2), Rgba([255, 255, 255, 255]));
+    }
+
+    #[test]
+    fn test_small_sources() {
+        let source_img = DynamicImage::from(image::RgbaImage::from_pixel(3, 2, Rgba([200, 40, 40, 255])));
+        let board = Config { board_width: 8, board_height: 4, ..Config::default() };
+
+        // sub-cell sources are refused by default, and fixed up by either option
+        let mut config = board.clone();
+        assert!(fit_small_image(&mut source_img.clone(), &mut config).is_err());
+
+        let mut upscaled = source_img.clone();
+        let mut config = Config { small_source: SmallSource::Upscale, ..board.clone() };
+        fit_small_image(&mut upscaled, &mut config).unwrap();
+        assert_eq!(upscaled.dimensions(), (9, 6));
+        assert_eq!((config.board_width, config.board_height), (8, 4));
+
+        let mut config = Config { small_source: SmallSource::ShrinkBoard, ..board };
+        fit_small_image(&mut source_img.clone(), &mut config).unwrap();
+        assert_eq!((config.board_width, config.board_height), (3, 2));
+
+        // one pixel per cell is the smallest source that works as is
+        let skin = test_skin();
+        let mut bytes = Vec::new();
+        upscaled.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png).unwrap();
+        let config = Config { board_width: 9, board_height: 6, ..Config::default() };
+        let png = approx_png(&bytes, vec![skin], &config).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().dimensions(), (9, 6));
+    }
+
+    #[derive(Debug)]
+    struct OnlyI;
+
+    impl PlacementCost for OnlyI {
+        fn cost(&self, piece: &Piece, _: &SkinnedBoard, _: &BlockSkin, _: &DynamicImage, _: &mut CostContext) -> Result<f64> {
+            Ok(if piece.get_char() == 'I' { 0.0 } else { 1.0 })
+        }
+    }
+
+    #[test]
+    fn test_custom_placement_cost() {
+        let glob = test_glob(2);
+        let source_img = DynamicImage::new_rgba8(16, 8);
+        let config = Config {
+            board_width: 8,
+            board_height: 4,
+            cost: Some(std::sync::Arc::new(OnlyI)),
+            ..Config::default()
+        };
+
+        let counts = approx_board(&source_img, &config, &glob).unwrap().piece_counts();
+        assert_eq!(counts.iter().sum::<usize>(), counts[2]);
+    }
+
+    #[test]
+    fn test_pruning_is_exact() {
+        let glob = test_glob(4);
+        let mut source_img = image::open("examples/sunset_1280.jpg").expect("could not load source image");
+        resize_image(&mut source_img, 4, 4, 16, 10);
+
+        let approx_with = |no_prune| {
+            let config = Config { board_width: 16, board_height: 10, no_prune, ..Config::default() };
+            approx(&source_img, &config, &glob).unwrap().into_bytes()
+        };
+        assert!(approx_with(false) == approx_with(true));
+    }
+
+    #[test]
+    fn test_coarse_to_fine() {
+        let mut first = test_skin();
+        let mut second = draw::BlockSkin::new(Path::new("test_images/synthetic_skin.png"), 1).expect("could not load skin");
+        first.resize(4, 4);
+        second.resize(4, 4);
+        let glob = GlobalData::from_skins(vec![first, second]);
+        let mut source_img = image::open("examples/sunset_1280.jpg").expect("could not load source image");
+        resize_image(&mut source_img, 4, 4, 8, 6);
+
+        let config = Config { board_width: 8, board_height: 6, coarse_to_fine: true, ..Config::default() };
+        let board = approx_board(&source_img, &config, &glob).unwrap();
+        let coarse_skins = coarse_skins(&source_img, &config, &glob).unwrap().unwrap();
+
+        // each 2x2 area shares a skin, and every piece uses the skin given to the cell it was placed from
+        for y in 0..6 {
+            for x in 0..8 {
+                assert_eq!(coarse_skins[y * 8 + x], coarse_skins[(y / 2 * 2) * 8 + x / 2 * 2]);
+            }
+        }
+        for piece in board.board().pieces() {
+            let cell = piece.get_cell();
+            assert_eq!(board.get_cells_skin(&cell), coarse_skins[cell.y * 8 + cell.x]);
+        }
+        assert!(coarse_skins.contains(&0) && coarse_skins.contains(&1));
+    }
+
+    #[test]
+    fn test_no_garbage() {
+        let glob = test_glob(2);
+        // a 5x3 board can't be tiled by tetrominoes, so some cells are always left over
+        let source_img = DynamicImage::new_rgba8(10, 6);
+
+        for (no_garbage, expect_full) in [(NoGarbage::Empty, false), (NoGarbage::Overlap, true)] {
+            let config = Config {
+                board_width: 5,
+                board_height: 3,
+                no_garbage: Some(no_garbage),
+                ..Config::default()
+            };
+            let board = approx_board(&source_img, &config, &glob).unwrap();
+            let cells: Vec<char> = (0..3)
+                .flat_map(|y| (0..5).map(move |x| Cell { x, y }))
+                .map(|cell| board.board().get(&cell).unwrap())
+                .collect();
+            assert!(!cells.iter().any(|c| ['G', 'B'].contains(c)));
+            assert_eq!(!cells.contains(&board::EMPTY_CELL), expect_full);
+        }
     }
 }
\ No newline at end of file
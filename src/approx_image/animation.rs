@@ -0,0 +1,79 @@
+use super::draw::{self, SkinnedBoard};
+
+use std::fs::{self, File};
+use std::path::Path;
+
+use anyhow::Result;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+
+// rough number of frames to aim for when the pieces per frame isn't given
+const DEFAULT_NUM_FRAMES: usize = 100;
+const FRAME_DELAY_MS: u32 = 50;
+
+// renders the board being filled in placement order, `pieces_per_frame` pieces at a time
+// the last frame is the finished board, including any cells filled without placing a piece
+pub fn build_frames(board: &SkinnedBoard, pieces_per_frame: Option<usize>) -> Result<Vec<RgbaImage>> {
+    let pieces = board.board().pieces();
+    let pieces_per_frame = pieces_per_frame.unwrap_or(pieces.len().div_ceil(DEFAULT_NUM_FRAMES)).max(1);
+
+    let width = u32::try_from(board.board_width())? * board.skins_width();
+    let height = u32::try_from(board.board_height())? * board.skins_height();
+    let mut img = RgbaImage::new(width, height);
+    let mut frames = Vec::new();
+    for chunk in pieces.chunks(pieces_per_frame) {
+        for piece in chunk {
+            draw::draw_piece(&mut img, board, piece)?;
+        }
+        frames.push(img.clone());
+    }
+    frames.push(draw::draw(board)?.into_rgba8());
+    Ok(frames)
+}
+
+// saves the build animation as a gif if `path` ends in `.gif`, otherwise as numbered pngs in the `path` directory
+pub fn save(board: &SkinnedBoard, path: &Path, pieces_per_frame: Option<usize>) -> Result<()> {
+    let frames = build_frames(board, pieces_per_frame)?;
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gif")) {
+        // a faster quantizer speed keeps encoding hundreds of large frames practical
+        let mut encoder = GifEncoder::new_with_speed(File::create(path)?, 10);
+        encoder.set_repeat(Repeat::Infinite)?;
+        let delay = Delay::from_numer_denom_ms(FRAME_DELAY_MS, 1);
+        encoder.encode_frames(frames.into_iter().map(|frame| Frame::from_parts(frame, 0, 0, delay)))?;
+    } else {
+        fs::create_dir_all(path)?;
+        for (index, frame) in frames.iter().enumerate() {
+            frame.save(path.join(format!("{index}.png")))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::test_skin;
+    use crate::approx_image::piece::{Cell, Orientation, Piece};
+
+    #[test]
+    fn test_frames_build_up_in_order() {
+        let mut skin = test_skin();
+        skin.resize(4, 4);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(6, 4, &skins);
+        board.place(&Piece::I(Cell { x: 0, y: 0 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::O(Cell { x: 1, y: 2 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::Black(Cell { x: 5, y: 3 }), 0).unwrap();
+
+        let frames = build_frames(&board, Some(2)).unwrap();
+        assert_eq!(frames.len(), 3);
+
+        // the first frame only has the first two pieces
+        let transparent = |img: &RgbaImage, x: u32, y: u32| img.get_pixel(x * 4, y * 4)[3] == 0;
+        assert!(!transparent(&frames[0], 0, 0));
+        assert!(transparent(&frames[0], 5, 3));
+        assert!(!transparent(&frames[1], 5, 3));
+        assert_eq!(frames[2], draw::draw(&board).unwrap().into_rgba8());
+    }
+}
@@ -0,0 +1,252 @@
+use std::io::{BufRead, Seek};
+
+use anyhow::Result;
+use image::{DynamicImage, Rgba};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum GridError {
+    #[error("Row has {actual} bytes, expected {expected}")]
+    InvalidRow{ expected: usize, actual: usize },
+
+    #[error("Image dimensions {width}x{height} are not evenly divided into {cell_width}x{cell_height} cells")]
+    UnevenGrid{ width: u32, height: u32, cell_width: u32, cell_height: u32 },
+
+    #[error("Png rows decode as {0:?}, which can't be averaged")]
+    UnsupportedPng(png::ColorType),
+}
+
+// the 8-bit pixel layouts rows can be pushed in, which cover what decoders usually hand back
+// colors without alpha average to an opaque alpha, matching what converting them to rgba8 would give
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RowLayout {
+    Luma8,
+    LumaA8,
+    Rgb8,
+    Rgba8,
+}
+
+impl RowLayout {
+    pub fn channels(self) -> usize {
+        match self {
+            RowLayout::Luma8 => 1,
+            RowLayout::LumaA8 => 2,
+            RowLayout::Rgb8 => 3,
+            RowLayout::Rgba8 => 4,
+        }
+    }
+
+    // the layout and raw bytes of an image that can be averaged in place
+    pub fn of(img: &DynamicImage) -> Option<(RowLayout, &[u8])> {
+        match img {
+            DynamicImage::ImageLuma8(img) => Some((RowLayout::Luma8, img.as_raw())),
+            DynamicImage::ImageLumaA8(img) => Some((RowLayout::LumaA8, img.as_raw())),
+            DynamicImage::ImageRgb8(img) => Some((RowLayout::Rgb8, img.as_raw())),
+            DynamicImage::ImageRgba8(img) => Some((RowLayout::Rgba8, img.as_raw())),
+            _ => None,
+        }
+    }
+}
+
+// averages 8-bit rows into grid cells as they arrive, so only one band of cell sums is kept in memory
+// rows can come from an in-memory image or straight from a decoder for sources too large to hold at once
+pub struct GridAverager {
+    layout: RowLayout,
+    cell_width: usize,
+    cell_height: usize,
+    grid_width: usize,
+    // per-channel sums for the band of cells currently being filled
+    band_sums: Vec<[u32; 4]>,
+    rows_in_band: usize,
+    averages: Vec<Rgba<u8>>,
+}
+
+impl GridAverager {
+    pub fn new(image_width: u32, image_height: u32, cell_width: u32, cell_height: u32, layout: RowLayout) -> Result<GridAverager> {
+        if cell_width == 0 || cell_height == 0 || !image_width.is_multiple_of(cell_width) || !image_height.is_multiple_of(cell_height) {
+            Err(GridError::UnevenGrid { width: image_width, height: image_height, cell_width, cell_height })?;
+        }
+
+        let grid_width = usize::try_from(image_width / cell_width)?;
+        let grid_height = usize::try_from(image_height / cell_height)?;
+        Ok(GridAverager {
+            layout,
+            cell_width: usize::try_from(cell_width)?,
+            cell_height: usize::try_from(cell_height)?,
+            grid_width,
+            band_sums: vec![[0; 4]; grid_width],
+            rows_in_band: 0,
+            averages: Vec::with_capacity(grid_width * grid_height),
+        })
+    }
+
+    // adds a single row of pixels in the averager's layout, top to bottom
+    pub fn push_row(&mut self, row: &[u8]) -> Result<()> {
+        let channels = self.layout.channels();
+        let expected = self.grid_width * self.cell_width * channels;
+        if row.len() != expected {
+            Err(GridError::InvalidRow { expected, actual: row.len() })?;
+        }
+
+        // the layout is matched once per row, keeping the per-pixel loops branch free
+        let cells = self.band_sums.iter_mut().zip(row.chunks_exact(self.cell_width * channels));
+        match self.layout {
+            RowLayout::Luma8 => cells.for_each(|(sum, pixels)| {
+                for &luma in pixels {
+                    add_pixel(sum, [luma, luma, luma, u8::MAX]);
+                }
+            }),
+            RowLayout::LumaA8 => cells.for_each(|(sum, pixels)| {
+                for pixel in pixels.chunks_exact(2) {
+                    add_pixel(sum, [pixel[0], pixel[0], pixel[0], pixel[1]]);
+                }
+            }),
+            RowLayout::Rgb8 => cells.for_each(|(sum, pixels)| {
+                for pixel in pixels.chunks_exact(3) {
+                    add_pixel(sum, [pixel[0], pixel[1], pixel[2], u8::MAX]);
+                }
+            }),
+            RowLayout::Rgba8 => cells.for_each(|(sum, pixels)| {
+                for pixel in pixels.chunks_exact(4) {
+                    add_pixel(sum, [pixel[0], pixel[1], pixel[2], pixel[3]]);
+                }
+            }),
+        }
+
+        // once a band of cells is complete, store its averages and start the next band
+        self.rows_in_band += 1;
+        if self.rows_in_band == self.cell_height {
+            let pixels_per_cell = u32::try_from(self.cell_width * self.cell_height)?;
+            for sum in &mut self.band_sums {
+                let avg = sum.map(|channel| u8::try_from(channel / pixels_per_cell).expect("average should be in range"));
+                self.averages.push(avg.into());
+                *sum = [0; 4];
+            }
+            self.rows_in_band = 0;
+        }
+        Ok(())
+    }
+
+    // returns the average of each cell in row-major order
+    pub fn finish(self) -> Vec<Rgba<u8>> {
+        self.averages
+    }
+}
+
+fn add_pixel(sum: &mut [u32; 4], pixel: [u8; 4]) {
+    for (channel_sum, channel) in sum.iter_mut().zip(pixel) {
+        *channel_sum += u32::from(channel);
+    }
+}
+
+// averages an image already in memory, reading 8-bit images in place and only converting other formats
+pub fn average_image_grid(img: &DynamicImage, cell_width: u32, cell_height: u32) -> Result<Vec<Rgba<u8>>> {
+    let converted;
+    let (layout, raw) = match RowLayout::of(img) {
+        Some(found) => found,
+        None => {
+            converted = img.to_rgba8();
+            (RowLayout::Rgba8, converted.as_raw().as_slice())
+        }
+    };
+
+    let mut averager = GridAverager::new(img.width(), img.height(), cell_width, cell_height, layout)?;
+    for row in raw.chunks_exact(usize::try_from(img.width())? * layout.channels()) {
+        averager.push_row(row)?;
+    }
+    Ok(averager.finish())
+}
+
+// averages a png as it's decoded, so only a single row of the image is ever in memory
+// interlaced pngs don't arrive in order and are decoded whole instead
+// jpegs have no row-by-row decoder among our dependencies, so they go through `average_image_grid`
+pub fn average_png_grid(reader: impl BufRead + Seek, cell_width: u32, cell_height: u32) -> Result<Vec<Rgba<u8>>> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let layout = match reader.output_color_type().0 {
+        png::ColorType::Grayscale => RowLayout::Luma8,
+        png::ColorType::GrayscaleAlpha => RowLayout::LumaA8,
+        png::ColorType::Rgb => RowLayout::Rgb8,
+        png::ColorType::Rgba => RowLayout::Rgba8,
+        color_type @ png::ColorType::Indexed => Err(GridError::UnsupportedPng(color_type))?,
+    };
+
+    let (width, height) = (reader.info().width, reader.info().height);
+    let mut averager = GridAverager::new(width, height, cell_width, cell_height, layout)?;
+    if reader.info().interlaced {
+        let mut frame = vec![0; reader.output_buffer_size().ok_or(png::DecodingError::LimitsExceeded)?];
+        let info = reader.next_frame(&mut frame)?;
+        for row in frame[..info.buffer_size()].chunks_exact(info.line_size) {
+            averager.push_row(row)?;
+        }
+    } else {
+        while let Some(row) = reader.next_row()? {
+            averager.push_row(row.data())?;
+        }
+    }
+    Ok(averager.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImageView, RgbaImage};
+
+    // straightforward per-pixel average used to check the row-based version
+    fn naive_average(img: &DynamicImage, cell_width: u32, cell_height: u32) -> Vec<Rgba<u8>> {
+        let mut averages = Vec::new();
+        for cell_y in (0..img.height()).step_by(cell_height as usize) {
+            for cell_x in (0..img.width()).step_by(cell_width as usize) {
+                let mut sum = [0u32; 4];
+                for y in 0..cell_height {
+                    for x in 0..cell_width {
+                        let pixel = img.get_pixel(cell_x + x, cell_y + y);
+                        for channel in 0..4 {
+                            sum[channel] += u32::from(pixel[channel]);
+                        }
+                    }
+                }
+                averages.push(sum.map(|channel| u8::try_from(channel / (cell_width * cell_height)).unwrap()).into());
+            }
+        }
+        averages
+    }
+
+    #[test]
+    fn test_matches_per_pixel_average() {
+        let img = RgbaImage::from_fn(12, 8, |x, y| Rgba([(x * 20) as u8, (y * 30) as u8, ((x * y) % 256) as u8, 200]));
+        let mut averager = GridAverager::new(12, 8, 3, 4, RowLayout::Rgba8).unwrap();
+        for row in img.as_raw().chunks_exact(12 * 4) {
+            averager.push_row(row).unwrap();
+        }
+
+        let expected = naive_average(&DynamicImage::ImageRgba8(img), 3, 4);
+        assert_eq!(averager.finish(), expected);
+    }
+
+    #[test]
+    fn test_rejects_uneven_grid() {
+        assert!(GridAverager::new(10, 8, 3, 4, RowLayout::Rgba8).is_err());
+        let mut averager = GridAverager::new(6, 4, 3, 4, RowLayout::Rgba8).unwrap();
+        assert!(averager.push_row(&[0; 5 * 4]).is_err());
+        let mut averager = GridAverager::new(6, 4, 3, 4, RowLayout::Rgb8).unwrap();
+        assert!(averager.push_row(&[0; 6 * 4]).is_err());
+    }
+
+    #[test]
+    fn test_layouts_match_rgba() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(12, 8, |x, y| image::Rgb([(x * 20) as u8, (y * 30) as u8, ((x * y) % 256) as u8])));
+        for img in [img.clone(), DynamicImage::ImageLuma8(img.to_luma8()), DynamicImage::ImageLumaA8(img.to_luma_alpha8()), DynamicImage::ImageRgb16(img.to_rgb16())] {
+            let expected = naive_average(&DynamicImage::ImageRgba8(img.to_rgba8()), 3, 4);
+            assert_eq!(average_image_grid(&img, 3, 4).unwrap(), expected);
+
+            // 8-bit images give the same averages when decoded row by row from a png
+            if RowLayout::of(&img).is_some() {
+                let mut png = Vec::new();
+                img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+                assert_eq!(average_png_grid(std::io::Cursor::new(png), 3, 4).unwrap(), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use super::{ApproxState, average_pixel_grid, placement_cost};
+use super::board::Board;
+use super::diff_cache::DiffCache;
+use super::draw::SkinnedBoard;
+use super::piece::{Cell, Orientation, Piece};
+use crate::cli::{Config, GlobalData, RngStream};
+
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, Rgba};
+
+// entry points into the approximation's hot paths so benchmarks can time them in isolation
+
+// counts how many pieces fit at every cell of a board with every other cell filled, like the search's placement checks
+pub fn count_placeable(board_width: usize, board_height: usize) -> Result<usize> {
+    let mut board = Board::new(board_width, board_height);
+    for y in 0..board_height {
+        for x in (y % 2..board_width).step_by(2) {
+            board.fill_cell(&Cell { x, y }, 'G')?;
+        }
+    }
+
+    let mut count = 0;
+    for y in 0..board_height {
+        for x in 0..board_width {
+            for orientation in Orientation::all() {
+                count += Piece::all_normal(Cell { x, y }, orientation).iter().filter(|piece| board.can_place(piece)).count();
+            }
+        }
+    }
+    Ok(count)
+}
+
+pub fn pixel_grid(source_img: &DynamicImage, cell_width: u32, cell_height: u32) -> Result<Vec<Rgba<u8>>> {
+    average_pixel_grid(source_img, cell_width, cell_height)
+}
+
+// scores every skin, piece, and orientation at every cell of an empty board, like the search's first look at each cell
+// returns the sum of the diffs so the work can't be optimized away
+pub fn score_all_pieces(source_img: &DynamicImage, config: &Config, glob: &GlobalData) -> Result<f64> {
+    let board = SkinnedBoard::new(config.board_width, config.board_height, &glob.skins);
+    assert_eq!(source_img.dimensions(), (u32::try_from(config.board_width)? * board.skins_width(), u32::try_from(config.board_height)? * board.skins_height()));
+
+    let state = ApproxState {
+        config,
+        source_img,
+        avg_pixel_grid: average_pixel_grid(source_img, board.skins_width(), board.skins_height())?,
+        cache: DiffCache::new(board.board_width(), board.board_height(), glob.skins.len(), config.garbage_levels > 1),
+        rng: config.rng(RngStream::TieBreak),
+        cell_skins: None,
+        filled_cells: 0,
+        preview: None,
+        previous_pieces: Vec::new(),
+        confidence: None,
+        detail: None,
+        mask: None,
+    };
+
+    let mut total = 0.0;
+    for y in 0..board.board_height() {
+        for x in 0..board.board_width() {
+            for skin in board.iter_skins() {
+                for orientation in Orientation::all() {
+                    for piece in Piece::all_normal(Cell { x, y }, orientation) {
+                        if board.board().can_place(&piece) {
+                            total += placement_cost(&piece, &board, skin, &state)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(total)
+}
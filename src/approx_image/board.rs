@@ -1,16 +1,32 @@
 use super::piece::{Cell, Piece};
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(into = "BoardData", try_from = "BoardData")]
 pub struct Board {
     cells: Vec<char>,
+    // one bit per cell, set when the cell is filled, so occupancy checks don't need to compare chars
+    // each row starts on a new word so rows wider than 64 cells still work
+    occupied: Vec<u64>,
+    words_per_row: usize,
     pieces: Vec<Piece>,
     pub width: usize,
     pub height: usize
 }
 
+// how `--ascii-out` writes the board
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum AsciiFormat {
+    // one line per row, boxed in like `Board::print`
+    #[default]
+    Grid,
+    // a single line with rows split by `/` and runs of a cell written as a count then the cell, like `3I.2G`; empty cells are `.`
+    Rle,
+}
+
 #[derive(Debug, Error)]
 pub enum CellError {
     #[error("Invalid cell: {0:?}")]
@@ -18,15 +34,58 @@ pub enum CellError {
 
     #[error("Occupied cell: {0:?}")]
     OccupiedCell(Cell),
+
+    #[error("Board rows don't match its {width}x{height} dimensions")]
+    InvalidRows{ width: usize, height: usize },
+}
+
+// how a board is serialized: its cells as one string per row and its placed pieces, without the bitboard
+#[derive(Serialize, Deserialize)]
+struct BoardData {
+    width: usize,
+    height: usize,
+    rows: Vec<String>,
+    pieces: Vec<Piece>,
+}
+
+impl From<Board> for BoardData {
+    fn from(board: Board) -> BoardData {
+        BoardData {
+            width: board.width,
+            height: board.height,
+            rows: board.cells.chunks(board.width.max(1)).map(|row| row.iter().collect()).collect(),
+            pieces: board.pieces,
+        }
+    }
+}
+
+impl TryFrom<BoardData> for Board {
+    type Error = anyhow::Error;
+
+    fn try_from(data: BoardData) -> Result<Board> {
+        if data.rows.len() != data.height || data.rows.iter().any(|row| row.chars().count() != data.width) {
+            Err(CellError::InvalidRows { width: data.width, height: data.height })?;
+        }
+        let mut board = Board::new(data.width, data.height);
+        for (y, row) in data.rows.iter().enumerate() {
+            for (x, cell_char) in row.chars().enumerate() {
+                board.set(&Cell { x, y }, cell_char)?;
+            }
+        }
+        board.pieces = data.pieces;
+        Ok(board)
+    }
 }
 
-pub const BLOCKED_CELL: char = 'B';
 pub const EMPTY_CELL: char = ' ';
 
 impl Board {
     pub fn new(width: usize, height: usize) -> Board {
+        let words_per_row = width.div_ceil(64);
         Board {
             cells: vec![EMPTY_CELL; width * height],
+            occupied: vec![0; words_per_row * height],
+            words_per_row,
             pieces: Vec::new(),
             width,
             height,
@@ -35,22 +94,36 @@ impl Board {
 
     #[allow(dead_code)]
     pub fn print(&self) {
-        println!("+{}+", "-".repeat(self.width));
-        for row in self.cells.chunks(self.width).rev() {
-            println!("|{}|", row.iter().collect::<String>());
+        print!("{}", self.to_ascii(AsciiFormat::Grid));
+    }
+
+    // the board's cells as text, with rows in the same order as the drawn image
+    pub fn to_ascii(&self, format: AsciiFormat) -> String {
+        let rows = self.cells.chunks(self.width);
+        match format {
+            AsciiFormat::Grid => {
+                let border = format!("+{}+\n", "-".repeat(self.width));
+                let mut grid = border.clone();
+                for row in rows {
+                    grid += &format!("|{}|\n", row.iter().collect::<String>());
+                }
+                grid + &border
+            }
+            AsciiFormat::Rle => rows.map(rle_row).collect::<Vec<_>>().join("/") + "\n",
         }
-        println!("+{}+", "-".repeat(self.width));
     }
 
     pub fn can_place(&self, piece: &Piece) -> bool {
         let Ok(to_occupy) = piece.get_occupancy() else {return false;};
-        for cell in &to_occupy {
-            let Ok(curr) = self.get(cell) else {return false};
-            if curr != EMPTY_CELL {
-                return false;
-            }
+        to_occupy.iter().all(|cell| self.is_empty(cell))
+    }
+
+    // whether the cell is on the board and unfilled
+    pub fn is_empty(&self, cell: &Cell) -> bool {
+        if !(cell.x < self.width && cell.y < self.height) {
+            return false;
         }
-        true
+        self.occupied[cell.y * self.words_per_row + cell.x / 64] & (1 << (cell.x % 64)) == 0
     }
 
     pub fn place(&mut self, piece: &Piece) -> Result<()> {
@@ -58,28 +131,85 @@ impl Board {
 
         // check if cells are empty
         for cell in &to_occupy {
-            let curr = self.get(cell)?;
-            if curr != EMPTY_CELL {
+            self.get(cell)?;
+            if !self.is_empty(cell) {
                 Err(CellError::OccupiedCell(*cell))?;
             }
         }
 
         // if so, place
         for cell in &to_occupy {
-            *self.get_mut(cell)? = piece.get_char();
+            self.set(cell, piece.get_char())?;
         }
         self.pieces.push(piece.clone());
 
         Ok(())
     }
 
+    // fills a single cell with a block that isn't part of any piece
+    pub fn fill_cell(&mut self, cell: &Cell, cell_char: char) -> Result<()> {
+        if self.get(cell)? != EMPTY_CELL {
+            Err(CellError::OccupiedCell(*cell))?;
+        }
+        self.set(cell, cell_char)
+    }
+
+    // whether the piece rests on the floor or on a filled cell that isn't part of itself
+    // rows grow downwards, so the floor is the last row
+    pub fn is_supported(&self, piece: &Piece) -> Result<bool> {
+        let cells = piece.get_occupancy()?;
+        Ok(cells.iter().any(|cell| {
+            let below = Cell { x: cell.x, y: cell.y + 1 };
+            cell.y + 1 == self.height || (!cells.contains(&below) && self.get(&below).is_ok_and(|c| c != EMPTY_CELL))
+        }))
+    }
+
+    // filled cells that don't rest on the floor, either directly or through the pieces and cells below them
+    pub fn floating_cells(&self) -> Result<Vec<Cell>> {
+        // cells of the same piece are held up together, while cells filled without a piece stand alone
+        let mut piece_at = vec![None; self.cells.len()];
+        for (i, piece) in self.pieces.iter().enumerate() {
+            for cell in piece.get_occupancy()? {
+                piece_at[cell.y * self.width + cell.x] = Some(i);
+            }
+        }
+
+        // spread support up from the floor until nothing changes
+        let mut grounded = vec![false; self.cells.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..self.cells.len()).rev() {
+                if grounded[i] || self.cells[i] == EMPTY_CELL {
+                    continue;
+                }
+                if i + self.width >= self.cells.len() || grounded[i + self.width] {
+                    changed = true;
+                    match piece_at[i] {
+                        Some(piece) => {
+                            for cell in self.pieces[piece].get_occupancy()? {
+                                grounded[cell.y * self.width + cell.x] = true;
+                            }
+                        }
+                        None => grounded[i] = true,
+                    }
+                }
+            }
+        }
+
+        Ok((0..self.cells.len())
+            .filter(|&i| self.cells[i] != EMPTY_CELL && !grounded[i])
+            .map(|i| Cell { x: i % self.width, y: i / self.width })
+            .collect())
+    }
+
     #[allow(dead_code)]
     pub fn undo_last_move(&mut self) -> Result<()> {
         assert!(!self.pieces.is_empty());
 
         let piece = self.pieces.pop().expect("pieces should not be empty");
         for cell in piece.get_occupancy()? {
-            *self.get_mut(&cell)? = EMPTY_CELL;
+            self.set(&cell, EMPTY_CELL)?;
         }
         Ok(())
     }
@@ -88,12 +218,16 @@ impl Board {
     pub fn remove_piece(&mut self, piece: &Piece) -> Result<()> {
         let to_occupy = piece.get_occupancy()?;
         for cell in &to_occupy {
-            *self.get_mut(cell)? = EMPTY_CELL;
+            self.set(cell, EMPTY_CELL)?;
         }
         self.pieces.retain(|p| p != piece);
         Ok(())
     }
 
+    pub fn pieces(&self) -> &[Piece] {
+        &self.pieces
+    }
+
     pub fn get(&self, cell: &Cell) -> Result<char> {
         if !(cell.x < self.width && cell.y < self.height) {
             Err(CellError::InvalidCell(*cell))?;
@@ -101,14 +235,34 @@ impl Board {
         Ok(self.cells[cell.y * self.width + cell.x])
     }
 
-    pub fn get_mut(&mut self, cell: &Cell) -> Result<&mut char> {
+    // every write goes through here so the cells and the bitboard stay in sync
+    fn set(&mut self, cell: &Cell, cell_char: char) -> Result<()> {
         if !(cell.x < self.width && cell.y < self.height) {
             Err(CellError::InvalidCell(*cell))?;
         }
-        Ok(&mut self.cells[cell.y * self.width + cell.x])
+        self.cells[cell.y * self.width + cell.x] = cell_char;
+        let bit = 1 << (cell.x % 64);
+        let word = &mut self.occupied[cell.y * self.words_per_row + cell.x / 64];
+        if cell_char == EMPTY_CELL {
+            *word &= !bit;
+        } else {
+            *word |= bit;
+        }
+        Ok(())
     }
 }
 
+fn rle_row(row: &[char]) -> String {
+    let mut encoded = String::new();
+    for run in row.chunk_by(|a, b| a == b) {
+        if run.len() > 1 {
+            encoded += &run.len().to_string();
+        }
+        encoded.push(if run[0] == EMPTY_CELL { '.' } else { run[0] });
+    }
+    encoded
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -136,6 +290,16 @@ mod tests {
         assert!(board.place(&piece).is_err());
     }
 
+    #[test]
+    fn test_fill_cell() {
+        let mut board = Board::new(4, 4);
+        let cell = Cell { x: 3, y: 3 };
+        assert!(board.fill_cell(&cell, 'T').is_ok());
+        assert_eq!(board.get(&cell).unwrap(), 'T');
+        assert!(board.fill_cell(&cell, 'T').is_err());
+        assert!(board.fill_cell(&Cell { x: 4, y: 0 }, 'T').is_err());
+    }
+
     #[test]
     fn test_place_overlap_2() {
         let mut board = Board::new(10, 20);
@@ -144,4 +308,61 @@ mod tests {
         assert!(board.place(&piece).is_ok());
         assert!(board.place(&piece2).is_err());
     }
+
+    #[test]
+    fn test_occupancy_past_first_word() {
+        // cells 62..66 straddle the first and second words of each row
+        let mut board = Board::new(100, 4);
+        let piece = Piece::I(Cell { x: 62, y: 2 }, Orientation::North);
+        board.place(&piece).unwrap();
+        assert!((62..66).all(|x| !board.is_empty(&Cell { x, y: 2 })));
+        assert!(board.is_empty(&Cell { x: 66, y: 2 }) && board.is_empty(&Cell { x: 62, y: 1 }));
+        assert!(!board.can_place(&Piece::O(Cell { x: 64, y: 2 }, Orientation::North)));
+
+        board.remove_piece(&piece).unwrap();
+        assert!(board.can_place(&piece));
+        assert!(!board.is_empty(&Cell { x: 100, y: 0 }));
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let mut board = Board::new(5, 2);
+        board.place(&Piece::I(Cell { x: 0, y: 1 }, Orientation::North)).unwrap();
+        board.fill_cell(&Cell { x: 4, y: 0 }, 'G').unwrap();
+
+        assert_eq!(board.to_ascii(AsciiFormat::Grid), "+-----+\n|    G|\n|IIII |\n+-----+\n");
+        assert_eq!(board.to_ascii(AsciiFormat::Rle), "4.G/4I.\n");
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        let mut board = Board::new(70, 2);
+        board.place(&Piece::I(Cell { x: 62, y: 1 }, Orientation::North)).unwrap();
+        board.fill_cell(&Cell { x: 0, y: 0 }, 'G').unwrap();
+
+        let json = serde_json::to_string(&board).unwrap();
+        let loaded: Board = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.to_ascii(AsciiFormat::Rle), board.to_ascii(AsciiFormat::Rle));
+        assert_eq!(loaded.pieces(), board.pieces());
+        assert!(!loaded.is_empty(&Cell { x: 64, y: 1 }) && loaded.is_empty(&Cell { x: 1, y: 0 }));
+
+        assert!(serde_json::from_str::<Board>(r#"{"width":2,"height":1,"rows":["G"],"pieces":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_floating_cells() {
+        let mut board = Board::new(4, 3);
+        let floor = Piece::I(Cell { x: 0, y: 2 }, Orientation::North);
+        let top = Piece::I(Cell { x: 0, y: 0 }, Orientation::North);
+        board.place(&floor).unwrap();
+        board.place(&top).unwrap();
+        assert!(board.is_supported(&floor).unwrap());
+        assert!(!board.is_supported(&top).unwrap());
+        assert_eq!(board.floating_cells().unwrap().len(), 4);
+
+        // a single cell under one end holds up the whole piece
+        board.fill_cell(&Cell { x: 0, y: 1 }, 'G').unwrap();
+        assert!(board.is_supported(&top).unwrap());
+        assert!(board.floating_cells().unwrap().is_empty());
+    }
 }
\ No newline at end of file
@@ -0,0 +1,89 @@
+use super::cell_pixel_diff;
+use super::board::EMPTY_CELL;
+use super::draw::SkinnedBoard;
+use super::piece::Cell;
+
+use anyhow::Result;
+use image::DynamicImage;
+
+// the pixel diff of each cell of a board against the source, scored the same way blocks are during the search
+// the drawn image is never built, and after changing a few cells only those are rescored with `update`
+pub struct BoardDiff {
+    diffs: Vec<f64>,
+    board_width: usize,
+    total: f64,
+}
+
+impl BoardDiff {
+    pub fn new(board: &SkinnedBoard, source_img: &DynamicImage) -> Result<BoardDiff> {
+        let mut board_diff = BoardDiff {
+            diffs: vec![0.0; board.board_width() * board.board_height()],
+            board_width: board.board_width(),
+            total: 0.0,
+        };
+        let cells: Vec<_> = (0..board.board_height())
+            .flat_map(|y| (0..board.board_width()).map(move |x| Cell { x, y }))
+            .collect();
+        board_diff.update(board, source_img, &cells)?;
+        Ok(board_diff)
+    }
+
+    // rescores `cells` after they've been changed on `board`
+    pub fn update(&mut self, board: &SkinnedBoard, source_img: &DynamicImage, cells: &[Cell]) -> Result<()> {
+        for cell in cells {
+            let diff = cell_diff(board, source_img, cell)?;
+            let index = cell.y * self.board_width + cell.x;
+            self.total += diff - self.diffs[index];
+            self.diffs[index] = diff;
+        }
+        Ok(())
+    }
+
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    pub fn cell(&self, cell: &Cell) -> f64 {
+        self.diffs[cell.y * self.board_width + cell.x]
+    }
+}
+
+// empty cells are drawn transparent, so they aren't counted
+fn cell_diff(board: &SkinnedBoard, source_img: &DynamicImage, cell: &Cell) -> Result<f64> {
+    let cell_char = board.board().get(cell)?;
+    if cell_char == EMPTY_CELL {
+        return Ok(0.0);
+    }
+    let skin = board.get_skin(board.get_cells_skin(cell));
+    cell_pixel_diff(cell, skin, skin.block_image_from_char(cell_char), source_img, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::approx_board;
+    use crate::cli::{test_glob, Config};
+
+    #[test]
+    fn test_update_matches_rescoring() {
+        let glob = test_glob(4);
+        let source_img = DynamicImage::ImageRgba8(image::RgbaImage::from_fn(24, 16, |x, y| image::Rgba([u8::try_from(x * 10).unwrap(), 60, u8::try_from(y * 15).unwrap(), 255])));
+        let config = Config { board_width: 6, board_height: 4, ..Config::default() };
+        let board = approx_board(&source_img, &config, &glob).unwrap();
+        assert!(board.total_diff(&source_img).unwrap() > 0.0);
+
+        // an empty board has nothing drawn to compare
+        let mut board = SkinnedBoard::new(6, 4, &glob.skins);
+        let mut board_diff = BoardDiff::new(&board, &source_img).unwrap();
+        assert_eq!(board_diff.total(), 0.0);
+
+        let cell = Cell { x: 2, y: 1 };
+        board.fill_cell(&cell, 'G', 0).unwrap();
+        board.fill_cell(&Cell { x: 0, y: 3 }, 'B', 0).unwrap();
+        board_diff.update(&board, &source_img, &[cell, Cell { x: 0, y: 3 }]).unwrap();
+        let rescored = BoardDiff::new(&board, &source_img).unwrap();
+        assert!((board_diff.total() - rescored.total()).abs() < 1e-6);
+        assert_eq!(rescored.total(), board.total_diff(&source_img).unwrap());
+        assert_eq!(board_diff.cell(&cell), rescored.cell(&cell));
+    }
+}
@@ -0,0 +1,257 @@
+use super::board::EMPTY_CELL;
+use super::draw::{self, SkinnedBoard, create_skins_from};
+use super::piece::{Cell, Piece};
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Debug, Error)]
+pub enum DumpError {
+    #[error("Skin used by the board was not found: {0}")]
+    MissingSkin(String),
+
+    #[error("Board dump has {actual} {field} but its dimensions need {expected}")]
+    InvalidDimensions{ field: &'static str, expected: usize, actual: usize },
+
+    #[error("Board dump has a piece outside of the board at {0:?}")]
+    InvalidPiece(Cell),
+
+    #[error("Board dump uses skin id {id}, but only lists {skins} skins")]
+    InvalidSkinId{ id: usize, skins: usize },
+
+    #[error("Board dump is version {0}, but only versions up to {DUMP_VERSION} can be read")]
+    UnsupportedVersion(u32),
+}
+
+// bumped whenever the dump's fields change; dumps from before versions existed are version 1
+pub const DUMP_VERSION: u32 = 2;
+
+// the version sits next to the dump's own fields, so older dumps without it still load
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    #[serde(default = "unversioned")]
+    version: u32,
+    #[serde(flatten)]
+    dump: T,
+}
+
+fn unversioned() -> u32 {
+    1
+}
+
+// skin-annotated board state that can be re-rendered without redoing the search
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct BoardDump {
+    pub width: usize,
+    pub height: usize,
+    // skin names, indexed by the skin ids used in `cells_skin`
+    pub skins: Vec<String>,
+    // one string of cell characters per row, starting from y = 0
+    pub rows: Vec<String>,
+    // skin id of every cell in row-major order; empty cells have no skin
+    pub cells_skin: Vec<Option<usize>>,
+    // the placed pieces, so a loaded board keeps them apart from garbage filled cells; missing before version 2
+    #[serde(default)]
+    pub pieces: Vec<Piece>,
+}
+
+impl BoardDump {
+    pub fn new(board: &SkinnedBoard) -> Result<BoardDump> {
+        let mut rows = Vec::new();
+        let mut cells_skin = Vec::new();
+        for y in 0..board.board_height() {
+            let mut row = String::new();
+            for x in 0..board.board_width() {
+                let cell = Cell { x, y };
+                let cell_char = board.board().get(&cell)?;
+                row.push(cell_char);
+                cells_skin.push((cell_char != EMPTY_CELL).then(|| board.get_cells_skin(&cell)));
+            }
+            rows.push(row);
+        }
+
+        Ok(BoardDump {
+            width: board.board_width(),
+            height: board.board_height(),
+            skins: board.iter_skins().map(|skin| skin.name().to_string()).collect(),
+            rows,
+            cells_skin,
+            pieces: board.board().pieces().to_vec(),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<BoardDump> {
+        BoardDump::from_json(&fs::read_to_string(path)?)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string(&Envelope { version: DUMP_VERSION, dump: self })?)
+    }
+
+    pub fn from_json(json: &str) -> Result<BoardDump> {
+        let envelope: Envelope<BoardDump> = serde_json::from_str(json)?;
+        if envelope.version > DUMP_VERSION {
+            Err(DumpError::UnsupportedVersion(envelope.version))?;
+        }
+        Ok(envelope.dump)
+    }
+
+    // draws the board using the given skins, which are matched to the dump's skins by name
+    pub fn render(&self, skins: &draw::Skins) -> Result<DynamicImage> {
+        draw::draw(&self.skinned_board(skins)?)
+    }
+
+    // rebuilds the board with the given skins, placing the dump's pieces before filling in the rest of its cells
+    pub fn skinned_board<'a>(&self, skins: &'a draw::Skins) -> Result<SkinnedBoard<'a>> {
+        self.validate()?;
+
+        // map the dump's skin ids onto the loaded skins
+        let skin_ids = self.skins
+            .iter()
+            .map(|name| {
+                skins
+                    .iter()
+                    .position(|skin| skin.name() == name)
+                    .ok_or_else(|| DumpError::MissingSkin(name.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut board = SkinnedBoard::new(self.width, self.height, skins);
+        for piece in &self.pieces {
+            let cell = piece.get_cell();
+            if let Some(skin_id) = self.cells_skin[cell.y * self.width + cell.x] {
+                board.place(piece, skin_ids[skin_id])?;
+            }
+        }
+        for (y, row) in self.rows.iter().enumerate() {
+            for (x, cell_char) in row.chars().enumerate() {
+                let cell = Cell { x, y };
+                if let (Some(skin_id), true) = (self.cells_skin[y * self.width + x], board.empty_at(&cell)) {
+                    board.fill_cell(&cell, cell_char, skin_ids[skin_id])?;
+                }
+            }
+        }
+        Ok(board)
+    }
+
+    // dumps can come from anywhere, so everything that's indexed by is checked against the dimensions first
+    fn validate(&self) -> Result<(), DumpError> {
+        let mismatch = |field, expected, actual| if expected == actual { Ok(()) } else { Err(DumpError::InvalidDimensions { field, expected, actual }) };
+        mismatch("rows", self.height, self.rows.len())?;
+        for row in &self.rows {
+            mismatch("cells in a row", self.width, row.chars().count())?;
+        }
+        mismatch("cell skins", self.width * self.height, self.cells_skin.len())?;
+
+        if let Some(&id) = self.cells_skin.iter().flatten().find(|&&id| id >= self.skins.len()) {
+            Err(DumpError::InvalidSkinId { id, skins: self.skins.len() })?;
+        }
+        if let Some(piece) = self.pieces.iter().find(|piece| {
+            let cell = piece.get_cell();
+            cell.x >= self.width || cell.y >= self.height
+        }) {
+            Err(DumpError::InvalidPiece(piece.get_cell()))?;
+        }
+        Ok(())
+    }
+}
+
+// re-renders a saved board with the skins in `skins_dir`, using square blocks of `block_size` pixels
+pub fn run(board_path: &Path, output: &Path, skins_dir: &Path, block_size: u32) -> Result<()> {
+    info!(board = %board_path.display(), "rendering board");
+
+    let dump = BoardDump::load(board_path).context("could not load board")?;
+    let mut skins = create_skins_from(skins_dir)?;
+    for skin in &mut skins {
+        skin.resize(block_size, block_size);
+    }
+
+    let img = dump.render(&skins).context("could not render board")?;
+    img.save(output).context("could not save output image")?;
+    info!(width = dump.width, height = dump.height, output = %output.display(), "rendered board");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::test_skin;
+    use crate::approx_image::piece::{Orientation, Piece};
+
+    #[test]
+    fn test_round_trip_render() {
+        let mut skin = test_skin();
+        skin.resize(6, 6);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(5, 4, &skins);
+        board.place(&Piece::T(Cell { x: 2, y: 1 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::Black(Cell { x: 4, y: 3 }), 0).unwrap();
+
+        let dump = BoardDump::new(&board).unwrap();
+        let loaded = BoardDump::from_json(&dump.to_json().unwrap()).unwrap();
+        assert_eq!(loaded, dump);
+        assert_eq!(loaded.skinned_board(&skins).unwrap().board().pieces(), board.board().pieces());
+
+        let rendered = loaded.render(&skins).unwrap();
+        assert_eq!(rendered.to_rgba8(), draw::draw(&board).unwrap().to_rgba8());
+    }
+
+    #[test]
+    fn test_versions() {
+        // dumps from before versions and pieces were saved still load
+        let unversioned = r#"{"width":1,"height":1,"skins":["skin"],"rows":["G"],"cells_skin":[0]}"#;
+        let dump = BoardDump::from_json(unversioned).unwrap();
+        assert!(dump.pieces.is_empty());
+
+        let newer = r#"{"version":99,"width":1,"height":1,"skins":["skin"],"rows":["G"],"cells_skin":[0]}"#;
+        assert!(BoardDump::from_json(newer).is_err());
+    }
+
+    #[test]
+    fn test_missing_skin() {
+        let skin = test_skin();
+        let dump = BoardDump {
+            width: 1,
+            height: 1,
+            skins: vec!["not a skin".to_string()],
+            rows: vec!["I".to_string()],
+            cells_skin: vec![Some(0)],
+            pieces: Vec::new(),
+        };
+        assert!(dump.render(&vec![skin]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_dumps() {
+        let skin = test_skin();
+        let skins = vec![skin];
+        let malformed = [
+            // a piece anchored past the edge of the board
+            r#"{"width":1,"height":1,"skins":["skin"],"rows":["G"],"cells_skin":[0],"pieces":[{"Gray":{"x":5,"y":0}}]}"#,
+            // a skin id with no skin name
+            r#"{"width":1,"height":1,"skins":["skin"],"rows":["G"],"cells_skin":[3]}"#,
+            // a row longer than the board
+            r#"{"width":1,"height":2,"skins":["skin"],"rows":["G","GG"],"cells_skin":[0,0]}"#,
+        ];
+        for json in malformed {
+            let dump = BoardDump::from_json(json).unwrap();
+            let err = dump.render(&skins).unwrap_err();
+            assert!(err.downcast_ref::<DumpError>().is_some(), "{err}");
+        }
+
+        let dump = BoardDump::from_json(r#"{"width":1,"height":2,"skins":["skin"],"rows":["G"],"cells_skin":[0,0]}"#).unwrap();
+        assert_eq!(dump.render(&skins).unwrap_err().to_string(), "Board dump has 1 rows but its dimensions need 2");
+    }
+}
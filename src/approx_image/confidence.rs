@@ -0,0 +1,82 @@
+use super::piece::Cell;
+
+use std::path::Path;
+
+use anyhow::Result;
+use image::{GrayImage, Luma};
+
+// per-cell diff of the piece that was placed over each cell
+// cells filled without being scored, like the nearest-mino and neighbor fills, have no diff
+pub struct ConfidenceMap {
+    width: usize,
+    height: usize,
+    diffs: Vec<Option<f64>>,
+}
+
+impl ConfidenceMap {
+    pub fn new(width: usize, height: usize) -> ConfidenceMap {
+        ConfidenceMap {
+            width,
+            height,
+            diffs: vec![None; width * height],
+        }
+    }
+
+    pub fn record(&mut self, cells: &[Cell], diff: f64) {
+        for cell in cells {
+            self.diffs[cell.y * self.width + cell.x] = Some(diff);
+        }
+    }
+
+    // normalized scores where 1 is the best match on the board and 0 is the worst
+    pub fn scores(&self) -> Vec<Option<f64>> {
+        let scored = self.diffs.iter().flatten();
+        let min = scored.clone().copied().fold(f64::MAX, f64::min);
+        let max = scored.copied().fold(f64::MIN, f64::max);
+        let range = max - min;
+        self.diffs
+            .iter()
+            .map(|diff| diff.map(|diff| if range > 0.0 { 1.0 - (diff - min) / range } else { 1.0 }))
+            .collect()
+    }
+
+    // writes a png heatmap with one pixel per cell if the path ends in `.png`, and a csv otherwise
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let scores = self.scores();
+        if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            // low-confidence cells are bright so they stand out; unscored cells are black
+            let heatmap = GrayImage::from_fn(u32::try_from(self.width)?, u32::try_from(self.height)?, |x, y| {
+                let score = scores[y as usize * self.width + x as usize];
+                Luma([score.map_or(0, |score| (255.0 - score * 255.0).round() as u8)])
+            });
+            heatmap.save(path)?;
+            return Ok(());
+        }
+
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(["x", "y", "diff", "confidence"])?;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                let format = |value: Option<f64>| value.map_or_else(String::new, |value| value.to_string());
+                writer.write_record([x.to_string(), y.to_string(), format(self.diffs[index]), format(scores[index])])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scores_are_normalized() {
+        let mut map = ConfidenceMap::new(2, 2);
+        map.record(&[Cell { x: 0, y: 0 }, Cell { x: 1, y: 0 }], 10.0);
+        map.record(&[Cell { x: 0, y: 1 }], 30.0);
+
+        assert_eq!(map.scores(), vec![Some(1.0), Some(1.0), Some(0.0), None]);
+    }
+}
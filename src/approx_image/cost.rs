@@ -0,0 +1,163 @@
+use super::{cell_pixel_diff, subtract_pixels, RED_WEIGHT, GREEN_WEIGHT, BLUE_WEIGHT};
+use super::board::EMPTY_CELL;
+use super::detail::DetailMap;
+use super::diff_cache::DiffCache;
+use super::draw::{BlockSkin, SkinnedBoard};
+use super::piece::{Cell, Piece};
+
+use std::fmt::Debug;
+
+use anyhow::Result;
+use image::{DynamicImage, Rgba};
+
+// scores how badly a piece drawn with a skin would match the source at its position on the board; lower is better
+// set `Config::cost` to search with a custom cost, otherwise `AvgPixelDiff` is used
+pub trait PlacementCost: Debug + Send + Sync {
+    fn cost(&self, piece: &Piece, board: &SkinnedBoard, skin: &BlockSkin, img: &DynamicImage, ctx: &mut CostContext) -> Result<f64>;
+}
+
+// data shared by every cost computed during a single approximation
+pub struct CostContext<'a> {
+    // average color of the source under each cell, row by row
+    pub avg_pixel_grid: &'a [Rgba<u8>],
+    // per-pixel diff weights, if `--detail-weight` is set
+    pub detail: Option<&'a DetailMap>,
+    pub(super) cache: &'a DiffCache,
+}
+
+// the default cost: the per-pixel diff of each block against the source, plus how well the piece's average color
+// keeps the same contrast with its already placed neighbors as the source has
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AvgPixelDiff;
+
+impl PlacementCost for AvgPixelDiff {
+    fn cost(&self, piece: &Piece, board: &SkinnedBoard, skin: &BlockSkin, img: &DynamicImage, ctx: &mut CostContext) -> Result<f64> {
+        let avg_pixel_grid = ctx.avg_pixel_grid;
+
+        let mut curr_pixel_diff: f64 = 0.0;
+        let mut total_curr_pixels: u32 = 0;
+
+        let mut context_pixel_diff: f64 = 0.0;
+        let mut total_context_pixels: u32 = 0;
+
+        let block_image = skin.block_image_from_piece(piece);
+
+        let center_cell = piece.get_cell();
+        let occupancy = piece.get_occupancy()?;
+        let context_cells = find_context_cells(board, &occupancy, &center_cell)?;
+
+        let avg_board_cell_pixel = block_image.get_average_pixel();
+        let avg_source_cell_pixel = find_average_source_cell_pixel(avg_pixel_grid, &occupancy, board);
+        for cell in occupancy {
+            // first analyze the context using average pixels
+            for context_cell in &context_cells {
+                let cell_char = board.board().get(&cell)?;
+                let skin_id = board.get_cells_skin(context_cell);
+
+                let context_skin = board.get_skin(skin_id);
+                let context_block_image = context_skin.block_image_from_char(cell_char);
+                let avg_board_context_pixel = context_block_image.get_average_pixel();
+
+                let avg_source_context_pixel = avg_pixel_grid[context_cell.y * board.board_width() + context_cell.x];
+
+                let board_context_diff = subtract_pixels(avg_board_cell_pixel, avg_board_context_pixel);
+                let source_context_diff = subtract_pixels(avg_source_cell_pixel, avg_source_context_pixel);
+
+                context_pixel_diff += f64::sqrt(
+                    f64::from(board_context_diff[0] - source_context_diff[0]).powf(2.0) * RED_WEIGHT +
+                    f64::from(board_context_diff[1] - source_context_diff[1]).powf(2.0) * GREEN_WEIGHT +
+                    f64::from(board_context_diff[2] - source_context_diff[2]).powf(2.0) * BLUE_WEIGHT
+                );
+                total_context_pixels += 1;
+            }
+
+            // then analyze the individual cell to find the pixel difference between the current cells
+            curr_pixel_diff += ctx.cache.get_or_compute(&cell, skin.id(), piece, || cell_pixel_diff(&cell, skin, block_image, img, ctx.detail))?;
+            total_curr_pixels += skin.width() * skin.height();
+        }
+
+        // weight the context diff in comparison with the current diff
+        let avg_pixel_diff = 
+            if total_context_pixels != 0 {
+                curr_pixel_diff / f64::from(total_curr_pixels) + context_pixel_diff / f64::from(total_context_pixels)
+            } else {
+                curr_pixel_diff / f64::from(total_curr_pixels)
+            };
+
+
+        Ok(avg_pixel_diff)
+    }
+}
+
+// a lower bound on `AvgPixelDiff` from average colors alone, cheap enough to rule out candidates before scoring them
+// the squared diff over a cell's pixels is at least the squared diff of their means, and the context diff is never negative;
+// the source averages are truncated, so each channel's gap is only counted past that rounding
+pub(super) fn avg_pixel_lower_bound(piece: &Piece, skin: &BlockSkin, avg_pixel_grid: &[Rgba<u8>], board_width: usize) -> Result<f64> {
+    let block_mean = skin.block_image_from_piece(piece).mean();
+    let occupancy = piece.get_occupancy()?;
+    let mut bound = 0.0;
+    for cell in &occupancy {
+        let source = avg_pixel_grid[cell.y * board_width + cell.x];
+        let gaps = [0, 1, 2].map(|i| {
+            let source = f64::from(source[i]);
+            (source - block_mean[i]).max(block_mean[i] - (source + 1.0)).max(0.0).powi(2)
+        });
+        bound += gaps[0] * RED_WEIGHT + gaps[1] * GREEN_WEIGHT + gaps[2] * BLUE_WEIGHT;
+    }
+    Ok(bound / occupancy.len() as f64)
+}
+
+fn find_context_cells(board: &SkinnedBoard, occupancy: &[Cell], center_cell: &Cell) -> Result<Vec<Cell>> {
+    const MIN_DX: i32 = 0;
+    const MIN_DY: i32 = 0;
+    const MAX_DX: i32 = 8;
+    const MAX_DY: i32 = 8;
+
+    // get the context cells
+    let mut context_cells: Vec<Cell> = Vec::new();
+    let mut dy: i32 = MIN_DY;
+    while dy < MAX_DY {
+        // compute and check the new y coordinate
+        let new_y = usize::try_from(i32::try_from(center_cell.y)? + dy);
+        let Ok(new_y) = new_y else {
+            dy += 1;
+            continue
+        };
+
+        let mut dx: i32 = MIN_DX;
+        while dx < MAX_DX {
+            // compute and check the new x coordinate
+            let new_x = usize::try_from(i32::try_from(center_cell.x)? + dx);
+            let Ok(new_x) = new_x else {
+                dx += 1;
+                continue
+            };
+
+            // only append contexts that are occupied with other pieces we already placed
+            let context_cell = Cell {x: new_x, y: new_y};
+            let context_char = board.board().get(&context_cell);
+            if context_char.is_ok() && context_char.expect("there must be a context char") != EMPTY_CELL && !occupancy.contains(&context_cell) {
+                context_cells.push(context_cell);
+            }
+            dx += 1;
+        }
+
+        dy += 1;
+    }
+
+    Ok(context_cells)
+}
+
+fn find_average_source_cell_pixel(avg_pixel_grid: &[Rgba<u8>], occupancy: &Vec<Cell>, board: &SkinnedBoard) -> Rgba<u8> {
+    let mut pixel_sum: [u32; 4] = [0, 0, 0, 0];
+
+    for cell in occupancy {
+        let pixel = &avg_pixel_grid[cell.y * board.board_width() + cell.x];
+        pixel_sum[0] += u32::from(pixel[0]);
+        pixel_sum[1] += u32::from(pixel[1]);
+        pixel_sum[2] += u32::from(pixel[2]);
+        pixel_sum[3] += u32::from(pixel[3]);
+    }
+
+    pixel_sum.map(|x| u8::try_from(x / u32::try_from(occupancy.len()).expect("there must be at least one")).expect("pixel should be in range")).into()
+}
@@ -0,0 +1,63 @@
+use image::DynamicImage;
+use imageproc::gradients::sobel_gradients;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DetailError {
+    #[error("Invalid detail weight, expected a finite number of at least 0: {0}")]
+    InvalidWeight(String),
+}
+
+// per-pixel weights for the diff, raised along the source's edges so fine details count for more than flat areas
+pub struct DetailMap {
+    width: u32,
+    weights: Vec<f64>,
+}
+
+impl DetailMap {
+    // pixels on the strongest edge get `1 + detail_weight` and perfectly flat pixels get 1
+    pub fn new(source_img: &DynamicImage, detail_weight: f64) -> DetailMap {
+        let gradients = sobel_gradients(&source_img.to_luma8());
+        let max = f64::from(gradients.pixels().map(|p| p[0]).max().unwrap_or(0).max(1));
+        DetailMap {
+            width: gradients.width(),
+            weights: gradients.pixels().map(|p| 1.0 + detail_weight * f64::from(p[0]) / max).collect(),
+        }
+    }
+
+    pub fn weight(&self, x: u32, y: u32) -> f64 {
+        self.weights[(y * self.width + x) as usize]
+    }
+}
+
+// weights below 1 would make some pixel diffs count for less than their plain diff, which pruning assumes never happens
+pub fn parse_detail_weight(s: &str) -> Result<f64, DetailError> {
+    match s.parse::<f64>() {
+        Ok(weight) if weight.is_finite() && weight >= 0.0 => Ok(weight),
+        _ => Err(DetailError::InvalidWeight(s.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn test_edges_weigh_more() {
+        // left half black, right half white
+        let img = DynamicImage::from(GrayImage::from_fn(8, 4, |x, _| Luma([if x < 4 { 0 } else { 255 }])));
+        let map = DetailMap::new(&img, 2.0);
+        assert!((map.weight(0, 2) - 1.0).abs() < f64::EPSILON);
+        assert!((map.weight(4, 2) - 3.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_parse_detail_weight() {
+        assert_eq!(parse_detail_weight("0").unwrap(), 0.0);
+        assert_eq!(parse_detail_weight("2.5").unwrap(), 2.5);
+        for invalid in ["-1", "NaN", "inf", "heavy"] {
+            assert!(parse_detail_weight(invalid).is_err());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use super::piece::{Cell, Piece, MAX_SHADES};
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+
+const NUM_BLOCKS: usize = 9;
+
+// memoizes the pixel diff between a skin's block and the source pixels underneath a single cell
+// the same (cell, skin, block) triple gets scored once for every piece/orientation covering the cell,
+// so caching it skips most of the per-pixel work
+// entries are stored as f64 bits so candidates can be scored in parallel; a race only computes the same diff twice
+// a cell's entries are only allocated once it's scored and are dropped once it's filled,
+// so memory follows the cells around the traversal instead of the whole board
+pub struct DiffCache {
+    slots: Vec<OnceLock<Box<[AtomicU64]>>>,
+    board_width: usize,
+    num_skins: usize,
+    // blocks per cell and skin, which includes the garbage shades when they're used
+    num_blocks: usize,
+}
+
+impl DiffCache {
+    pub fn new(board_width: usize, board_height: usize, num_skins: usize, shades: bool) -> DiffCache {
+        let num_blocks = if shades { NUM_BLOCKS + MAX_SHADES } else { NUM_BLOCKS };
+        DiffCache {
+            slots: (0..board_width * board_height).map(|_| OnceLock::new()).collect(),
+            board_width,
+            num_skins,
+            num_blocks,
+        }
+    }
+
+    pub fn get_or_compute(&self, cell: &Cell, skin_id: usize, piece: &Piece, compute: impl FnOnce() -> Result<f64>) -> Result<f64> {
+        // shades share gray's block index, so they get their own entries after the regular blocks
+        let block = match piece {
+            Piece::Shade(_, level) => NUM_BLOCKS + usize::from(*level) - 1,
+            _ => piece.block_index(),
+        };
+        // NaN marks entries that have not been computed yet
+        let slot = self.slots[cell.y * self.board_width + cell.x]
+            .get_or_init(|| (0..self.num_skins * self.num_blocks).map(|_| AtomicU64::new(f64::NAN.to_bits())).collect());
+        let entry = &slot[skin_id * self.num_blocks + block];
+        let diff = f64::from_bits(entry.load(Ordering::Relaxed));
+        if !diff.is_nan() {
+            return Ok(diff);
+        }
+        let diff = compute()?;
+        entry.store(diff.to_bits(), Ordering::Relaxed);
+        Ok(diff)
+    }
+
+    // frees the entries of cells that were filled, since they won't be scored again
+    pub fn release(&mut self, cells: &[Cell]) {
+        for cell in cells {
+            self.slots[cell.y * self.board_width + cell.x].take();
+        }
+    }
+
+    #[cfg(test)]
+    fn allocated_cells(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.get().is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::piece::Orientation;
+
+    #[test]
+    fn test_computes_once() {
+        let cache = DiffCache::new(4, 4, 2, false);
+        let cell = Cell { x: 1, y: 2 };
+        let piece = Piece::T(cell, Orientation::North);
+
+        assert_eq!(cache.get_or_compute(&cell, 1, &piece, || Ok(3.0)).unwrap(), 3.0);
+        assert_eq!(cache.get_or_compute(&cell, 1, &piece, || panic!("should be cached")).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn test_orientation_shares_entry() {
+        let cache = DiffCache::new(4, 4, 1, true);
+        let cell = Cell { x: 3, y: 3 };
+
+        cache.get_or_compute(&cell, 0, &Piece::S(cell, Orientation::North), || Ok(1.0)).unwrap();
+        let diff = cache.get_or_compute(&cell, 0, &Piece::S(cell, Orientation::West), || Ok(2.0)).unwrap();
+        assert_eq!(diff, 1.0);
+
+        // other blocks and skins must not collide
+        let diff = cache.get_or_compute(&cell, 0, &Piece::Z(cell, Orientation::North), || Ok(2.0)).unwrap();
+        assert_eq!(diff, 2.0);
+        cache.get_or_compute(&cell, 0, &Piece::Gray(cell), || Ok(3.0)).unwrap();
+        let diff = cache.get_or_compute(&cell, 0, &Piece::Shade(cell, 7), || Ok(4.0)).unwrap();
+        assert_eq!(diff, 4.0);
+    }
+
+    #[test]
+    fn test_release_recomputes() {
+        let mut cache = DiffCache::new(4, 4, 1, false);
+        let cell = Cell { x: 0, y: 1 };
+        let piece = Piece::I(cell, Orientation::North);
+
+        cache.get_or_compute(&cell, 0, &piece, || Ok(1.0)).unwrap();
+        cache.release(&[cell]);
+        assert_eq!(cache.allocated_cells(), 0);
+        assert_eq!(cache.get_or_compute(&cell, 0, &piece, || Ok(2.0)).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn test_large_board_stays_bounded() {
+        let (width, height) = (1000, 1000);
+        let mut cache = DiffCache::new(width, height, 10, true);
+
+        // score each cell along with the row below it, like a piece reaching down, then fill it
+        let mut max_allocated = 0;
+        for y in 0..height {
+            for x in 0..width {
+                let cell = Cell { x, y };
+                for c in [cell, Cell { x, y: (y + 1).min(height - 1) }] {
+                    cache.get_or_compute(&c, 9, &Piece::Shade(c, 7), || Ok(1.0)).unwrap();
+                }
+                cache.release(&[cell]);
+            }
+            max_allocated = max_allocated.max(cache.allocated_cells());
+        }
+        assert!(max_allocated <= width, "{max_allocated} cells allocated");
+        assert_eq!(cache.allocated_cells(), 0);
+    }
+}
@@ -1,7 +1,12 @@
-use super::board::{Board, EMPTY_CELL, BLOCKED_CELL};
-use super::piece::{Cell, Piece};
+use super::board::{Board, EMPTY_CELL};
+use super::board_diff::BoardDiff;
+use super::piece::{shade_level, Cell, Piece, MAX_SHADES};
+use super::render::{render, SkinRenderer};
 
-use anyhow::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
 use image::Rgba;
 use imageproc::{image, image::GenericImageView, image::DynamicImage, image::imageops::resize};
 use thiserror::Error;
@@ -14,6 +19,8 @@ pub struct SkinnedBoard<'a> {
     board: Board,
     cells_skin: Vec<usize>,
     skins: &'a Skins,
+    // number of placed pieces of each type, indexed by `Piece::block_index`
+    piece_counts: [usize; 9],
 }
 
 #[derive(Clone)]
@@ -28,16 +35,21 @@ pub struct BlockSkin {
     j_img: BlockImage,
     s_img: BlockImage,
     z_img: BlockImage,
+    // gray garbage shaded towards black, by level; made from the black and gray blocks, so they follow any resize
+    shade_imgs: Vec<BlockImage>,
 
     width: u32,
     height: u32,
     id: usize,
+    name: String,
 }
 
 #[derive(Clone)]
 pub struct BlockImage {
     img: image::DynamicImage,
     avg_pixel: Rgba<u8>,
+    // exact mean color of the current image, unlike `avg_pixel` which is rounded and kept from before any resize
+    mean: [f64; 3],
 }
 
 #[derive(Debug, Error)]
@@ -46,13 +58,26 @@ pub enum ResizeError {
     ZeroDimensions{ skin_width: u32, skin_height: u32 },
 }
 
+#[derive(Debug, Error)]
+pub enum SkinLoadError {
+    #[error("Skins directory {0} could not be read")]
+    MissingDir(PathBuf),
+
+    #[error("No skin pngs found in {0}")]
+    NoSkins(PathBuf),
+
+    #[error("No skins were given")]
+    Empty,
+}
+
 impl<'a> SkinnedBoard<'a> {
     pub fn new(width: usize, height: usize, skins: &'a Skins) -> SkinnedBoard {
         // cells skin must have the same dimensions as board
         SkinnedBoard {
             board: Board::new(width, height),
             cells_skin: vec![INVALID_SKIN_ID; width * height],
-            skins
+            skins,
+            piece_counts: [0; 9],
         }
     }
 
@@ -86,7 +111,7 @@ impl<'a> SkinnedBoard<'a> {
     }
 
     pub fn empty_at(&self, cell: &Cell) -> bool {
-        self.board.get(cell).unwrap_or(BLOCKED_CELL) == EMPTY_CELL
+        self.board.is_empty(cell)
     }
 
     pub fn place(&mut self, piece: &Piece, skin_id: usize) -> Result<()>{
@@ -97,32 +122,149 @@ impl<'a> SkinnedBoard<'a> {
         for cell in piece.get_occupancy()? {
             self.cells_skin[cell.y * board_width + cell.x] = skin_id;
         }
+        self.piece_counts[piece.block_index()] += 1;
+
+        Ok(())
+    }
+
+    pub fn fill_cell(&mut self, cell: &Cell, cell_char: char, skin_id: usize) -> Result<()> {
+        let board_width = self.board_width();
+        self.board.fill_cell(cell, cell_char)?;
+        self.cells_skin[cell.y * board_width + cell.x] = skin_id;
+        Ok(())
+    }
+
+    // copies every piece and filled cell of `other` onto this board, with its top left corner at (`x`, `y`)
+    pub fn paste(&mut self, other: &SkinnedBoard, x: usize, y: usize) -> Result<()> {
+        for piece in other.board.pieces() {
+            let skin_id = other.get_cells_skin(&piece.get_occupancy()?[0]);
+            self.place(&piece.translated(x, y), skin_id)?;
+        }
 
+        // cells filled without a piece, like garbage from the fill styles
+        for cell_y in 0..other.board_height() {
+            for cell_x in 0..other.board_width() {
+                let cell = Cell { x: cell_x, y: cell_y };
+                let cell_char = other.board.get(&cell)?;
+                let target = Cell { x: x + cell_x, y: y + cell_y };
+                if cell_char != EMPTY_CELL && self.empty_at(&target) {
+                    self.fill_cell(&target, cell_char, other.get_cells_skin(&cell))?;
+                }
+            }
+        }
         Ok(())
     }
 
+    pub fn piece_counts(&self) -> [usize; 9] {
+        self.piece_counts
+    }
+
     pub fn get_cells_skin(&self, cell: &Cell) -> usize {
         self.cells_skin[cell.y * self.board_width() + cell.x]
     }
+
+    // the summed pixel diff of every filled cell against `source_img`, without drawing the board
+    // use `BoardDiff` directly to rescore only the cells that change
+    pub fn total_diff(&self, source_img: &DynamicImage) -> Result<f64> {
+        Ok(BoardDiff::new(self, source_img)?.total())
+    }
 }
 
 pub fn resize_skins(skins: &mut Skins, image_width: u32, image_height: u32, board_width: usize, board_height: usize) -> Result<()> {
+    let (skin_width, skin_height) = stretch_skin_size(image_width, image_height, board_width, board_height)?;
+    for skin in skins.iter_mut() {
+        skin.resize(skin_width, skin_height);
+    }
+    Ok(())
+}
+
+// resizes the skins to the largest cells that keep their original aspect ratio and still fit the board inside the image
+// the image is expected to be letterboxed to the board afterwards
+pub fn letterbox_skins(skins: &mut Skins, image_width: u32, image_height: u32, board_width: usize, board_height: usize) -> Result<()> {
+    let (skin_width, skin_height) = letterbox_skin_size(skins, image_width, image_height, board_width, board_height)?;
+    for skin in skins.iter_mut() {
+        skin.resize(skin_width, skin_height);
+    }
+    Ok(())
+}
+
+// the size `resize_skins` would resize the skins to
+pub fn stretch_skin_size(image_width: u32, image_height: u32, board_width: usize, board_height: usize) -> Result<(u32, u32)> {
     let skin_width = image_width / u32::try_from(board_width)?;
     let skin_height = image_height / u32::try_from(board_height)?;
     if skin_width == 0 || skin_height == 0 {
         Err(ResizeError::ZeroDimensions { skin_width, skin_height })?;
     }
-    for skin in skins.iter_mut() {
-        skin.resize(skin_width, skin_height);
+    Ok((skin_width, skin_height))
+}
+
+// the size `letterbox_skins` would resize the skins to
+pub fn letterbox_skin_size(skins: &Skins, image_width: u32, image_height: u32, board_width: usize, board_height: usize) -> Result<(u32, u32)> {
+    let aspect = f64::from(skins[0].width) / f64::from(skins[0].height);
+    let max_width = f64::from(image_width / u32::try_from(board_width)?);
+    let max_height = f64::from(image_height / u32::try_from(board_height)?);
+
+    // cells are limited by whichever image dimension runs out first
+    let skin_height = max_height.min((max_width / aspect).floor());
+    let skin_width = (skin_height * aspect).floor().min(max_width);
+    let (skin_width, skin_height) = (skin_width as u32, skin_height as u32);
+    if skin_width == 0 || skin_height == 0 {
+        Err(ResizeError::ZeroDimensions { skin_width, skin_height })?;
     }
-    Ok(())
+    Ok((skin_width, skin_height))
 }
 
+// standard colors of each block type, in the order the blocks appear in a skin image
+const SECTION_COLORS: [Rgba<u8>; 9] = [
+    Rgba([40, 40, 40, 255]),
+    Rgba([130, 130, 130, 255]),
+    Rgba([215, 15, 55, 255]),
+    Rgba([227, 91, 2, 255]),
+    Rgba([227, 159, 2, 255]),
+    Rgba([89, 177, 1, 255]),
+    Rgba([15, 155, 215, 255]),
+    Rgba([33, 65, 198, 255]),
+    Rgba([175, 41, 138, 255]),
+];
+
 impl BlockSkin {
-    pub fn new(skin_path: &str, id: usize) -> Result<BlockSkin> {
+    // images much wider than they are tall are full skins, while anything else is taken as a single block
+    pub fn new(skin_path: &Path, id: usize) -> Result<BlockSkin> {
+        let img = imageproc::image::open(skin_path)?;
+        let name = skin_path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().to_string());
+        if img.width() < 2 * img.height() {
+            return Ok(BlockSkin::from_block(&img, name, id));
+        }
+        Ok(BlockSkin::from_image(img, name, id))
+    }
+
+    // builds a full skin from a single block texture by tinting its shading with each block type's standard color
+    // mid-gray parts of the texture become the color itself, while darker and lighter parts shade towards black and white
+    pub fn from_block(block: &DynamicImage, name: String, id: usize) -> BlockSkin {
+        let (width, height) = block.dimensions();
+        let shading = block.to_luma8();
+        let strip = image::RgbaImage::from_fn(width * 9, height, |x, y| {
+            let color = SECTION_COLORS[(x / width) as usize];
+            let shade = f64::from(shading.get_pixel(x % width, y)[0]) / 255.0;
+            let tint = |channel: u8| {
+                let channel = f64::from(channel);
+                let tinted = if shade < 0.5 { channel * shade * 2.0 } else { channel + (255.0 - channel) * (shade * 2.0 - 1.0) };
+                tinted.round() as u8
+            };
+            Rgba([tint(color[0]), tint(color[1]), tint(color[2]), 255])
+        });
+        BlockSkin::from_image(DynamicImage::from(strip), name, id)
+    }
+
+    // loads a skin from encoded image bytes, for callers without filesystem access
+    pub fn from_bytes(bytes: &[u8], name: &str, id: usize) -> Result<BlockSkin> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(BlockSkin::from_image(img, name.to_string(), id))
+    }
+
+    pub fn from_image(img: DynamicImage, name: String, id: usize) -> BlockSkin {
         const NUM_SECTIONS: u32 = 9;
 
-        let img = imageproc::image::open(skin_path)?;
         let (width, height) = img.dimensions();
         let section_width = width / NUM_SECTIONS;
         let img_buffer = img.into_rgb8();
@@ -135,7 +277,8 @@ impl BlockSkin {
         }
         
         // return the skin
-        Ok(BlockSkin {
+        BlockSkin {
+            shade_imgs: shades(&new_images[0], &new_images[1]),
             black_img: new_images[0].clone(),
             gray_img: new_images[1].clone(),
             i_img: new_images[6].clone(),
@@ -148,18 +291,19 @@ impl BlockSkin {
             width: section_width,
             height,
             id,
-        })
+            name,
+        }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         for block in self.as_array_ref_mut() {
             block.resize(width, height);
         }
+        self.shade_imgs = shades(&self.black_img, &self.gray_img);
         self.width = width;
         self.height = height;
     }
 
-    #[allow(dead_code)]
     pub fn as_array_ref(&self) -> [&BlockImage; 9] {
         [&self.black_img, &self.gray_img, &self.i_img, &self.o_img, &self.t_img, &self.l_img, &self.j_img, &self.s_img, &self.z_img]
     }
@@ -179,6 +323,7 @@ impl BlockSkin {
             Piece::Z(_, _) => &self.z_img,
             Piece::Gray(_) => &self.gray_img,
             Piece::Black(_) => &self.black_img,
+            Piece::Shade(_, level) => &self.shade_imgs[usize::from(*level) - 1],
         }
     }
 
@@ -192,9 +337,12 @@ impl BlockSkin {
             'S' => &self.s_img,
             'Z' => &self.z_img,
             'G' => &self.gray_img,
-            _ => &self.black_img,
+            _ => match shade_level(cell_val) {
+                Some(level) => &self.shade_imgs[usize::from(level) - 1],
+                None => &self.black_img,
+            },
         }
-        
+
     }
 
     pub fn width(&self) -> u32 {
@@ -208,6 +356,10 @@ impl BlockSkin {
     pub fn id(&self) -> usize {
         self.id
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 }
 
 impl BlockImage {
@@ -224,6 +376,7 @@ impl BlockImage {
             .into();
 
         BlockImage {
+            mean: mean_rgb(&img),
             img,
             avg_pixel,
         }
@@ -232,6 +385,7 @@ impl BlockImage {
     pub fn resize(&mut self, width: u32, height: u32) {
         if self.img.width() != width || self.img.height() != height {
             self.img = DynamicImage::from(resize(&self.img, width, height, image::imageops::FilterType::Lanczos3));
+            self.mean = mean_rgb(&self.img);
         }
     }
 
@@ -245,6 +399,10 @@ impl BlockImage {
         self.img.height()
     }
 
+    pub fn image(&self) -> &DynamicImage {
+        &self.img
+    }
+
     pub fn get_pixel(&self, x: u32, y: u32) -> Rgba<u8> {
         self.img.get_pixel(x, y)
     }
@@ -252,61 +410,210 @@ impl BlockImage {
     pub fn get_average_pixel(&self) -> Rgba<u8> {
         self.avg_pixel
     }
+
+    pub fn mean(&self) -> [f64; 3] {
+        self.mean
+    }
+}
+
+// blends the black block into the gray one in eighths, one image per shade level
+fn shades(black: &BlockImage, gray: &BlockImage) -> Vec<BlockImage> {
+    let (black, gray) = (black.image().to_rgba8(), gray.image().to_rgba8());
+    (1..=MAX_SHADES)
+        .map(|level| {
+            let amount = level as f64 / (MAX_SHADES + 1) as f64;
+            let shade = image::RgbaImage::from_fn(gray.width(), gray.height(), |x, y| {
+                let (b, g) = (black.get_pixel(x, y), gray.get_pixel(x, y));
+                Rgba(std::array::from_fn(|c| (f64::from(b[c]) + (f64::from(g[c]) - f64::from(b[c])) * amount).round() as u8))
+            });
+            BlockImage::new(DynamicImage::from(shade))
+        })
+        .collect()
+}
+
+fn mean_rgb(img: &DynamicImage) -> [f64; 3] {
+    let num_pixels = f64::from(img.width() * img.height());
+    img.pixels()
+        .fold([0.0; 3], |acc, (_x, _y, p)| [acc[0] + f64::from(p[0]), acc[1] + f64::from(p[1]), acc[2] + f64::from(p[2])])
+        .map(|sum| sum / num_pixels)
 }
 
 pub fn draw(skin_board: &SkinnedBoard) -> Result<DynamicImage> {
-    let board = &skin_board.board;
-    let skins = skin_board.skins;
-    let cells_skin = &skin_board.cells_skin;
+    render(skin_board, SkinRenderer::default())
+}
+
+// writes the board as a png one row of blocks at a time, so only that strip is ever in memory
+// the pixels match `draw`, which needs the whole image in memory and can't handle boards this big
+pub fn draw_tiled<W: Write>(skin_board: &SkinnedBoard, writer: W) -> Result<()> {
+    let width = u32::try_from(skin_board.board_width())? * skin_board.skins_width();
+    let height = u32::try_from(skin_board.board_height())? * skin_board.skins_height();
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header()?;
+    let mut stream = png_writer.stream_writer()?;
+
+    for y in 0..skin_board.board_height() {
+        let mut strip = image::RgbaImage::new(width, skin_board.skins_height());
+        for x in 0..skin_board.board_width() {
+            let cell = Cell { x, y };
+            let cell_char = skin_board.board.get(&cell)?;
+            if cell_char != EMPTY_CELL {
+                draw_block(&mut strip, skin_board.get_skin(skin_board.get_cells_skin(&cell)), cell_char, &Cell { x, y: 0 })?;
+            }
+        }
+        stream.write_all(strip.as_raw())?;
+    }
+
+    stream.finish()?;
+    png_writer.finish()?;
+    Ok(())
+}
+
+// draws a single cell of the board onto an image sized for the whole board
+// unfilled cells are left untouched, which lets callers build the image up incrementally
+pub fn draw_cell(img: &mut image::RgbaImage, skin_board: &SkinnedBoard, cell: &Cell) -> Result<()> {
+    let cell_char = skin_board.board.get(cell)?;
+    if cell_char == EMPTY_CELL {
+        return Ok(());
+    }
+    draw_block(img, skin_board.get_skin(skin_board.get_cells_skin(cell)), cell_char, cell)
+}
+
+// redraws just `cells` on an image of the whole board, e.g. a canvas kept across changes to the board
+// each cell is cleared first, so cells that were changed or emptied don't keep any of their old block
+pub fn draw_region(skin_board: &SkinnedBoard, cells: &[Cell], img: &mut image::RgbaImage) -> Result<()> {
+    let (width, height) = (skin_board.skins_width(), skin_board.skins_height());
+    for cell in cells {
+        let (pixel_x, pixel_y) = (u32::try_from(cell.x)? * width, u32::try_from(cell.y)? * height);
+        for y in pixel_y..pixel_y + height {
+            for x in pixel_x..pixel_x + width {
+                img.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+            }
+        }
+        draw_cell(img, skin_board, cell)?;
+    }
+    Ok(())
+}
 
-    let width = u32::try_from(board.width)? * skins[0].width;
-    let height = u32::try_from(board.height)? * skins[0].height;
+// overlays one of the skin's blocks onto the cell's spot in an image sized for the whole board
+pub fn draw_block(img: &mut image::RgbaImage, skin: &BlockSkin, cell_char: char, cell: &Cell) -> Result<()> {
+    let block = skin.block_image_from_char(cell_char);
+    let pixel_x = u32::try_from(cell.x)? * skin.width;
+    let pixel_y = u32::try_from(cell.y)? * skin.height;
+    image::imageops::overlay(img, &block.img, pixel_x.into(), pixel_y.into());
+    Ok(())
+}
+
+// draws every cell covered by a placed piece
+pub fn draw_piece(img: &mut image::RgbaImage, skin_board: &SkinnedBoard, piece: &Piece) -> Result<()> {
+    for cell in piece.get_occupancy()? {
+        draw_cell(img, skin_board, &cell)?;
+    }
+    Ok(())
+}
 
-    let mut img = image::RgbaImage::new(width, height);
+// draws each filled cell as a single pixel of its block's average color, leaving unfilled cells transparent
+pub fn draw_preview(skin_board: &SkinnedBoard) -> Result<image::RgbaImage> {
+    let board = &skin_board.board;
+    let mut img = image::RgbaImage::new(u32::try_from(board.width)?, u32::try_from(board.height)?);
     for y in 0..board.height {
         for x in 0..board.width {
-            let skin_id = cells_skin[y * board.width + x];
-            let skin = skin_board.get_skin(skin_id);
             let cell = board.get(&Cell { x, y })?;
-            let block = match cell {
-                'I' => &skin.i_img,
-                'O' => &skin.o_img,
-                'T' => &skin.t_img,
-                'L' => &skin.l_img,
-                'J' => &skin.j_img,
-                'S' => &skin.s_img,
-                'Z' => &skin.z_img,
-                'G' => &skin.gray_img,
-                'B' => &skin.black_img,
-                _ => panic!("Invalid cell value: {cell}"),
-            };
-            let pixel_x = u32::try_from(x)? * skin.width;
-            let pixel_y = u32::try_from(y)? * skin.height;
-            image::imageops::overlay(&mut img, &block.img, pixel_x.into(), pixel_y.into());
+            if cell == EMPTY_CELL {
+                continue;
+            }
+            let skin = skin_board.get_skin(skin_board.cells_skin[y * board.width + x]);
+            img.put_pixel(u32::try_from(x)?, u32::try_from(y)?, skin.block_image_from_char(cell).get_average_pixel());
         }
     }
-    Ok(DynamicImage::from(img))
+    Ok(img)
+}
+
+// orders skins by file name and renumbers their ids to match
+// this makes skin order independent of directory iteration order, which differs between machines
+pub fn sort_skins(skins: &mut Skins) {
+    skins.sort_by(|a, b| a.name.cmp(&b.name));
+    for (id, skin) in skins.iter_mut().enumerate() {
+        skin.id = id;
+    }
+}
+
+pub fn create_skins() -> Result<Skins> {
+    create_skins_from(Path::new("assets"))
 }
 
-pub fn create_skins() -> Skins {
+// loads every png in `dir` as a skin, skipping other files
+pub fn create_skins_from(dir: &Path) -> Result<Skins> {
     let mut skins = Vec::new();
-    for file in std::fs::read_dir("assets").expect("assets directory not found") {
-        let path = file.expect("failed to read file").path();
-        if path.is_file() && path.extension().expect("no file extension found") == "png" {
-            skins.push(BlockSkin::new(path.to_str().expect("failed to convert path to string"), skins.len()).expect("failed to load skin"));
+    let files = std::fs::read_dir(dir).map_err(|_| SkinLoadError::MissingDir(dir.to_path_buf()))?;
+    for file in files {
+        let path = file.with_context(|| format!("failed to read {}", dir.display()))?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "png") {
+            let skin = BlockSkin::new(&path, skins.len()).with_context(|| format!("failed to load skin {}", path.display()))?;
+            skins.push(skin);
         }
     }
+    if skins.is_empty() {
+        Err(SkinLoadError::NoSkins(dir.to_path_buf()))?;
+    }
 
-    skins
+    Ok(skins)
+}
+
+// the skin most tests draw with, at its original size
+#[cfg(test)]
+pub(crate) fn test_skin() -> BlockSkin {
+    BlockSkin::new(Path::new("test_images/HqGYC5G - Imgur.png"), 0).expect("could not load skin")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::approx_image::piece::Orientation;
+
+    #[test]
+    fn test_sort_skins() {
+        let skin = test_skin();
+        let mut skins = Vec::new();
+        for (id, name) in ["c", "a", "b"].into_iter().enumerate() {
+            skins.push(BlockSkin { id, name: name.to_string(), ..skin.clone() });
+        }
+
+        sort_skins(&mut skins);
+        let sorted: Vec<_> = skins.iter().map(|skin| (skin.id(), skin.name())).collect();
+        assert_eq!(sorted, vec![(0, "a"), (1, "b"), (2, "c")]);
+    }
+
+    #[test]
+    fn test_from_block() {
+        let block = DynamicImage::from(image::GrayImage::from_fn(4, 4, |x, _| image::Luma([if x == 0 { 255 } else { 128 }])));
+        let skin = BlockSkin::from_block(&block, "block".to_string(), 0);
+        assert_eq!((skin.width, skin.height), (4, 4));
+
+        // flat parts take the block type's color and highlights are lighter
+        let i_block = skin.block_image_from_char('I');
+        let flat = i_block.get_pixel(2, 2);
+        assert!(flat[2] > flat[0] && flat[2] > 200);
+        assert!(i_block.get_pixel(0, 2)[0] == 255 && i_block.get_pixel(0, 2)[2] == 255);
+        assert_ne!(skin.block_image_from_char('Z').get_average_pixel(), i_block.get_average_pixel());
+    }
+
+    #[test]
+    fn test_shades_between_black_and_gray() {
+        let skin = test_skin();
+        let black = skin.block_image_from_char('B').get_average_pixel();
+        let gray = skin.block_image_from_char('G').get_average_pixel();
+        let shade = skin.block_image_from_char('4').get_average_pixel();
+        for channel in 0..3 {
+            let (low, high) = (black[channel].min(gray[channel]), black[channel].max(gray[channel]));
+            assert!((low..=high).contains(&shade[channel]));
+        }
+    }
 
     #[test]
     fn test_init() {
-        let skin = BlockSkin::new("test_images/HqGYC5G - Imgur.png", 0).expect("could not load skin");
+        let skin = test_skin();
         assert_eq!(skin.width, 36);
         assert_eq!(skin.height, 36);
 
@@ -316,9 +623,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_create_skins_from_bad_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(create_skins_from(&dir.path().join("missing")).is_err());
+
+        // files that aren't pngs are skipped, so this directory has no skins
+        std::fs::write(dir.path().join("README"), "not a skin").unwrap();
+        let err = create_skins_from(dir.path()).err().unwrap();
+        assert!(matches!(err.downcast_ref::<SkinLoadError>(), Some(SkinLoadError::NoSkins(_))));
+
+        std::fs::write(dir.path().join("broken.png"), "not a png").unwrap();
+        assert!(create_skins_from(dir.path()).is_err());
+    }
+
     #[test]
     fn test_resize_larger() {
-        let mut skin = BlockSkin::new("test_images/HqGYC5G - Imgur.png", 0).expect("could not load skin");
+        let mut skin = test_skin();
         skin.resize(64, 64);
         assert_eq!(skin.width, 64);
         assert_eq!(skin.height, 64);
@@ -331,7 +652,7 @@ mod tests {
 
     #[test]
     fn test_resize_smaller() {
-        let mut skin = BlockSkin::new("test_images/HqGYC5G - Imgur.png", 0).expect("could not load skin");
+        let mut skin = test_skin();
         skin.resize(16, 16);
         assert_eq!(skin.width, 16);
         assert_eq!(skin.height, 16);
@@ -344,7 +665,7 @@ mod tests {
 
     #[test]
     fn test_save_skinned_board() {
-        let mut skin = BlockSkin::new("test_images/HqGYC5G - Imgur.png", 0).expect("could not load skin");
+        let mut skin = test_skin();
         skin.resize(16, 16);
         let skins = vec![skin];
 
@@ -366,4 +687,88 @@ mod tests {
         let image = draw(&board).unwrap();
         image.save("test_results/test_save_skinned_board.png").expect("failed to save image");
     }
+
+    // fnv-1a over the dimensions and raw pixels, which unlike `DefaultHasher` is stable across rust versions
+    fn snapshot_hash(img: &DynamicImage) -> u64 {
+        let img = img.to_rgba8();
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in img.width().to_le_bytes().into_iter().chain(img.height().to_le_bytes()).chain(img.into_raw()) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        hash
+    }
+
+    #[test]
+    fn test_draw_tiled_matches_draw() {
+        let mut skin = BlockSkin::new(Path::new("test_images/synthetic_skin.png"), 0).expect("could not load skin");
+        skin.resize(6, 5);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(7, 4, &skins);
+        board.place(&Piece::I(Cell { x: 1, y: 2 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::T(Cell { x: 4, y: 0 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::Gray(Cell { x: 6, y: 3 }), 0).unwrap();
+
+        let mut png = Vec::new();
+        draw_tiled(&board, &mut png).unwrap();
+        let tiled = image::load_from_memory(&png).unwrap();
+        assert_eq!(tiled.to_rgba8(), draw(&board).unwrap().to_rgba8());
+    }
+
+    #[test]
+    fn test_draw_region_matches_draw() {
+        let mut skin = BlockSkin::new(Path::new("test_images/synthetic_skin.png"), 0).expect("could not load skin");
+        skin.resize(6, 5);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(7, 4, &skins);
+        board.place(&Piece::I(Cell { x: 1, y: 2 }, Orientation::North), 0).unwrap();
+        let mut canvas = draw(&board).unwrap().to_rgba8();
+
+        // only the new piece's cells are redrawn on the kept canvas
+        let piece = Piece::T(Cell { x: 4, y: 0 }, Orientation::North);
+        board.place(&piece, 0).unwrap();
+        draw_region(&board, &piece.get_occupancy().unwrap(), &mut canvas).unwrap();
+        assert_eq!(canvas, draw(&board).unwrap().to_rgba8());
+    }
+
+    #[test]
+    fn test_draw_snapshots() {
+        // the synthetic skin has flat blocks with darker borders and a marker pixel so shifts and flips change the hash
+        // if a change to drawing is intentional, update the hashes from the failure message
+        let snapshots = [
+            ((8, 8), 0xf814_cdc0_7da4_e544),
+            ((16, 16), 0x58b9_baff_41ff_fe37),
+            ((7, 7), 0xf354_8ac2_a323_14a8),
+            ((12, 8), 0x4922_3cd3_6837_c3bf),
+            ((5, 9), 0x13c7_98fb_7955_690b),
+        ];
+
+        let skin = BlockSkin::new(Path::new("test_images/synthetic_skin.png"), 0).expect("could not load skin");
+        let mut failures = Vec::new();
+        for ((width, height), expected) in snapshots {
+            let mut skin = skin.clone();
+            skin.resize(width, height);
+            let skins = vec![skin];
+
+            // every block type, in an order that doesn't repeat along rows or columns
+            let (board_width, board_height) = (5, 3);
+            let mut board = SkinnedBoard::new(board_width, board_height, &skins);
+            for y in 0..board_height {
+                for x in 0..board_width {
+                    let cell_char = ['I', 'O', 'T', 'L', 'J', 'S', 'Z', 'G', 'B'][(x * 2 + y * 3) % 9];
+                    board.fill_cell(&Cell { x, y }, cell_char, 0).expect("failed to fill cell");
+                }
+            }
+
+            let image = draw(&board).unwrap();
+            assert_eq!(image.dimensions(), (width * 5, height * 3));
+            let hash = snapshot_hash(&image);
+            if hash != expected {
+                failures.push(format!("{width}x{height}: {hash:#018x}"));
+            }
+        }
+        assert!(failures.is_empty(), "draw output changed: {failures:?}");
+    }
 }
\ No newline at end of file
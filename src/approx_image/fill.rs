@@ -0,0 +1,225 @@
+use super::{ApproxState, cell_pixel_diff};
+use super::draw::SkinnedBoard;
+use super::piece::{Cell, Orientation, Piece, TETROMINO_CHARS};
+
+use anyhow::Result;
+
+// fills the gaps under floating cells with gray garbage, in the skin of the cell above, so every piece rests on something
+pub fn support_floating(board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    for cell in board.board().floating_cells()? {
+        let skin_id = board.get_cells_skin(&cell);
+        let mut below = Cell { x: cell.x, y: cell.y + 1 };
+        while below.y < board.board_height() && board.empty_at(&below) {
+            board.fill_cell(&below, 'G', skin_id)?;
+            state.record_filled(board, &[below])?;
+            below.y += 1;
+        }
+    }
+    Ok(())
+}
+
+// fills the masked out cells with whichever garbage block best matches the source underneath, before any pieces are placed
+pub fn fill_masked(board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    for y in 0..board.board_height() {
+        for x in 0..board.board_width() {
+            let cell = Cell { x, y };
+            if !state.masked(&cell) {
+                continue;
+            }
+
+            let mut best: Option<(Piece, usize)> = None;
+            let mut best_diff = f64::MAX;
+            for skin in board.iter_skins() {
+                if !state.skin_allowed(&cell, skin.id(), board.board_width()) {
+                    continue;
+                }
+                for piece in Piece::all_garbage(cell, state.config.garbage_levels) {
+                    let block_image = skin.block_image_from_piece(&piece);
+                    let diff = state.cache.get_or_compute(&cell, skin.id(), &piece, || cell_pixel_diff(&cell, skin, block_image, state.source_img, state.detail.as_ref()))?;
+                    if diff < best_diff {
+                        best_diff = diff;
+                        best = Some((piece, skin.id()));
+                    }
+                }
+            }
+
+            if let Some((piece, skin_id)) = best {
+                board.place(&piece, skin_id)?;
+                state.record_filled(board, &[cell])?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// fills every empty cell with the single tetromino mino whose block best matches the source underneath
+pub fn fill_nearest_mino(board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    for cell in empty_cells(board, state) {
+        if state.config.cancel.is_cancelled() {
+            break;
+        }
+        fill_with_nearest_mino(&cell, board, state)?;
+    }
+    Ok(())
+}
+
+// fills every empty cell with part of a tetromino that may overlap the pieces already placed, without any garbage
+// of the tetrominoes covering a cell, the one over the most empty cells wins, then the one closest to the source;
+// only its blocks over empty cells are filled, so the placed pieces stay whole
+pub fn fill_overlapping(board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    for cell in empty_cells(board, state) {
+        if state.config.cancel.is_cancelled() {
+            break;
+        }
+        // an earlier overlapping piece may have covered this cell already
+        if !board.empty_at(&cell) {
+            continue;
+        }
+
+        let mut best: Option<(Vec<Cell>, char, usize)> = None;
+        let mut best_score = (0, f64::MAX);
+        for skin in board.iter_skins() {
+            if !state.skin_allowed(&cell, skin.id(), board.board_width()) {
+                continue;
+            }
+            for piece in covering_pieces(&cell) {
+                if state.config.banned_pieces.contains(&piece.get_char()) {
+                    continue;
+                }
+                let Ok(occupancy) = piece.get_occupancy() else {
+                    continue;
+                };
+                if occupancy.iter().any(|c| board.board().get(c).is_err() || state.masked(c)) {
+                    continue;
+                }
+
+                let uncovered: Vec<Cell> = occupancy.into_iter().filter(|c| board.empty_at(c)).collect();
+                let block_image = skin.block_image_from_piece(&piece);
+                let mut diff = 0.0;
+                for c in &uncovered {
+                    diff += state.cache.get_or_compute(c, skin.id(), &piece, || cell_pixel_diff(c, skin, block_image, state.source_img, state.detail.as_ref()))?;
+                }
+                let diff = diff / uncovered.len() as f64;
+
+                if uncovered.len() > best_score.0 || (uncovered.len() == best_score.0 && diff < best_score.1) {
+                    best_score = (uncovered.len(), diff);
+                    best = Some((uncovered, piece.get_char(), skin.id()));
+                }
+            }
+        }
+
+        if let Some((cells, cell_char, skin_id)) = best {
+            for c in &cells {
+                board.fill_cell(c, cell_char, skin_id)?;
+            }
+            state.record_filled(board, &cells)?;
+        }
+    }
+    Ok(())
+}
+
+// every tetromino in every orientation that occupies the cell
+fn covering_pieces(cell: &Cell) -> Vec<Piece> {
+    // no piece reaches further than 3 cells from where it's anchored
+    let mut pieces = Vec::new();
+    for y in cell.y.saturating_sub(3)..=cell.y + 3 {
+        for x in cell.x.saturating_sub(3)..=cell.x + 3 {
+            for orientation in Orientation::all() {
+                pieces.extend(Piece::all_normal(Cell { x, y }, orientation).into_iter().filter(|piece| {
+                    piece.get_occupancy().is_ok_and(|occupancy| occupancy.contains(cell))
+                }));
+            }
+        }
+    }
+    pieces
+}
+
+// fills every empty cell with the most common tetromino block among its filled neighbors
+// cells without any tetromino neighbors fall back to the nearest mino
+pub fn fill_dominant_neighbor(board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    for cell in empty_cells(board, state) {
+        if state.config.cancel.is_cancelled() {
+            break;
+        }
+        match dominant_neighbor(&cell, board) {
+            Some((cell_char, skin_id)) => {
+                board.fill_cell(&cell, cell_char, skin_id)?;
+                state.record_filled(board, &[cell])?;
+            }
+            None => fill_with_nearest_mino(&cell, board, state)?,
+        }
+    }
+    Ok(())
+}
+
+fn fill_with_nearest_mino(cell: &Cell, board: &mut SkinnedBoard, state: &mut ApproxState) -> Result<()> {
+    let mut best: Option<(char, usize)> = None;
+    let mut best_diff = f64::MAX;
+    for skin in board.iter_skins() {
+        if !state.skin_allowed(cell, skin.id(), board.board_width()) {
+            continue;
+        }
+
+        // the orientation doesn't matter since only the block type is used
+        for piece in Piece::all_normal(*cell, Orientation::North) {
+            if state.config.banned_pieces.contains(&piece.get_char()) {
+                continue;
+            }
+            let block_image = skin.block_image_from_piece(&piece);
+            let diff = state.cache.get_or_compute(cell, skin.id(), &piece, || cell_pixel_diff(cell, skin, block_image, state.source_img, state.detail.as_ref()))?;
+            if diff < best_diff {
+                best_diff = diff;
+                best = Some((piece.get_char(), skin.id()));
+            }
+        }
+    }
+
+    if let Some((cell_char, skin_id)) = best {
+        board.fill_cell(cell, cell_char, skin_id)?;
+        state.record_filled(board, &[*cell])?;
+    }
+    Ok(())
+}
+
+fn dominant_neighbor(cell: &Cell, board: &SkinnedBoard) -> Option<(char, usize)> {
+    // counts are kept in a vec so ties are broken by neighbor order instead of hash order
+    let mut counts: Vec<((char, usize), usize)> = Vec::new();
+    for (dx, dy) in [(0, 1), (-1, 0), (1, 0), (0, -1)] {
+        let (Some(x), Some(y)) = (cell.x.checked_add_signed(dx), cell.y.checked_add_signed(dy)) else {
+            continue;
+        };
+        let neighbor = Cell { x, y };
+        let Ok(neighbor_char) = board.board().get(&neighbor) else {
+            continue;
+        };
+        if !TETROMINO_CHARS.contains(&neighbor_char) {
+            continue;
+        }
+
+        let key = (neighbor_char, board.get_cells_skin(&neighbor));
+        match counts.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((key, 1)),
+        }
+    }
+
+    counts
+        .into_iter()
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map(|(key, _)| key)
+}
+
+// empty cells that aren't masked out
+fn empty_cells(board: &SkinnedBoard, state: &ApproxState) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    for y in (0..board.board_height()).rev() {
+        for x in 0..board.board_width() {
+            let cell = Cell { x, y };
+            if board.empty_at(&cell) && !state.masked(&cell) {
+                cells.push(cell);
+            }
+        }
+    }
+    cells
+}
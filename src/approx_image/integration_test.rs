@@ -1,75 +1,149 @@
-use super::{Config, GlobalData, draw::resize_skins, resize_image};
+use super::{Config, GlobalData, fit_image, fit_small_image};
+use crate::utils::{CancelledError, PartialFailure};
 
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time;
 
-use anyhow::Result;
+use anyhow::{ensure, Result};
 use image::GenericImageView;
 use imageproc::image::DynamicImage;
 use dssim::Dssim;
 use rayon::prelude::*;
+use thiserror::Error;
+use tracing::{info, info_span, warn};
 
-// tests all image in the directory
+// directory the integration test reads when none is given
+pub const DEFAULT_DIR: &str = "sources";
+
+#[derive(Debug, Error)]
+pub enum IntegrationError {
+    #[error("Average dssim {average:.6} is above the allowed maximum of {max:.6}")]
+    Regression { average: f64, max: f64 },
+}
+
+// tests all image in the directory, failing if the average dssim is above `max_avg_dssim`
+// each image's scores are written to `csv_path` if given
 #[allow(clippy::cast_precision_loss)]
-pub fn run(dir: &str, config: &Config, glob: &GlobalData) -> Result<()> {
-    println!("Running integration test on {dir}");
+pub fn run(dir: &Path, max_avg_dssim: Option<f64>, csv_path: Option<&Path>, config: &Config, glob: &GlobalData) -> Result<()> {
+    let _span = info_span!("integration_test", dir = %dir.display()).entered();
+    info!("running integration test");
 
     let start = time::Instant::now();
-    let num_files = fs::read_dir(dir)?.count();
-    let images: Vec<_> = fs::read_dir(dir)?
+    let mut images: Vec<PathBuf> = fs::read_dir(dir)?
         .filter_map(std::result::Result::ok)
+        .map(|entry| entry.path())
         .collect();
+    // sorted so the csv rows come out in the same order every run
+    images.sort();
+    let num_files = images.len();
 
-    println!("Approximating {num_files} images");
+    info!(num_files, "approximating images");
 
-    let total_diff: f64 = images
+    // a bad image is reported and skipped so the rest can still be scored
+    // images that were cut short by a cancel are left out, since their scores would be of a partial board
+    let scores: Vec<(&PathBuf, ImageScore)> = images
         .par_iter()
-        .map(|image| {
-            score_image(&image.path(), config, glob).expect("failed to score image")
+        .filter_map(|image| match score_image(image, config, glob) {
+            Ok(_) if config.cancel.is_cancelled() => None,
+            Ok(score) => Some((image, score)),
+            Err(e) => {
+                warn!(image = %image.display(), "failed to score image: {e:#}");
+                None
+            }
         })
-        .sum();
+        .collect();
 
-    assert_ne!(num_files, 0, "No images found in directory");
+    ensure!(num_files != 0, "no images found in directory");
+
+    if let Some(csv_path) = csv_path {
+        save_scores(&scores, csv_path)?;
+        info!(path = %csv_path.display(), "saved image scores");
+    }
+    if config.cancel.is_cancelled() {
+        warn!(scored = scores.len(), num_files, "cancelled integration test");
+        return Err(CancelledError.into());
+    }
+
+    let total_diff: f64 = scores.iter().map(|(_, score)| score.dssim).sum();
+    let average_diff = total_diff / (scores.len().max(1) as f64);
+    info!(
+        num_files,
+        total_diff,
+        average_diff,
+        elapsed = ?start.elapsed(),
+        "integration test finished"
+    );
+
+    if let Some(max) = max_avg_dssim {
+        if average_diff > max {
+            return Err(IntegrationError::Regression { average: average_diff, max }.into());
+        }
+    }
+
+    let failed = num_files - scores.len();
+    if failed != 0 {
+        return Err(PartialFailure { failed, total: num_files, items: "images" }.into());
+    }
+    Ok(())
+}
 
-    println!("Number of images={num_files}");
-    println!("Total Dssim diff={total_diff}");
-    println!("Average Dssim diff={}", total_diff / (num_files as f64));
-    println!("Time Elapsed: {:?}", start.elapsed());
+fn save_scores(scores: &[(&PathBuf, ImageScore)], path: &Path) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["image", "dssim", "similarity"])?;
+    for (image, score) in scores {
+        writer.write_record([image.display().to_string(), score.dssim.to_string(), score.similarity.to_string()])?;
+    }
+    writer.flush()?;
     Ok(())
 }
 
-fn score_image(path: &Path, old_config: &Config, glob: &GlobalData) -> Result<f64> {
-    let mut total_diff = 0.0;
-    let mut source_img = image::open(path)?;
+// approximates the image at the configured board width, with the height following the image's aspect ratio
+pub fn score_image(path: &Path, old_config: &Config, glob: &GlobalData) -> Result<ImageScore> {
+    let mut source_img = super::load::open(path)?;
     
     // set the board height to scale to the image
     let board_height = source_img.width() * u32::try_from(old_config.board_width)? / source_img.height();
-    let config = Config {
+    let mut config = Config {
         board_width: old_config.board_width,
         board_height: board_height as usize,
-        ..*old_config
+        ..old_config.clone()
     };
 
-    // create a new glob for the local approximation since each image can contain different sizes
-    // this means the block skin sizes should be tailored to the image
-    let mut glob = glob.clone();
-
-    // resize the source image and skins as necessary
+    // each image can need a different skin size, so the skins are tailored to the image
+    // images that need the same size share one resized copy
+    fit_small_image(&mut source_img, &mut config)?;
     let (image_width, image_height) = source_img.dimensions();
-    resize_skins(&mut glob.skins, image_width, image_height, config.board_width, config.board_height)?;
-    resize_image(&mut source_img, glob.skin_width(), glob.skin_height(), config.board_width, config.board_height);
+    let glob = glob.fitted(image_width, image_height, &config)?;
+    fit_image(&mut source_img, glob.skin_width(), glob.skin_height(), &config);
 
     // handle scoring
     let approx_img = super::approx(&source_img, &config, &glob)?;
-    let dssim_diff = diff_images_dssim(&approx_img, &source_img);
-    total_diff += dssim_diff;
-    println!("Diff: {dssim_diff}, Source: {path:?}");
+    let score = score_images(&approx_img, &source_img)?;
+    info!(diff = score.dssim, source = %path.display(), "scored image");
 
-    Ok(total_diff)
+    Ok(score)
 }
 
-fn diff_images_dssim(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
+// how close an approximation is to its source
+#[derive(Clone, Copy, Debug, serde::Serialize)]
+pub struct ImageScore {
+    // 0 means identical, and larger is worse
+    pub dssim: f64,
+    // image-compare's rgb hybrid similarity, where 1 means identical
+    pub similarity: f64,
+}
+
+// scores an approximation against its source, which must have the same dimensions
+pub fn score_images(approx_img: &DynamicImage, source_img: &DynamicImage) -> Result<ImageScore> {
+    let similarity = image_compare::rgb_hybrid_compare(&approx_img.to_rgb8(), &source_img.to_rgb8())?.score;
+    Ok(ImageScore {
+        dssim: diff_images_dssim(approx_img, source_img),
+        similarity,
+    })
+}
+
+pub fn diff_images_dssim(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
     let d = Dssim::new();
 
     let image1_buffer = image1.to_rgb8();
@@ -83,4 +157,30 @@ fn diff_images_dssim(image1: &DynamicImage, image2: &DynamicImage) -> f64 {
 
     let (diff, _) = d.compare(&d_image1, &d_image2);
     diff.into()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_glob;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_threshold_and_csv() {
+        let dir = Path::new("test_results/integration_sources");
+        fs::create_dir_all(dir).unwrap();
+        let source_img = RgbaImage::from_fn(16, 16, |x, y| Rgba([(x * 16) as u8, (y * 16) as u8, 90, 255]));
+        source_img.save(dir.join("gradient.png")).unwrap();
+
+        let glob = test_glob(2);
+        let config = Config { board_width: 8, ..Config::default() };
+
+        let csv_path = Path::new("test_results/integration_scores.csv");
+        assert!(run(dir, Some(f64::MAX), Some(csv_path), &config, &glob).is_ok());
+        let csv = fs::read_to_string(csv_path).unwrap();
+        assert!(csv.starts_with("image,dssim,similarity\n"));
+        assert!(csv.contains("gradient.png"));
+
+        let err = run(dir, Some(-1.0), None, &config, &glob).unwrap_err();
+        assert!(err.is::<IntegrationError>());
+    }
+}
@@ -0,0 +1,105 @@
+use super::board::EMPTY_CELL;
+use super::draw::SkinnedBoard;
+use super::piece::{shade_level, Cell};
+
+use std::fmt::Write;
+use std::io::Cursor;
+
+use anyhow::Result;
+use base64::Engine;
+use image::ImageFormat;
+
+// same order as `BlockSkin::as_array_ref`
+const BLOCK_CHARS: [char; 9] = ['B', 'G', 'I', 'O', 'T', 'L', 'J', 'S', 'Z'];
+const BLOCK_NAMES: [&str; 9] = ["Black", "Gray", "I", "O", "T", "L", "J", "S", "Z"];
+
+const BLOCK_SIZE: usize = 32;
+const COLUMN_WIDTH: usize = 112;
+const ROW_HEIGHT: usize = 72;
+const MARGIN: usize = 8;
+
+// renders an svg listing every skin used by the board, with each block and how many minos use it
+pub fn legend_svg(board: &SkinnedBoard) -> Result<String> {
+    let counts = count_blocks(board)?;
+    let used_skins: Vec<_> = board
+        .iter_skins()
+        .filter(|skin| counts[skin.id()].iter().any(|count| *count != 0))
+        .collect();
+
+    let width = MARGIN * 2 + COLUMN_WIDTH * BLOCK_CHARS.len();
+    let height = MARGIN * 2 + ROW_HEIGHT * used_skins.len();
+    let mut svg = String::new();
+    writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#)?;
+    writeln!(svg, r"<style>text {{ font-family: monospace; font-size: 12px; }}</style>")?;
+
+    for (row, skin) in used_skins.iter().enumerate() {
+        let y = MARGIN + row * ROW_HEIGHT;
+        writeln!(svg, r#"<text x="{MARGIN}" y="{}">skin {}: {}</text>"#, y + 12, skin.id(), escape(skin.name()))?;
+
+        for (column, block) in skin.as_array_ref().iter().enumerate() {
+            let x = MARGIN + column * COLUMN_WIDTH;
+            let block_y = y + 18;
+            writeln!(svg, r#"<image x="{x}" y="{block_y}" width="{BLOCK_SIZE}" height="{BLOCK_SIZE}" href="data:image/png;base64,{}"/>"#, png_base64(block.image())?)?;
+            writeln!(svg, r#"<text x="{}" y="{}">{} x{}</text>"#, x + BLOCK_SIZE + 4, block_y + 20, BLOCK_NAMES[column], counts[skin.id()][column])?;
+        }
+    }
+
+    writeln!(svg, "</svg>")?;
+    Ok(svg)
+}
+
+// number of minos using each block of each skin, indexed by skin id and then block
+fn count_blocks(board: &SkinnedBoard) -> Result<Vec<[usize; 9]>> {
+    let mut counts = vec![[0; 9]; board.iter_skins().len()];
+    for y in 0..board.board_height() {
+        for x in 0..board.board_width() {
+            let cell = Cell { x, y };
+            let cell_char = board.board().get(&cell)?;
+            if cell_char == EMPTY_CELL {
+                continue;
+            }
+            // shades are listed with the gray they're made from
+            let cell_char = if shade_level(cell_char).is_some() { 'G' } else { cell_char };
+            if let Some(block) = BLOCK_CHARS.iter().position(|c| *c == cell_char) {
+                counts[board.get_cells_skin(&cell)][block] += 1;
+            }
+        }
+    }
+    Ok(counts)
+}
+
+pub(super) fn png_base64(img: &image::DynamicImage) -> Result<String> {
+    let mut bytes = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::test_skin;
+    use crate::approx_image::piece::{Orientation, Piece};
+
+    #[test]
+    fn test_legend_counts() {
+        let mut skin = test_skin();
+        skin.resize(4, 4);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(4, 4, &skins);
+        board.place(&Piece::I(Cell { x: 0, y: 0 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::Gray(Cell { x: 0, y: 1 }), 0).unwrap();
+
+        let counts = count_blocks(&board).unwrap();
+        assert_eq!(counts[0], [0, 1, 4, 0, 0, 0, 0, 0, 0]);
+
+        let svg = legend_svg(&board).unwrap();
+        assert!(svg.contains("skin 0: HqGYC5G - Imgur"));
+        assert!(svg.contains("I x4"));
+        assert!(svg.contains("Gray x1"));
+    }
+}
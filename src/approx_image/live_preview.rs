@@ -0,0 +1,189 @@
+use std::fmt;
+
+use anyhow::Result;
+use image::RgbaImage;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LivePreviewError {
+    #[error("live previews are not supported by this build; rebuild with `--features preview`")]
+    MissingFeature,
+
+    #[error("live previews are not supported on macOS, where windows can only be opened from the main thread")]
+    UnsupportedPlatform,
+}
+
+// a window showing the board while it's being approximated, and each video frame once it's done
+// the window lives on its own thread and only ever shows the latest image it was sent, so slow redraws never hold up the search
+// macOS only allows windows on the main thread, which the search runs on, so `--preview` is refused there
+// clones share the same window, which closes once every clone is dropped
+#[derive(Clone)]
+pub struct LivePreview {
+    #[cfg(feature = "preview")]
+    inner: std::sync::Arc<window::Shared>,
+}
+
+impl fmt::Debug for LivePreview {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("LivePreview")
+    }
+}
+
+#[cfg(feature = "preview")]
+impl LivePreview {
+    // fails if the window can't be opened, e.g. without a display
+    pub fn open() -> Result<LivePreview> {
+        if cfg!(target_os = "macos") {
+            Err(LivePreviewError::UnsupportedPlatform)?;
+        }
+        Ok(LivePreview { inner: window::spawn()? })
+    }
+
+    // shows the image drawn by `draw`, unless the window was updated too recently or has been closed
+    pub fn update(&self, draw: impl FnOnce() -> Result<RgbaImage>) -> Result<()> {
+        if self.inner.wants_frame() {
+            self.inner.show(draw()?);
+        }
+        Ok(())
+    }
+
+    // shows the image no matter how recently the window was updated
+    pub fn show(&self, img: RgbaImage) {
+        self.inner.show(img);
+    }
+}
+
+#[cfg(not(feature = "preview"))]
+impl LivePreview {
+    pub fn open() -> Result<LivePreview> {
+        Err(LivePreviewError::MissingFeature)?
+    }
+
+    pub fn update(&self, _draw: impl FnOnce() -> Result<RgbaImage>) -> Result<()> {
+        Ok(())
+    }
+
+    pub fn show(&self, _img: RgbaImage) {}
+}
+
+#[cfg(feature = "preview")]
+mod window {
+    use std::sync::{mpsc, Arc, Mutex, Weak};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use anyhow::{anyhow, Context, Result};
+    use image::RgbaImage;
+    use minifb::{ScaleMode, Window, WindowOptions};
+    use tracing::warn;
+
+    // how often the window is redrawn, and how often new images are drawn for it
+    const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+    // the window opens at this size, and images are scaled to fit it without stretching
+    const WINDOW_SIZE: usize = 800;
+
+    pub struct Shared {
+        state: Mutex<State>,
+    }
+
+    struct State {
+        latest: Option<RgbaImage>,
+        last_shown: Option<Instant>,
+        closed: bool,
+    }
+
+    impl Shared {
+        pub fn wants_frame(&self) -> bool {
+            let state = self.state.lock().expect("live preview lock poisoned");
+            !state.closed && state.last_shown.is_none_or(|shown| shown.elapsed() >= FRAME_INTERVAL)
+        }
+
+        pub fn show(&self, img: RgbaImage) {
+            let mut state = self.state.lock().expect("live preview lock poisoned");
+            if !state.closed {
+                state.latest = Some(img);
+                state.last_shown = Some(Instant::now());
+            }
+        }
+
+        fn take(&self) -> Option<RgbaImage> {
+            self.state.lock().expect("live preview lock poisoned").latest.take()
+        }
+
+        fn close(&self) {
+            self.state.lock().expect("live preview lock poisoned").closed = true;
+        }
+    }
+
+    pub fn spawn() -> Result<Arc<Shared>> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State { latest: None, last_shown: None, closed: false }),
+        });
+        let weak = Arc::downgrade(&shared);
+
+        // the window has to be created on the thread that draws it, which reports back whether that worked
+        let (opened_tx, opened_rx) = mpsc::channel();
+        thread::Builder::new().name("live-preview".to_string()).spawn(move || {
+            let window = match open() {
+                Ok(window) => {
+                    let _ = opened_tx.send(Ok(()));
+                    window
+                }
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                    return;
+                }
+            };
+            if let Err(e) = run(window, &weak) {
+                warn!("closed the live preview: {e:#}");
+            }
+            if let Some(shared) = weak.upgrade() {
+                shared.close();
+            }
+        })?;
+        opened_rx
+            .recv()
+            .map_err(|_| anyhow!("the live preview thread stopped before opening its window"))?
+            .context("could not open the live preview window")?;
+        Ok(shared)
+    }
+
+    fn run(mut window: Window, weak: &Weak<Shared>) -> Result<()> {
+        let mut buffer = (Vec::new(), 0, 0);
+        loop {
+            // every handle was dropped, so the run is over
+            let Some(shared) = weak.upgrade() else {
+                return Ok(());
+            };
+            if let Some(img) = shared.take() {
+                buffer = (to_buffer(&img), img.width() as usize, img.height() as usize);
+            }
+            drop(shared);
+
+            if !window.is_open() {
+                return Ok(());
+            }
+            if buffer.0.is_empty() {
+                window.update();
+            } else {
+                window.update_with_buffer(&buffer.0, buffer.1, buffer.2)?;
+            }
+            thread::sleep(FRAME_INTERVAL);
+        }
+    }
+
+    fn open() -> Result<Window> {
+        let options = WindowOptions { resize: true, scale_mode: ScaleMode::AspectRatioStretch, ..WindowOptions::default() };
+        Ok(Window::new("image-to-tetris", WINDOW_SIZE, WINDOW_SIZE, options)?)
+    }
+
+    // minifb takes 0RGB pixels, so transparent cells are blended onto black
+    fn to_buffer(img: &RgbaImage) -> Vec<u32> {
+        img.pixels()
+            .map(|p| {
+                let blend = |c: u8| u32::from(c) * u32::from(p[3]) / 255;
+                (blend(p[0]) << 16) | (blend(p[1]) << 8) | blend(p[2])
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,129 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat, ImageReader};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error("{0} images are not supported by this build; rebuild with `--features {1}`")]
+    MissingFeature(&'static str, &'static str),
+
+    #[error("Path {0} is not valid UTF-8")]
+    NonUtf8Path(PathBuf),
+
+    #[error("Heic image {0} could not be decoded as rgba")]
+    MalformedHeic(PathBuf),
+}
+
+// a source or output of `-` is read from stdin or written to stdout, for shell pipelines
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+// opens a source image, detecting its format from its contents rather than trusting the extension
+// webp is always supported, while avif and heic need the `avif` and `heic` features since they link native decoders
+pub fn open(path: &Path) -> Result<DynamicImage> {
+    if is_stdio(path) {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        return decode(ImageReader::new(Cursor::new(bytes)).with_guessed_format()?);
+    }
+
+    let reader = ImageReader::open(path)?.with_guessed_format()?;
+    if reader.format().is_none() && is_heic(path) {
+        return open_heic(path);
+    }
+    decode(reader)
+}
+
+// saves an image with the format from the path's extension, or as png to stdout
+pub fn save(img: &DynamicImage, path: &Path) -> Result<()> {
+    if !is_stdio(path) {
+        return Ok(img.save(path)?);
+    }
+
+    let mut png = Vec::new();
+    img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&png)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// opens the path to be written to, or stdout for `-`
+pub fn create(path: &Path) -> Result<Box<dyn Write>> {
+    if is_stdio(path) {
+        return Ok(Box::new(io::stdout().lock()));
+    }
+    Ok(Box::new(BufWriter::new(File::create(path)?)))
+}
+
+// writes text output, like an svg, to the path or to stdout
+pub fn save_text(text: &str, path: &Path) -> Result<()> {
+    if !is_stdio(path) {
+        return Ok(std::fs::write(path, text)?);
+    }
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn decode<R: BufRead + Seek>(reader: ImageReader<R>) -> Result<DynamicImage> {
+    match reader.format() {
+        Some(ImageFormat::Avif) if !cfg!(feature = "avif") => Err(LoadError::MissingFeature("avif", "avif"))?,
+        _ => Ok(reader.decode()?),
+    }
+}
+
+fn is_heic(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("heic") || ext.eq_ignore_ascii_case("heif"))
+}
+
+#[cfg(feature = "heic")]
+fn open_heic(path: &Path) -> Result<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let path_str = path.to_str().ok_or_else(|| LoadError::NonUtf8Path(path.to_path_buf()))?;
+    let ctx = HeifContext::read_from_file(path_str)?;
+    let handle = ctx.primary_image_handle()?;
+    let decoded = LibHeif::new().decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)?;
+    let malformed = || LoadError::MalformedHeic(path.to_path_buf());
+    let plane = decoded.planes().interleaved.ok_or_else(malformed)?;
+
+    // rows may be padded, so copy them out one at a time
+    let row_len = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(row.get(..row_len).ok_or_else(malformed)?);
+    }
+    let img = image::RgbaImage::from_raw(plane.width, plane.height, pixels).ok_or_else(malformed)?;
+    Ok(DynamicImage::from(img))
+}
+
+#[cfg(not(feature = "heic"))]
+fn open_heic(_path: &Path) -> Result<DynamicImage> {
+    Err(LoadError::MissingFeature("heic", "heic"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_ignores_extension() {
+        // a webp saved with the wrong extension, as phones and chat apps often do
+        let img = DynamicImage::from(image::RgbaImage::from_pixel(4, 3, image::Rgba([10, 200, 30, 255])));
+        let path = Path::new("test_results/test_open_ignores_extension.png");
+        img.save_with_format(path, ImageFormat::WebP).unwrap();
+
+        let loaded = open(path).unwrap();
+        assert_eq!(loaded.to_rgba8(), img.to_rgba8());
+    }
+}
@@ -0,0 +1,58 @@
+use super::load;
+
+use std::path::Path;
+
+use anyhow::Result;
+use image::imageops::FilterType;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MaskFill {
+    // masked cells are filled with whichever of black or gray garbage is closer to the source
+    #[default]
+    Garbage,
+    // masked cells are left empty and transparent
+    Empty,
+}
+
+// which cells of the board are approximated, from the white parts of a mask image
+// the mask is scaled to the board, so it can be any size as long as it has the source's proportions
+pub struct Mask {
+    width: usize,
+    cells: Vec<bool>,
+}
+
+impl Mask {
+    pub fn load(path: &Path, board_width: usize, board_height: usize) -> Result<Mask> {
+        let mask_img = load::open(path)?;
+        let cells = mask_img
+            .resize_exact(u32::try_from(board_width)?, u32::try_from(board_height)?, FilterType::Triangle)
+            .to_luma8()
+            .pixels()
+            .map(|p| p[0] >= 128)
+            .collect();
+        Ok(Mask { width: board_width, cells })
+    }
+
+    // whether the cell is outside of the approximated region
+    pub fn masked(&self, x: usize, y: usize) -> bool {
+        !self.cells[y * self.width + x]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn test_mask_scales_to_board() {
+        // only the right half is white
+        let mask_img = GrayImage::from_fn(40, 20, |x, _| Luma([if x < 20 { 0 } else { 255 }]));
+        let path = Path::new("test_results/test_mask_scales_to_board.png");
+        mask_img.save(path).unwrap();
+
+        let mask = Mask::load(path, 4, 2).unwrap();
+        assert!(mask.masked(0, 0) && mask.masked(1, 1));
+        assert!(!mask.masked(2, 0) && !mask.masked(3, 1));
+    }
+}
@@ -0,0 +1,108 @@
+use super::draw::{self, SkinnedBoard};
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::GenericImageView;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SplitError {
+    #[error("Invalid split, expected COLUMNSxROWS like 3x1: {0}")]
+    InvalidSplit(String),
+
+    #[error("Can't split a {0}x{1} board into {2}x{3} panels; every panel needs at least one block")]
+    TooManyPanels(usize, usize, usize, usize),
+}
+
+// parses a split like `3x1` into columns and rows of panels
+pub fn parse_split(s: &str) -> Result<(usize, usize), SplitError> {
+    let invalid = || SplitError::InvalidSplit(s.to_string());
+    let (columns, rows) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+    let columns: usize = columns.parse().map_err(|_| invalid())?;
+    let rows: usize = rows.parse().map_err(|_| invalid())?;
+    if columns == 0 || rows == 0 {
+        return Err(invalid());
+    }
+    Ok((columns, rows))
+}
+
+// draws the board and saves it as columns x rows panels cut along block edges, named `<output stem>_<row>_<column>`
+// panels differ in size by at most one block when the board doesn't divide evenly
+pub fn save_panels(board: &SkinnedBoard, output: &Path, (columns, rows): (usize, usize)) -> Result<Vec<PathBuf>> {
+    let (board_width, board_height) = (board.board_width(), board.board_height());
+    if columns > board_width || rows > board_height {
+        return Err(SplitError::TooManyPanels(board_width, board_height, columns, rows).into());
+    }
+
+    let img = draw::draw(board)?;
+    let mut paths = Vec::new();
+    for row in 0..rows {
+        let (top, bottom) = (row * board_height / rows, (row + 1) * board_height / rows);
+        for column in 0..columns {
+            let (left, right) = (column * board_width / columns, (column + 1) * board_width / columns);
+            let panel = img.view(
+                u32::try_from(left)? * board.skins_width(),
+                u32::try_from(top)? * board.skins_height(),
+                u32::try_from(right - left)? * board.skins_width(),
+                u32::try_from(bottom - top)? * board.skins_height(),
+            );
+            let path = panel_path(output, row, column);
+            panel.to_image().save(&path)?;
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+fn panel_path(output: &Path, row: usize, column: usize) -> PathBuf {
+    let stem = output.file_stem().map_or_else(|| "panel".into(), |stem| stem.to_string_lossy());
+    let name = match output.extension() {
+        Some(ext) => format!("{stem}_{row}_{column}.{}", ext.to_string_lossy()),
+        None => format!("{stem}_{row}_{column}"),
+    };
+    output.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::BlockSkin;
+    use crate::approx_image::piece::Cell;
+
+    #[test]
+    fn test_parse_split() {
+        assert_eq!(parse_split("3x1").unwrap(), (3, 1));
+        assert_eq!(parse_split("2X2").unwrap(), (2, 2));
+        assert!(parse_split("3").is_err());
+        assert!(parse_split("0x2").is_err());
+        assert!(parse_split("ax2").is_err());
+    }
+
+    #[test]
+    fn test_save_panels() {
+        let mut skin = BlockSkin::new(Path::new("test_images/synthetic_skin.png"), 0).expect("could not load skin");
+        skin.resize(4, 4);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(5, 3, &skins);
+        for y in 0..3 {
+            for x in 0..5 {
+                board.fill_cell(&Cell { x, y }, ['I', 'O', 'T', 'L', 'J', 'S', 'Z'][(x + y * 2) % 7], 0).unwrap();
+            }
+        }
+
+        let output = Path::new("test_results/test_save_panels.png");
+        let paths = save_panels(&board, output, (2, 2)).unwrap();
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[3], Path::new("test_results/test_save_panels_1_1.png"));
+
+        // the last panel starts on a block edge and holds the leftover blocks
+        let whole = draw::draw(&board).unwrap();
+        let panel = image::open(&paths[3]).unwrap();
+        assert_eq!(panel.dimensions(), (12, 8));
+        assert_eq!(panel.to_rgba8(), whole.view(8, 4, 12, 8).to_image());
+
+        assert!(save_panels(&board, output, (6, 1)).is_err());
+    }
+}
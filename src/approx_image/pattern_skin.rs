@@ -0,0 +1,93 @@
+use super::draw::BlockSkin;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+const INK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const PAPER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+// skin sections in the order they appear in a skin image, as indices into `BlockSkin::as_array_ref`
+const SECTION_BLOCKS: [usize; 9] = [0, 1, 8, 5, 3, 7, 2, 6, 4];
+
+// whether the pixel at (x, y) of a block is inked, given the repeat period of the pattern
+type Pattern = fn(u32, u32, u32) -> bool;
+
+const PATTERNS: [Pattern; 9] = [
+    |_, _, _| true,
+    |x, y, p| (x / (p / 2) + y / (p / 2)) % 2 == 0,
+    |x, y, p| x % p < 2 || y % p < 2,
+    |_, y, p| y % p < p / 2,
+    |x, _, p| x % p < p / 2,
+    |x, y, p| (x + y) % p < p / 3,
+    |x, y, p| {
+        let (dx, dy) = ((x % p).abs_diff(p / 2), (y % p).abs_diff(p / 2));
+        dx * dx + dy * dy <= (p / 4) * (p / 4)
+    },
+    |x, y, p| (x + y) % p == 0,
+    |_, _, _| false,
+];
+
+// builds a black and white skin where each block type has its own pattern instead of its own color
+// so approximations stay readable when printed in grayscale
+// patterns are assigned by ink coverage so that darker blocks in `reference` get denser patterns
+pub fn pattern_skin(reference: &BlockSkin, id: usize) -> BlockSkin {
+    let (width, height) = (reference.width(), reference.height());
+    let period = (width.min(height) / 6).max(4);
+
+    // rank the reference blocks from darkest to lightest and the patterns from most to least ink
+    let blocks = reference.as_array_ref();
+    let mut blocks_by_luminance: Vec<usize> = (0..blocks.len()).collect();
+    blocks_by_luminance.sort_by_key(|block| luminance(blocks[*block].get_average_pixel()));
+    let mut patterns_by_ink: Vec<usize> = (0..PATTERNS.len()).collect();
+    patterns_by_ink.sort_by_key(|pattern| std::cmp::Reverse(coverage(PATTERNS[*pattern], width, height, period)));
+
+    let mut block_patterns = [0; 9];
+    for (block, pattern) in blocks_by_luminance.into_iter().zip(patterns_by_ink) {
+        block_patterns[block] = pattern;
+    }
+
+    // lay the blocks out the same way a skin image is, with an outline so neighboring blocks stay separate
+    let img = RgbaImage::from_fn(width * 9, height, |x, y| {
+        let (section, x) = (x / width, x % width);
+        let pattern = PATTERNS[block_patterns[SECTION_BLOCKS[section as usize]]];
+        let outline = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+        if outline || pattern(x, y, period) { INK } else { PAPER }
+    });
+    BlockSkin::from_image(DynamicImage::ImageRgba8(img), "patterns".to_string(), id)
+}
+
+fn coverage(pattern: Pattern, width: u32, height: u32, period: u32) -> usize {
+    (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|(x, y)| pattern(*x, *y, period))
+        .count()
+}
+
+fn luminance(pixel: Rgba<u8>) -> u32 {
+    299 * u32::from(pixel[0]) + 587 * u32::from(pixel[1]) + 114 * u32::from(pixel[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::test_skin;
+
+    #[test]
+    fn test_darkest_block_gets_most_ink() {
+        let reference = test_skin();
+        let skin = pattern_skin(&reference, 1);
+        assert_eq!((skin.width(), skin.height(), skin.id()), (reference.width(), reference.height(), 1));
+
+        let reference_blocks = reference.as_array_ref();
+        let blocks = skin.as_array_ref();
+        let darkest = (0..9).min_by_key(|block| luminance(reference_blocks[*block].get_average_pixel())).unwrap();
+        let lightest = (0..9).max_by_key(|block| luminance(reference_blocks[*block].get_average_pixel())).unwrap();
+        assert!(luminance(blocks[darkest].get_average_pixel()) < luminance(blocks[lightest].get_average_pixel()));
+
+        // every block type should look different
+        for a in 0..9 {
+            for b in (a + 1)..9 {
+                assert_ne!(blocks[a].image().to_rgba8(), blocks[b].image().to_rgba8());
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Clone, Debug)]
@@ -7,13 +8,13 @@ pub struct Dir {
     pub y: i32
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Cell {
     pub x: usize,
     pub y: usize
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Orientation {
     North,
     East,
@@ -21,7 +22,13 @@ pub enum Orientation {
     West
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rotation {
+    Clockwise,
+    CounterClockwise,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Piece {
     I(Cell, Orientation),
     O(Cell, Orientation),
@@ -32,6 +39,8 @@ pub enum Piece {
     Z(Cell, Orientation),
     Gray(Cell),
     Black(Cell),
+    // gray garbage darkened to `level` eighths of the way from black to gray, see `--garbage-levels`
+    Shade(Cell, u8),
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -39,6 +48,37 @@ pub enum Piece {
 pub enum PieceError {
     #[error("Invalid piece shape: {0:?}")]
     NegativeOccupancy(Box<[Dir]>),
+
+    #[error("Invalid tetromino: {0}, expected one of I, O, T, L, J, S, Z")]
+    InvalidTetromino(String),
+}
+
+pub const TETROMINO_CHARS: [char; 7] = ['I', 'O', 'T', 'L', 'J', 'S', 'Z'];
+
+// board chars of each garbage shade, by level
+pub const SHADE_CHARS: [char; MAX_SHADES] = ['1', '2', '3', '4', '5', '6', '7'];
+pub const MAX_SHADES: usize = 7;
+
+// the garbage level a board char stands for, if it's a shade
+pub fn shade_level(c: char) -> Option<u8> {
+    SHADE_CHARS.iter().position(|&shade| shade == c).and_then(|index| u8::try_from(index + 1).ok())
+}
+
+// the shades used with `levels` gray garbage levels, spread evenly between black and gray
+// a single level only uses the skin's own gray
+pub fn shade_levels(levels: u8) -> Vec<u8> {
+    let levels = u16::from(levels.clamp(1, 8));
+    let steps = u16::try_from(MAX_SHADES + 1).unwrap_or(8);
+    (1..levels).filter_map(|level| u8::try_from((level * steps + levels / 2) / levels).ok()).collect()
+}
+
+// parses a single tetromino letter, ignoring case
+pub fn parse_tetromino(s: &str) -> Result<char, PieceError> {
+    let mut chars = s.trim().chars();
+    match (chars.next().map(|c| c.to_ascii_uppercase()), chars.next()) {
+        (Some(c), None) if TETROMINO_CHARS.contains(&c) => Ok(c),
+        _ => Err(PieceError::InvalidTetromino(s.to_string())),
+    }
 }
 
 // constants modified from https://github.com/freyhoe/ditzy22/blob/main/common.h
@@ -72,10 +112,10 @@ const L_SHAPE: [[Dir; 4]; 4] = [
 ];
 
 const J_SHAPE: [[Dir; 4]; 4] = [
-    [Dir{ x: -1, y: 0 }, Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: -1, y: -1 }],
-    [Dir{ x: -1, y: -2 }, Dir{ x: 0, y: 0 }, Dir{ x: 0, y: -1 }, Dir{ x: 0, y: -2 }],
-    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: 0, y: -1 }],
+    [Dir{ x: 2, y: -1 }, Dir{ x: 1, y: -1 }, Dir{ x: 0, y: -1 }, Dir{ x: 0, y: 0 }],
     [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: -2 }, Dir{ x: -1, y: -1 }, Dir{ x: -1, y: 0 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: 0, y: -1 }],
+    [Dir{ x: -1, y: -2 }, Dir{ x: 0, y: 0 }, Dir{ x: 0, y: -1 }, Dir{ x: 0, y: -2 }],
 ];
 
 const S_SHAPE: [[Dir; 4]; 4] = [
@@ -92,10 +132,61 @@ const Z_SHAPE: [[Dir; 4]; 4] = [
     [Dir{ x: 0, y: 0 }, Dir{ x: 0, y: -1 }, Dir{ x: -1, y: -1 }, Dir{ x: -1, y: -2 }],
 ];
 
+// the corner of each orientation's srs bounding box, relative to the piece's cell
+// rotating keeps the box in place, which is what makes the rotation srs rather than about the cell
+const I_BOX: [Dir; 4] = [Dir{ x: 0, y: 1 }, Dir{ x: -2, y: 0 }, Dir{ x: -3, y: 2 }, Dir{ x: -1, y: 3 }];
+const O_BOX: [Dir; 4] = [Dir{ x: -2, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: -2, y: 0 }];
+const T_BOX: [Dir; 4] = [Dir{ x: -1, y: 1 }, Dir{ x: -2, y: 1 }, Dir{ x: -2, y: 1 }, Dir{ x: -1, y: 0 }];
+const L_BOX: [Dir; 4] = [Dir{ x: -2, y: 0 }, Dir{ x: -1, y: 1 }, Dir{ x: -2, y: 1 }, Dir{ x: -1, y: 0 }];
+const J_BOX: [Dir; 4] = [Dir{ x: 0, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: -2, y: 1 }, Dir{ x: -1, y: 0 }];
+const S_BOX: [Dir; 4] = [Dir{ x: -2, y: 0 }, Dir{ x: -1, y: 1 }, Dir{ x: -2, y: 1 }, Dir{ x: 0, y: 1 }];
+const Z_BOX: [Dir; 4] = [Dir{ x: -1, y: 1 }, Dir{ x: -2, y: 0 }, Dir{ x: -1, y: 2 }, Dir{ x: -1, y: 0 }];
+
+// srs wall kicks, tried in order, in the same frame as the shapes
+// indexed by the orientation rotated from, with clockwise before counterclockwise
+const JLSTZ_KICKS: [[Dir; 5]; 8] = [
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: -1, y: 1 }, Dir{ x: 0, y: -2 }, Dir{ x: -1, y: -2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: 1, y: 1 }, Dir{ x: 0, y: -2 }, Dir{ x: 1, y: -2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: 1, y: -1 }, Dir{ x: 0, y: 2 }, Dir{ x: 1, y: 2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: 1, y: -1 }, Dir{ x: 0, y: 2 }, Dir{ x: 1, y: 2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: 1, y: 1 }, Dir{ x: 0, y: -2 }, Dir{ x: 1, y: -2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: -1, y: 1 }, Dir{ x: 0, y: -2 }, Dir{ x: -1, y: -2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: -1, y: -1 }, Dir{ x: 0, y: 2 }, Dir{ x: -1, y: 2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: -1, y: -1 }, Dir{ x: 0, y: 2 }, Dir{ x: -1, y: 2 }],
+];
+
+const I_KICKS: [[Dir; 5]; 8] = [
+    [Dir{ x: 0, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: -2, y: -1 }, Dir{ x: 1, y: 2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: 2, y: 0 }, Dir{ x: -1, y: 2 }, Dir{ x: 2, y: -1 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: 2, y: 0 }, Dir{ x: -1, y: 2 }, Dir{ x: 2, y: -1 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 2, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: 2, y: 1 }, Dir{ x: -1, y: -2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 2, y: 0 }, Dir{ x: -1, y: 0 }, Dir{ x: 2, y: 1 }, Dir{ x: -1, y: -2 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: 1, y: -2 }, Dir{ x: -2, y: 1 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: 1, y: -2 }, Dir{ x: -2, y: 1 }],
+    [Dir{ x: 0, y: 0 }, Dir{ x: -2, y: 0 }, Dir{ x: 1, y: 0 }, Dir{ x: -2, y: -1 }, Dir{ x: 1, y: 2 }],
+];
+
 impl Orientation {
     pub fn all() -> [Orientation; 4] {
         [Orientation::North, Orientation::East, Orientation::South, Orientation::West]
     }
+
+    pub fn rotated(self, rotation: Rotation) -> Orientation {
+        let turns = match rotation {
+            Rotation::Clockwise => 1,
+            Rotation::CounterClockwise => 3,
+        };
+        Orientation::all()[(self.index() + turns) % 4]
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Orientation::North => 0,
+            Orientation::East => 1,
+            Orientation::South => 2,
+            Orientation::West => 3,
+        }
+    }
 }
 
 impl Piece {
@@ -111,8 +202,120 @@ impl Piece {
         ]
     }
 
-    pub fn all_garbage(cell: Cell) -> Vec<Piece> {
-        vec![Piece::Gray(cell), Piece::Black(cell)]
+    // black and gray garbage, followed by the extra shades for `levels` gray levels
+    pub fn all_garbage(cell: Cell, levels: u8) -> Vec<Piece> {
+        let mut pieces = vec![Piece::Gray(cell), Piece::Black(cell)];
+        pieces.extend(shade_levels(levels).into_iter().map(|level| Piece::Shade(cell, level)));
+        pieces
+    }
+
+    // the same piece moved right by `dx` and down by `dy` cells
+    pub fn translated(&self, dx: usize, dy: usize) -> Piece {
+        let shift = |c: &Cell| Cell { x: c.x + dx, y: c.y + dy };
+        match self {
+            Piece::I(c, o) => Piece::I(shift(c), *o),
+            Piece::O(c, o) => Piece::O(shift(c), *o),
+            Piece::T(c, o) => Piece::T(shift(c), *o),
+            Piece::L(c, o) => Piece::L(shift(c), *o),
+            Piece::J(c, o) => Piece::J(shift(c), *o),
+            Piece::S(c, o) => Piece::S(shift(c), *o),
+            Piece::Z(c, o) => Piece::Z(shift(c), *o),
+            Piece::Gray(c) => Piece::Gray(shift(c)),
+            Piece::Black(c) => Piece::Black(shift(c)),
+            Piece::Shade(c, level) => Piece::Shade(shift(c), *level),
+        }
+    }
+
+    // the same piece moved by `dx` and `dy` cells, or none if its cell would leave the board's top or left edge
+    pub fn shifted(&self, dx: i32, dy: i32) -> Option<Piece> {
+        let cell = self.get_cell();
+        let cell = Cell {
+            x: cell.x.checked_add_signed(isize::try_from(dx).ok()?)?,
+            y: cell.y.checked_add_signed(isize::try_from(dy).ok()?)?,
+        };
+        match self {
+            Piece::Gray(_) => Some(Piece::Gray(cell)),
+            Piece::Black(_) => Some(Piece::Black(cell)),
+            Piece::Shade(_, level) => Some(Piece::Shade(cell, *level)),
+            _ => self.reoriented(cell, self.get_orientation()),
+        }
+    }
+
+    // the piece rotated in place by srs, without trying any kicks
+    // garbage can't rotate, and the rotation fails if the piece's cell would leave the board's top or left edge
+    pub fn rotate(&self, rotation: Rotation) -> Option<Piece> {
+        self.rotated_with_kick(rotation, &Dir { x: 0, y: 0 })
+    }
+
+    // rotates the piece by srs, trying each wall kick in order until the rotated piece fits
+    pub fn try_rotate(&self, rotation: Rotation, fits: impl Fn(&Piece) -> bool) -> Option<Piece> {
+        self.kicks(rotation)
+            .iter()
+            .filter_map(|kick| self.rotated_with_kick(rotation, kick))
+            .find(|piece| fits(piece))
+    }
+
+    // the srs wall kicks for rotating out of the piece's orientation, the first being no kick at all
+    pub fn kicks(&self, rotation: Rotation) -> &'static [Dir] {
+        let kicks = match self {
+            Piece::I(_, _) => &I_KICKS,
+            Piece::T(_, _) | Piece::L(_, _) | Piece::J(_, _) | Piece::S(_, _) | Piece::Z(_, _) => &JLSTZ_KICKS,
+            Piece::O(_, _) => return &[Dir { x: 0, y: 0 }],
+            Piece::Gray(_) | Piece::Black(_) | Piece::Shade(_, _) => return &[],
+        };
+        let direction = match rotation {
+            Rotation::Clockwise => 0,
+            Rotation::CounterClockwise => 1,
+        };
+        &kicks[self.get_orientation().index() * 2 + direction]
+    }
+
+    fn rotated_with_kick(&self, rotation: Rotation, kick: &Dir) -> Option<Piece> {
+        let boxes = match self {
+            Piece::I(_, _) => &I_BOX,
+            Piece::O(_, _) => &O_BOX,
+            Piece::T(_, _) => &T_BOX,
+            Piece::L(_, _) => &L_BOX,
+            Piece::J(_, _) => &J_BOX,
+            Piece::S(_, _) => &S_BOX,
+            Piece::Z(_, _) => &Z_BOX,
+            Piece::Gray(_) | Piece::Black(_) | Piece::Shade(_, _) => return None,
+        };
+        let from = self.get_orientation();
+        let to = from.rotated(rotation);
+        let (old_box, new_box) = (&boxes[from.index()], &boxes[to.index()]);
+        self.reoriented(self.get_cell(), to)?
+            .shifted(old_box.x - new_box.x + kick.x, old_box.y - new_box.y + kick.y)
+    }
+
+    // the same kind of tetromino at another cell and orientation
+    fn reoriented(&self, cell: Cell, orientation: Orientation) -> Option<Piece> {
+        match self {
+            Piece::I(_, _) => Some(Piece::I(cell, orientation)),
+            Piece::O(_, _) => Some(Piece::O(cell, orientation)),
+            Piece::T(_, _) => Some(Piece::T(cell, orientation)),
+            Piece::L(_, _) => Some(Piece::L(cell, orientation)),
+            Piece::J(_, _) => Some(Piece::J(cell, orientation)),
+            Piece::S(_, _) => Some(Piece::S(cell, orientation)),
+            Piece::Z(_, _) => Some(Piece::Z(cell, orientation)),
+            Piece::Gray(_) | Piece::Black(_) | Piece::Shade(_, _) => None,
+        }
+    }
+
+    // index of the piece's block type, in the same order as `BlockSkin::as_array_ref`
+    // shades count as gray, since they're drawn from the gray block
+    pub fn block_index(&self) -> usize {
+        match self {
+            Piece::Black(_) => 0,
+            Piece::Gray(_) | Piece::Shade(_, _) => 1,
+            Piece::I(_, _) => 2,
+            Piece::O(_, _) => 3,
+            Piece::T(_, _) => 4,
+            Piece::L(_, _) => 5,
+            Piece::J(_, _) => 6,
+            Piece::S(_, _) => 7,
+            Piece::Z(_, _) => 8,
+        }
     }
 
     pub fn get_char(&self) -> char {
@@ -125,7 +328,8 @@ impl Piece {
             Piece::S(_, _) => 'S',
             Piece::Z(_, _) => 'Z',
             Piece::Gray(_) => 'G',
-            Piece::Black(_) => 'B'
+            Piece::Black(_) => 'B',
+            Piece::Shade(_, level) => SHADE_CHARS[usize::from(*level) - 1],
         }
     }
 
@@ -152,7 +356,8 @@ impl Piece {
             Piece::S(c, _) |
             Piece::Z(c, _) |
             Piece::Gray(c) |
-            Piece::Black(c) => *c
+            Piece::Black(c) |
+            Piece::Shade(c, _) => *c
         }
     }
 
@@ -167,7 +372,7 @@ impl Piece {
             Piece::J(_, _) => &J_SHAPE,
             Piece::S(_, _) => &S_SHAPE,
             Piece::Z(_, _) => &Z_SHAPE,
-            Piece::Gray(c) | Piece::Black(c) => return Ok(vec![*c]),
+            Piece::Gray(c) | Piece::Black(c) | Piece::Shade(c, _) => return Ok(vec![*c]),
         };
 
         let orien = self.get_orientation();
@@ -209,10 +414,68 @@ mod tests {
         assert_eq!(piece.get_cell(), Cell { x: 1, y: 1 });
     }
 
+    #[test]
+    fn test_shade_levels() {
+        assert!(shade_levels(1).is_empty());
+        assert_eq!(shade_levels(4), vec![2, 4, 6]);
+        assert_eq!(shade_levels(8), vec![1, 2, 3, 4, 5, 6, 7]);
+
+        let cell = Cell { x: 0, y: 0 };
+        let garbage = Piece::all_garbage(cell, 4);
+        assert_eq!(garbage.len(), 5);
+        assert_eq!(garbage[2].get_char(), '2');
+        assert_eq!(shade_level('2'), Some(2));
+    }
+
     #[test]
     fn test_get_occupancy() {
         let piece = Piece::I(Cell { x: 2, y: 2 }, Orientation::North);
         assert!(piece.get_occupancy().is_ok());
     }
 
+    #[test]
+    fn test_rotate_round_trip() {
+        for orientation in Orientation::all() {
+            for piece in Piece::all_normal(Cell { x: 8, y: 8 }, orientation) {
+                let mut rotated = piece.clone();
+                for _ in 0..4 {
+                    rotated = rotated.rotate(Rotation::Clockwise).unwrap();
+                }
+                assert_eq!(rotated, piece);
+
+                let back = piece.rotate(Rotation::Clockwise).unwrap().rotate(Rotation::CounterClockwise).unwrap();
+                assert_eq!(back, piece);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rotate_keeps_srs_center() {
+        // a t spins about its middle block, and an o doesn't move at all
+        let mut t = Piece::T(Cell { x: 5, y: 5 }, Orientation::North);
+        for _ in 0..4 {
+            let rotated = t.rotate(Rotation::Clockwise).unwrap();
+            assert!(rotated.get_occupancy().unwrap().contains(&Cell { x: 5, y: 5 }));
+            t = rotated;
+        }
+
+        let o = Piece::O(Cell { x: 5, y: 5 }, Orientation::North);
+        let mut o_cells = o.rotate(Rotation::CounterClockwise).unwrap().get_occupancy().unwrap();
+        let mut cells = o.get_occupancy().unwrap();
+        o_cells.sort();
+        cells.sort();
+        assert_eq!(o_cells, cells);
+    }
+
+    #[test]
+    fn test_try_rotate_kicks_off_wall() {
+        let in_bounds = |piece: &Piece| piece.get_occupancy().is_ok_and(|cells| cells.iter().all(|c| c.x < 10 && c.y < 20));
+
+        // an upright t against the left wall has no room to point down without being kicked right
+        let t = Piece::T(Cell { x: 1, y: 5 }, Orientation::East);
+        assert!(!in_bounds(&t.rotate(Rotation::Clockwise).unwrap()));
+        assert_eq!(t.try_rotate(Rotation::Clockwise, in_bounds), Some(Piece::T(Cell { x: 2, y: 5 }, Orientation::South)));
+
+        assert_eq!(Piece::Gray(Cell { x: 1, y: 1 }).try_rotate(Rotation::Clockwise, in_bounds), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,239 @@
+use super::{animation, approx_board, board_dump, draw, legend, load, panels, playfield, preprocess};
+use super::render::{render, OutputFormat, SvgRenderer};
+use super::integration_test::{score_images, ImageScore};
+use super::draw::SkinnedBoard;
+use crate::cli::{Config, GlobalData};
+use crate::summary::RunSummary;
+
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Result;
+use image::DynamicImage;
+use thiserror::Error;
+use tracing::{debug, info};
+
+// stages used when none are configured
+pub const DEFAULT_STAGES: [&str; 4] = ["preprocess", "approximate", "stats", "export"];
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("Unknown pipeline stage: {0}")]
+    UnknownStage(String),
+
+    #[error("Stage {0} needs an approximated board; add the approximate stage before it")]
+    MissingBoard(&'static str),
+}
+
+// everything a stage can read or change while an image moves through the pipeline
+pub struct PipelineContext<'a> {
+    pub config: &'a Config,
+    pub glob: &'a GlobalData,
+    pub output: &'a Path,
+    // source image, already resized to fit the board
+    pub image: DynamicImage,
+    pub board: Option<SkinnedBoard<'a>>,
+    pub score: Option<ImageScore>,
+    // how long each stage took and what was written, for `--summary-out`
+    pub summary: RunSummary,
+}
+
+pub trait Stage {
+    fn name(&self) -> &'static str;
+    fn run(&self, ctx: &mut PipelineContext) -> Result<()>;
+}
+
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    // builds a pipeline from stage names, using the default stages if `names` is empty
+    pub fn from_names<S: AsRef<str>>(names: &[S]) -> Result<Pipeline> {
+        let stages = if names.is_empty() {
+            DEFAULT_STAGES.iter().map(|name| stage(name)).collect::<Result<_>>()?
+        } else {
+            names.iter().map(|name| stage(name.as_ref())).collect::<Result<_>>()?
+        };
+        Ok(Pipeline { stages })
+    }
+
+    // adds a stage to the end of the pipeline, unless it's already there
+    pub fn push(&mut self, name: &str) -> Result<()> {
+        if !self.stages.iter().any(|stage| stage.name() == name) {
+            self.stages.push(stage(name)?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, ctx: &mut PipelineContext) -> Result<()> {
+        for stage in &self.stages {
+            debug!(stage = stage.name(), "running stage");
+            let start = Instant::now();
+            stage.run(ctx)?;
+            ctx.summary.stage(stage.name(), start.elapsed());
+        }
+        Ok(())
+    }
+}
+
+// looks up a stage by name; new stages are registered here
+pub fn stage(name: &str) -> Result<Box<dyn Stage>> {
+    match name {
+        "preprocess" => Ok(Box::new(Preprocess)),
+        "approximate" => Ok(Box::new(Approximate)),
+        "stats" => Ok(Box::new(Stats)),
+        "score" => Ok(Box::new(Score)),
+        "export" => Ok(Box::new(Export)),
+        _ => Err(PipelineError::UnknownStage(name.to_string()).into()),
+    }
+}
+
+// applies the configured source preprocessing, like duotone
+struct Preprocess;
+
+impl Stage for Preprocess {
+    fn name(&self) -> &'static str { "preprocess" }
+
+    fn run(&self, ctx: &mut PipelineContext) -> Result<()> {
+        preprocess::apply(&mut ctx.image, ctx.config)
+    }
+}
+
+// fills the board with pieces
+struct Approximate;
+
+impl Stage for Approximate {
+    fn name(&self) -> &'static str { "approximate" }
+
+    fn run(&self, ctx: &mut PipelineContext) -> Result<()> {
+        ctx.board = Some(if ctx.config.playfields {
+            playfield::approx_playfields(&ctx.image, ctx.config, ctx.glob)?
+        } else {
+            approx_board(&ctx.image, ctx.config, ctx.glob)?
+        });
+        Ok(())
+    }
+}
+
+// logs how often each piece was used
+struct Stats;
+
+impl Stage for Stats {
+    fn name(&self) -> &'static str { "stats" }
+
+    fn run(&self, ctx: &mut PipelineContext) -> Result<()> {
+        let board = ctx.board.as_ref().ok_or(PipelineError::MissingBoard(self.name()))?;
+        let counts = board.piece_counts();
+        info!(
+            i = counts[2], o = counts[3], t = counts[4], l = counts[5], j = counts[6], s = counts[7], z = counts[8],
+            gray = counts[1], black = counts[0],
+            "piece usage"
+        );
+        Ok(())
+    }
+}
+
+// compares the drawn board to the source
+struct Score;
+
+impl Stage for Score {
+    fn name(&self) -> &'static str { "score" }
+
+    fn run(&self, ctx: &mut PipelineContext) -> Result<()> {
+        let board = ctx.board.as_ref().ok_or(PipelineError::MissingBoard(self.name()))?;
+        let score = score_images(&draw::draw(board)?, &ctx.image)?;
+        info!(dssim = score.dssim, similarity = score.similarity, "scored approximation");
+        ctx.score = Some(score);
+        Ok(())
+    }
+}
+
+// saves the drawn board, along with the board dump, ascii board, legend, and build animation if configured
+struct Export;
+
+impl Stage for Export {
+    fn name(&self) -> &'static str { "export" }
+
+    fn run(&self, ctx: &mut PipelineContext) -> Result<()> {
+        let board = ctx.board.as_ref().ok_or(PipelineError::MissingBoard(self.name()))?;
+        match (ctx.config.output_format, ctx.config.split) {
+            (OutputFormat::Image, Some(split)) => {
+                let paths = panels::save_panels(board, ctx.output, split)?;
+                info!(panels = paths.len(), output = %ctx.output.display(), "saved approximation as panels");
+                ctx.summary.outputs.extend(paths);
+            }
+            (format, _) => {
+                match format {
+                    // very large boards are streamed out as a png instead of being drawn in memory
+                    OutputFormat::Image if ctx.config.tiled => draw::draw_tiled(board, load::create(ctx.output)?)?,
+                    OutputFormat::Image => {
+                        let img = if ctx.config.playfields { playfield::draw(board)? } else { draw::draw(board)? };
+                        load::save(&img, ctx.output)?;
+                    }
+                    OutputFormat::Svg => load::save_text(&render(board, SvgRenderer::skinned())?, ctx.output)?,
+                    OutputFormat::SvgFlat => load::save_text(&render(board, SvgRenderer::default())?, ctx.output)?,
+                }
+                info!(output = %ctx.output.display(), "saved approximation");
+                ctx.summary.output(ctx.output);
+            }
+        }
+
+        if let Some(board_path) = &ctx.config.board_out {
+            board_dump::BoardDump::new(board)?.save(board_path)?;
+            info!(path = %board_path.display(), "saved board");
+            ctx.summary.output(board_path);
+        }
+
+        if let Some(ascii_path) = &ctx.config.ascii_out {
+            std::fs::write(ascii_path, board.board().to_ascii(ctx.config.ascii_format))?;
+            info!(path = %ascii_path.display(), "saved ascii board");
+            ctx.summary.output(ascii_path);
+        }
+
+        if let Some(legend_path) = &ctx.config.legend {
+            std::fs::write(legend_path, legend::legend_svg(board)?)?;
+            info!(path = %legend_path.display(), "saved legend");
+            ctx.summary.output(legend_path);
+        }
+
+        if let Some(animation_path) = &ctx.config.build_animation {
+            animation::save(board, animation_path, ctx.config.pieces_per_frame)?;
+            info!(path = %animation_path.display(), "saved build animation");
+            ctx.summary.output(animation_path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_names() {
+        assert!(Pipeline::from_names::<&str>(&[]).is_ok());
+        assert!(Pipeline::from_names(&["approximate", "export"]).is_ok());
+        assert!(Pipeline::from_names(&["approximate", "sharpen"]).is_err());
+
+        let mut pipeline = Pipeline::from_names(&["approximate", "score"]).unwrap();
+        pipeline.push("score").unwrap();
+        assert_eq!(pipeline.stages.len(), 2);
+    }
+
+    #[test]
+    fn test_export_needs_board() {
+        let config = Config::default();
+        let glob = GlobalData::from_skins(Vec::new());
+        let mut ctx = PipelineContext {
+            config: &config,
+            glob: &glob,
+            output: Path::new("test_results/unused.png"),
+            image: DynamicImage::new_rgba8(1, 1),
+            board: None,
+            score: None,
+            summary: RunSummary::default(),
+        };
+        assert!(Pipeline::from_names(&["export"]).unwrap().run(&mut ctx).is_err());
+    }
+}
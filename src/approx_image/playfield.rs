@@ -0,0 +1,106 @@
+use super::approx_board;
+use super::draw::{self, SkinnedBoard};
+use crate::cli::{Config, GlobalData};
+
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use rayon::prelude::*;
+use thiserror::Error;
+
+// size of a standard tetris playfield, in cells
+pub const PLAYFIELD_WIDTH: usize = 10;
+pub const PLAYFIELD_HEIGHT: usize = 20;
+
+// color of the gaps drawn between playfields
+const GAP_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+#[derive(Debug, Error)]
+pub enum PlayfieldError {
+    #[error("Board must be a whole number of {PLAYFIELD_WIDTH}x{PLAYFIELD_HEIGHT} playfields, got {0}x{1}")]
+    UnevenBoard(usize, usize),
+}
+
+// approximates the source as a grid of standard playfields, each filled on its own so no piece crosses between them
+// the playfields are joined into one board so everything that works on a board still does
+pub fn approx_playfields<'a>(source_img: &DynamicImage, config: &Config, glob: &'a GlobalData) -> Result<SkinnedBoard<'a>> {
+    if !config.board_width.is_multiple_of(PLAYFIELD_WIDTH) || !config.board_height.is_multiple_of(PLAYFIELD_HEIGHT) {
+        Err(PlayfieldError::UnevenBoard(config.board_width, config.board_height))?;
+    }
+    let columns = config.board_width / PLAYFIELD_WIDTH;
+    let rows = config.board_height / PLAYFIELD_HEIGHT;
+
+    // per-board outputs only make sense for the whole grid
+    let field_config = Config {
+        board_width: PLAYFIELD_WIDTH,
+        board_height: PLAYFIELD_HEIGHT,
+        preview: None,
+        confidence_out: None,
+        ..config.clone()
+    };
+    let field_width = glob.skin_width() * u32::try_from(PLAYFIELD_WIDTH)?;
+    let field_height = glob.skin_height() * u32::try_from(PLAYFIELD_HEIGHT)?;
+
+    let fields = (0..rows * columns)
+        .into_par_iter()
+        .map(|i| {
+            let (x, y) = (u32::try_from(i % columns)? * field_width, u32::try_from(i / columns)? * field_height);
+            let field_img = source_img.crop_imm(x, y, field_width, field_height);
+            approx_board(&field_img, &field_config, glob)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut board = SkinnedBoard::new(config.board_width, config.board_height, &glob.skins);
+    for (i, field) in fields.iter().enumerate() {
+        board.paste(field, (i % columns) * PLAYFIELD_WIDTH, (i / columns) * PLAYFIELD_HEIGHT)?;
+    }
+    Ok(board)
+}
+
+// draws a board made of playfields with a gap between each one, so they read as separate boards
+pub fn draw(board: &SkinnedBoard) -> Result<DynamicImage> {
+    let img = draw::draw(board)?;
+    let columns = u32::try_from(board.board_width() / PLAYFIELD_WIDTH)?;
+    let rows = u32::try_from(board.board_height() / PLAYFIELD_HEIGHT)?;
+    let field_width = board.skins_width() * u32::try_from(PLAYFIELD_WIDTH)?;
+    let field_height = board.skins_height() * u32::try_from(PLAYFIELD_HEIGHT)?;
+    let gap = (board.skins_width() / 4).max(1);
+
+    let mut grid = RgbaImage::from_pixel(
+        columns * field_width + (columns - 1) * gap,
+        rows * field_height + (rows - 1) * gap,
+        GAP_COLOR,
+    );
+    for row in 0..rows {
+        for column in 0..columns {
+            let field = img.view(column * field_width, row * field_height, field_width, field_height);
+            let (x, y) = (column * (field_width + gap), row * (field_height + gap));
+            image::imageops::replace(&mut grid, &*field, x.into(), y.into());
+        }
+    }
+    Ok(DynamicImage::from(grid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_glob;
+
+    #[test]
+    fn test_playfields_stay_separate() {
+        let glob = test_glob(2);
+        let config = Config { board_width: 20, board_height: 20, ..Config::default() };
+        let source_img = DynamicImage::from(RgbaImage::from_fn(40, 40, |x, y| Rgba([(x * 6) as u8, (y * 6) as u8, 120, 255])));
+
+        let board = approx_playfields(&source_img, &config, &glob).unwrap();
+        for piece in board.board().pieces() {
+            let cells = piece.get_occupancy().unwrap();
+            assert!(cells.iter().all(|cell| cell.x / PLAYFIELD_WIDTH == cells[0].x / PLAYFIELD_WIDTH));
+        }
+
+        let img = draw(&board).unwrap();
+        assert_eq!(img.dimensions(), (20 * 2 + 1, 20 * 2));
+
+        let uneven = Config { board_width: 15, ..config };
+        assert!(approx_playfields(&source_img, &uneven, &glob).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use crate::cli::Config;
+
+use anyhow::{ensure, Result};
+use image::{DynamicImage, Rgba};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ColorError {
+    #[error("Invalid color, expected RRGGBB hex: {0}")]
+    InvalidHex(String),
+}
+
+// applies every enabled preprocessing step to an already resized source image
+pub fn apply(img: &mut DynamicImage, config: &Config) -> Result<()> {
+    if config.brightness.is_some() || config.contrast.is_some() || config.gamma.is_some() {
+        adjust_levels(img, config.brightness.unwrap_or(0.0), config.contrast.unwrap_or(1.0), config.gamma.unwrap_or(1.0))?;
+    }
+    if let Some(colors) = config.quantize_colors {
+        quantize(img, usize::from(colors));
+    }
+    if let Some((dark, light)) = config.duotone {
+        duotone(img, dark, light)?;
+    }
+    Ok(())
+}
+
+// maps the image onto two colors by luminance, using Floyd-Steinberg dithering to keep intermediate shades
+pub fn duotone(img: &mut DynamicImage, dark: Rgba<u8>, light: Rgba<u8>) -> Result<()> {
+    let mut buffer = img.to_rgba8();
+    let width = usize::try_from(buffer.width())?;
+    let height = usize::try_from(buffer.height())?;
+
+    let mut luma: Vec<f32> = buffer.pixels().map(luminance).collect();
+    for (i, pixel) in buffer.pixels_mut().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let (target, color) = if luma[i] < 128.0 { (0.0, dark) } else { (255.0, light) };
+
+        // push the quantization error onto the pixels that haven't been visited yet
+        let error = luma[i] - target;
+        if x + 1 < width {
+            luma[i + 1] += error * 7.0 / 16.0;
+        }
+        if y + 1 < height {
+            if x > 0 {
+                luma[i + width - 1] += error * 3.0 / 16.0;
+            }
+            luma[i + width] += error * 5.0 / 16.0;
+            if x + 1 < width {
+                luma[i + width + 1] += error / 16.0;
+            }
+        }
+
+        // keep the original transparency
+        *pixel = Rgba([color[0], color[1], color[2], pixel[3]]);
+    }
+
+    *img = DynamicImage::from(buffer);
+    Ok(())
+}
+
+// shifts the brightness by a fraction of full white, scales the contrast around mid gray, then applies the gamma
+// skins only cover part of the color range, so a little extra contrast lets more of the source's shades land on distinct blocks
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn adjust_levels(img: &mut DynamicImage, brightness: f64, contrast: f64, gamma: f64) -> Result<()> {
+    ensure!(gamma > 0.0, "gamma must be positive, got {gamma}");
+
+    let levels: Vec<u8> = (0..=u8::MAX)
+        .map(|value| {
+            let value = f64::from(value) / 255.0 + brightness;
+            let value = (value - 0.5) * contrast + 0.5;
+            (value.clamp(0.0, 1.0).powf(1.0 / gamma) * 255.0).round() as u8
+        })
+        .collect();
+
+    let mut buffer = img.to_rgba8();
+    for pixel in buffer.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = levels[usize::from(*channel)];
+        }
+    }
+
+    *img = DynamicImage::from(buffer);
+    Ok(())
+}
+
+// reduces the image to at most `colors` colors by median cut, which smooths out noise before blocks are picked
+// each pixel becomes the average color of its box, keeping its own transparency
+pub fn quantize(img: &mut DynamicImage, colors: usize) {
+    let mut buffer = img.to_rgba8();
+    let pixels: Vec<[u8; 3]> = buffer.pixels().map(|p| [p[0], p[1], p[2]]).collect();
+
+    // keep splitting the box with the widest channel at its median until there are enough boxes
+    let mut boxes: Vec<Vec<usize>> = vec![(0..pixels.len()).collect()];
+    while boxes.len() < colors {
+        let Some((i, channel, range)) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, indices)| {
+                let (channel, range) = widest_channel(&pixels, indices);
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range)
+        else {
+            break;
+        };
+        // every box is a single color already
+        if range == 0 {
+            break;
+        }
+
+        let mut lower = boxes.swap_remove(i);
+        lower.sort_unstable_by_key(|&p| pixels[p][channel]);
+        let upper = lower.split_off(lower.len() / 2);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let mut quantized = pixels.clone();
+    for indices in boxes {
+        let mut sum = [0u64; 3];
+        for &p in &indices {
+            for (total, value) in sum.iter_mut().zip(pixels[p]) {
+                *total += u64::from(value);
+            }
+        }
+        let count = indices.len() as u64;
+        let mean = sum.map(|total| u8::try_from(total / count).unwrap_or(u8::MAX));
+        for &p in &indices {
+            quantized[p] = mean;
+        }
+    }
+
+    for (pixel, color) in buffer.pixels_mut().zip(quantized) {
+        *pixel = Rgba([color[0], color[1], color[2], pixel[3]]);
+    }
+
+    *img = DynamicImage::from(buffer);
+}
+
+// the channel with the largest spread of values among the pixels, and that spread
+fn widest_channel(pixels: &[[u8; 3]], indices: &[usize]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = indices.iter().map(|&p| pixels[p][channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|&(_, range)| range)
+        .unwrap_or((0, 0))
+}
+
+pub fn parse_color(hex: &str) -> Result<Rgba<u8>, ColorError> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if digits.len() != 6 || !digits.is_ascii() {
+        return Err(ColorError::InvalidHex(hex.to_string()));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| ColorError::InvalidHex(hex.to_string()));
+    Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]))
+}
+
+fn luminance(pixel: &Rgba<u8>) -> f32 {
+    0.299 * f32::from(pixel[0]) + 0.587 * f32::from(pixel[1]) + 0.114 * f32::from(pixel[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_color("0A0b0C").unwrap(), Rgba([10, 11, 12, 255]));
+        assert!(parse_color("fff").is_err());
+        assert!(parse_color("gg0000").is_err());
+    }
+
+    #[test]
+    fn test_duotone_only_uses_two_colors() {
+        let dark = Rgba([20, 0, 60, 255]);
+        let light = Rgba([250, 200, 100, 255]);
+        let mut img = DynamicImage::from(image::RgbaImage::from_fn(16, 8, |x, y| {
+            let shade = u8::try_from(x * 16 + y).unwrap();
+            Rgba([shade, shade, shade, 255])
+        }));
+
+        duotone(&mut img, dark, light).unwrap();
+        let buffer = img.to_rgba8();
+        assert!(buffer.pixels().all(|p| *p == dark || *p == light));
+        assert!(buffer.pixels().any(|p| *p == dark));
+        assert!(buffer.pixels().any(|p| *p == light));
+    }
+
+    #[test]
+    fn test_adjust_levels() {
+        let source = DynamicImage::from(image::RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([u8::try_from(x * 16).unwrap(), u8::try_from(y * 16).unwrap(), 200, 100])
+        }));
+
+        let mut img = source.clone();
+        adjust_levels(&mut img, 0.0, 1.0, 1.0).unwrap();
+        assert_eq!(img, source);
+
+        // no contrast at all leaves only mid gray
+        adjust_levels(&mut img, 0.0, 0.0, 1.0).unwrap();
+        assert!(img.to_rgba8().pixels().all(|p| *p == Rgba([128, 128, 128, 100])));
+
+        let mut img = source.clone();
+        adjust_levels(&mut img, 0.2, 1.0, 2.0).unwrap();
+        assert!(img.to_rgba8().pixels().zip(source.to_rgba8().pixels()).all(|(p, s)| p[0] >= s[0] && p[3] == s[3]));
+
+        assert!(adjust_levels(&mut img, 0.0, 1.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_quantize_limits_colors() {
+        let mut img = DynamicImage::from(image::RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([u8::try_from(x * 16).unwrap(), u8::try_from(y * 16).unwrap(), 128, u8::try_from(x + y).unwrap()])
+        }));
+
+        quantize(&mut img, 4);
+        let buffer = img.to_rgba8();
+        let mut colors: Vec<[u8; 3]> = buffer.pixels().map(|p| [p[0], p[1], p[2]]).collect();
+        colors.sort_unstable();
+        colors.dedup();
+        assert_eq!(colors.len(), 4);
+        assert!(buffer.enumerate_pixels().all(|(x, y, p)| p[3] == u8::try_from(x + y).unwrap()));
+    }
+}
@@ -0,0 +1,58 @@
+use super::draw::{self, SkinnedBoard};
+
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use image::imageops::{resize, FilterType};
+
+// previews are upscaled so their longest side is roughly this many pixels
+const PREVIEW_SIZE: u32 = 512;
+
+#[derive(Clone, Debug)]
+pub struct PreviewConfig {
+    pub path: PathBuf,
+    // write a new preview every time this percentage of cells has been filled
+    pub every_percent: f64,
+}
+
+// periodically writes a low-res render of the board while it is being filled
+pub struct PreviewWriter {
+    config: PreviewConfig,
+    step: usize,
+    next: usize,
+}
+
+impl PreviewWriter {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+    pub fn new(config: &PreviewConfig, total_cells: usize) -> PreviewWriter {
+        let step = ((total_cells as f64 * config.every_percent / 100.0).ceil() as usize).max(1);
+        PreviewWriter {
+            config: config.clone(),
+            step,
+            next: step,
+        }
+    }
+
+    pub fn update(&mut self, board: &SkinnedBoard, filled_cells: usize) -> Result<()> {
+        if filled_cells < self.next {
+            return Ok(());
+        }
+        while self.next <= filled_cells {
+            self.next += self.step;
+        }
+        self.write(board)
+    }
+
+    fn write(&self, board: &SkinnedBoard) -> Result<()> {
+        let img = draw::draw_preview(board)?;
+        let scale = (PREVIEW_SIZE / img.width().max(img.height())).max(1);
+        let img = resize(&img, img.width() * scale, img.height() * scale, FilterType::Nearest);
+
+        // write to a temporary file first so viewers never see a partially written preview
+        let tmp_path = self.config.path.with_extension("png.tmp");
+        img.save_with_format(&tmp_path, image::ImageFormat::Png)?;
+        fs::rename(&tmp_path, &self.config.path)?;
+        Ok(())
+    }
+}
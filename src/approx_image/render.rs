@@ -0,0 +1,211 @@
+use super::board::EMPTY_CELL;
+use super::draw::{self, BlockSkin, SkinnedBoard};
+use super::legend::png_base64;
+use super::piece::Cell;
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use anyhow::Result;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+// what an approximated image is saved as
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    // a raster image in the format of the output's extension
+    #[default]
+    Image,
+    // an svg where every cell reuses a symbol of its skin's block, so the file stays small and scales to any size
+    Svg,
+    // an svg of flat rects in each block's average color
+    SvgFlat,
+}
+
+// draws a finished board one filled cell at a time, so the same walk over the board can produce different outputs
+pub trait CellRenderer {
+    type Output;
+
+    // called once before any cells, to size the output for the board
+    fn begin(&mut self, skin_board: &SkinnedBoard) -> Result<()>;
+
+    // called for every filled cell, with its block type and the skin it was placed with
+    fn cell(&mut self, cell: &Cell, cell_char: char, skin: &BlockSkin) -> Result<()>;
+
+    fn finish(self) -> Result<Self::Output>;
+}
+
+// runs the renderer over every filled cell, row by row; unfilled cells are skipped
+pub fn render<R: CellRenderer>(skin_board: &SkinnedBoard, mut renderer: R) -> Result<R::Output> {
+    renderer.begin(skin_board)?;
+    for y in 0..skin_board.board_height() {
+        for x in 0..skin_board.board_width() {
+            let cell = Cell { x, y };
+            let cell_char = skin_board.board().get(&cell)?;
+            if cell_char == EMPTY_CELL {
+                continue;
+            }
+            renderer.cell(&cell, cell_char, skin_board.get_skin(skin_board.get_cells_skin(&cell)))?;
+        }
+    }
+    renderer.finish()
+}
+
+// draws each cell with its skin's block image, which is what `draw::draw` produces
+#[derive(Default)]
+pub struct SkinRenderer {
+    img: RgbaImage,
+}
+
+impl CellRenderer for SkinRenderer {
+    type Output = DynamicImage;
+
+    fn begin(&mut self, skin_board: &SkinnedBoard) -> Result<()> {
+        self.img = RgbaImage::new(board_pixels(skin_board.board_width(), skin_board.skins_width())?, board_pixels(skin_board.board_height(), skin_board.skins_height())?);
+        Ok(())
+    }
+
+    fn cell(&mut self, cell: &Cell, cell_char: char, skin: &BlockSkin) -> Result<()> {
+        draw::draw_block(&mut self.img, skin, cell_char, cell)
+    }
+
+    fn finish(self) -> Result<DynamicImage> {
+        Ok(DynamicImage::from(self.img))
+    }
+}
+
+// fills each cell with its block's average color, for a flat look without the skin's shading
+#[derive(Default)]
+pub struct FlatRenderer {
+    img: RgbaImage,
+}
+
+impl CellRenderer for FlatRenderer {
+    type Output = DynamicImage;
+
+    fn begin(&mut self, skin_board: &SkinnedBoard) -> Result<()> {
+        self.img = RgbaImage::new(board_pixels(skin_board.board_width(), skin_board.skins_width())?, board_pixels(skin_board.board_height(), skin_board.skins_height())?);
+        Ok(())
+    }
+
+    fn cell(&mut self, cell: &Cell, cell_char: char, skin: &BlockSkin) -> Result<()> {
+        let color = skin.block_image_from_char(cell_char).get_average_pixel();
+        let (left, top) = (u32::try_from(cell.x)? * skin.width(), u32::try_from(cell.y)? * skin.height());
+        for y in top..top + skin.height() {
+            for x in left..left + skin.width() {
+                self.img.put_pixel(x, y, color);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<DynamicImage> {
+        Ok(DynamicImage::from(self.img))
+    }
+}
+
+// writes an svg with a rect of each block's average color per cell, which scales to any size without blurring
+// a skinned svg instead embeds each block that's used once as a symbol and places a copy of it in every cell
+#[derive(Default)]
+pub struct SvgRenderer {
+    skinned: bool,
+    header: String,
+    defs: String,
+    body: String,
+    // the skin id and block of every symbol already in `defs`
+    symbols: HashSet<(usize, char)>,
+}
+
+impl SvgRenderer {
+    pub fn skinned() -> SvgRenderer {
+        SvgRenderer { skinned: true, ..SvgRenderer::default() }
+    }
+}
+
+impl CellRenderer for SvgRenderer {
+    type Output = String;
+
+    fn begin(&mut self, skin_board: &SkinnedBoard) -> Result<()> {
+        let width = board_pixels(skin_board.board_width(), skin_board.skins_width())?;
+        let height = board_pixels(skin_board.board_height(), skin_board.skins_height())?;
+        // crisp edges keep antialiasing from opening seams between neighboring cells
+        writeln!(self.header, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" shape-rendering="crispEdges">"#)?;
+        Ok(())
+    }
+
+    fn cell(&mut self, cell: &Cell, cell_char: char, skin: &BlockSkin) -> Result<()> {
+        let (x, y) = (u32::try_from(cell.x)? * skin.width(), u32::try_from(cell.y)? * skin.height());
+        let (width, height) = (skin.width(), skin.height());
+        let block = skin.block_image_from_char(cell_char);
+        if !self.skinned {
+            writeln!(self.body, r#"<rect x="{x}" y="{y}" width="{width}" height="{height}" fill="{}"/>"#, hex(block.get_average_pixel()))?;
+            return Ok(());
+        }
+
+        let id = format!("s{}{cell_char}", skin.id());
+        if self.symbols.insert((skin.id(), cell_char)) {
+            // pixelated keeps the block's pixels sharp when the svg is scaled up
+            writeln!(
+                self.defs,
+                r#"<symbol id="{id}" viewBox="0 0 {width} {height}"><image width="{width}" height="{height}" style="image-rendering:pixelated" href="data:image/png;base64,{}"/></symbol>"#,
+                png_base64(block.image())?
+            )?;
+        }
+        writeln!(self.body, r##"<use href="#{id}" x="{x}" y="{y}" width="{width}" height="{height}"/>"##)?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<String> {
+        let mut svg = self.header;
+        if !self.defs.is_empty() {
+            writeln!(svg, "<defs>\n{}</defs>", self.defs)?;
+        }
+        svg += &self.body;
+        writeln!(svg, "</svg>")?;
+        Ok(svg)
+    }
+}
+
+fn board_pixels(cells: usize, cell_size: u32) -> Result<u32> {
+    Ok(u32::try_from(cells)? * cell_size)
+}
+
+fn hex(color: Rgba<u8>) -> String {
+    format!("#{:02x}{:02x}{:02x}", color[0], color[1], color[2])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::piece::{Orientation, Piece};
+
+    use std::path::Path;
+
+    #[test]
+    fn test_renderers() {
+        let mut skin = BlockSkin::new(Path::new("test_images/synthetic_skin.png"), 0).expect("could not load skin");
+        skin.resize(4, 4);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(4, 2, &skins);
+        board.place(&Piece::I(Cell { x: 0, y: 1 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::Gray(Cell { x: 3, y: 0 }), 0).unwrap();
+
+        let flat = render(&board, FlatRenderer::default()).unwrap().into_rgba8();
+        let i_color = skins[0].block_image_from_char('I').get_average_pixel();
+        assert_eq!(flat.dimensions(), (16, 8));
+        assert!((0..16).all(|x| *flat.get_pixel(x, 6) == i_color));
+        assert_eq!(flat.get_pixel(0, 0)[3], 0);
+
+        let svg = render(&board, SvgRenderer::default()).unwrap();
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="8""#));
+        assert_eq!(svg.matches("<rect").count(), 5);
+        assert!(svg.contains(&format!(r#"<rect x="12" y="4" width="4" height="4" fill="{}"/>"#, hex(i_color))));
+
+        // each block used is embedded once, however many cells use it
+        let svg = render(&board, SvgRenderer::skinned()).unwrap();
+        assert_eq!(svg.matches("<symbol").count(), 2);
+        assert_eq!(svg.matches("<use").count(), 5);
+        assert!(svg.contains(r##"<use href="#s0I" x="12" y="4" width="4" height="4"/>"##));
+        assert!(svg.find("<defs>").unwrap() < svg.find("<use").unwrap());
+    }
+}
@@ -0,0 +1,132 @@
+use super::{SkinMode, RED_WEIGHT, GREEN_WEIGHT, BLUE_WEIGHT};
+use super::draw::Skins;
+
+use image::Rgba;
+
+// assigns a single skin to every cell so that pieces within the same region share a skin
+// returns None when every piece is free to pick its own skin
+pub fn assign_skins(mode: SkinMode, num_regions: usize, avg_pixel_grid: &[Rgba<u8>], board_width: usize, board_height: usize, skins: &Skins) -> Option<Vec<usize>> {
+    let num_regions = num_regions.clamp(1, avg_pixel_grid.len().max(1));
+    let labels = match mode {
+        SkinMode::PerPiece => return None,
+        SkinMode::Single => return Some(vec![best_skin(avg_pixel_grid, skins); avg_pixel_grid.len()]),
+        SkinMode::PerRegion => kmeans(avg_pixel_grid, num_regions),
+        SkinMode::PerBand => (0..board_height)
+            .flat_map(|y| std::iter::repeat_n(y * num_regions / board_height, board_width))
+            .collect(),
+    };
+
+    // each region uses whichever skin has blocks closest to the region's cell colors
+    let region_skins: Vec<usize> = (0..num_regions)
+        .map(|region| {
+            let region_colors: Vec<_> = labels
+                .iter()
+                .zip(avg_pixel_grid)
+                .filter(|(label, _)| **label == region)
+                .map(|(_, color)| *color)
+                .collect();
+            best_skin(&region_colors, skins)
+        })
+        .collect();
+
+    Some(labels.into_iter().map(|label| region_skins[label]).collect())
+}
+
+fn best_skin(colors: &[Rgba<u8>], skins: &Skins) -> usize {
+    let mut best_skin_id = 0;
+    let mut best_diff = f64::MAX;
+    for skin in skins {
+        let block_colors: Vec<_> = skin.as_array_ref().iter().map(|block| block.get_average_pixel()).collect();
+        let diff: f64 = colors
+            .iter()
+            .map(|color| block_colors.iter().map(|block| color_diff(color, block)).fold(f64::MAX, f64::min))
+            .sum();
+        if diff < best_diff {
+            best_diff = diff;
+            best_skin_id = skin.id();
+        }
+    }
+    best_skin_id
+}
+
+// clusters colors into k groups, returning the group of each color
+fn kmeans(colors: &[Rgba<u8>], k: usize) -> Vec<usize> {
+    const ITERATIONS: usize = 16;
+
+    // spread the initial centroids over the colors sorted by brightness so the result is deterministic
+    let mut sorted = colors.to_vec();
+    sorted.sort_by_key(|p| u32::from(p[0]) + u32::from(p[1]) + u32::from(p[2]));
+    let mut centroids: Vec<Rgba<u8>> = (0..k)
+        .map(|i| sorted[(2 * i + 1) * sorted.len() / (2 * k)])
+        .collect();
+
+    let mut labels = vec![0; colors.len()];
+    for _ in 0..ITERATIONS {
+        // assign each color to its nearest centroid
+        for (label, color) in labels.iter_mut().zip(colors) {
+            *label = (0..k)
+                .min_by(|a, b| color_diff(color, &centroids[*a]).total_cmp(&color_diff(color, &centroids[*b])))
+                .expect("there must be at least one centroid");
+        }
+
+        // move each centroid to the mean of its colors
+        let mut sums = vec![[0u64; 4]; k];
+        let mut counts = vec![0u64; k];
+        for (label, color) in labels.iter().zip(colors) {
+            for channel in 0..4 {
+                sums[*label][channel] += u64::from(color[channel]);
+            }
+            counts[*label] += 1;
+        }
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            if count != 0 {
+                *centroid = sum.map(|x| u8::try_from(x / count).expect("average should be in range")).into();
+            }
+        }
+    }
+
+    labels
+}
+
+fn color_diff(a: &Rgba<u8>, b: &Rgba<u8>) -> f64 {
+    let diff = [0, 1, 2].map(|i| f64::from(i32::from(a[i]) - i32::from(b[i])).powi(2));
+    diff[0] * RED_WEIGHT + diff[1] * GREEN_WEIGHT + diff[2] * BLUE_WEIGHT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::{BlockSkin, test_skin};
+    use image::DynamicImage;
+
+    #[test]
+    fn test_kmeans_separates_colors() {
+        let dark = Rgba([10, 10, 10, 255]);
+        let light = Rgba([240, 240, 240, 255]);
+        let colors = vec![dark, light, dark, light, light, dark];
+
+        let labels = kmeans(&colors, 2);
+        assert_eq!(labels[0], labels[2]);
+        assert_eq!(labels[0], labels[5]);
+        assert_eq!(labels[1], labels[3]);
+        assert_eq!(labels[1], labels[4]);
+        assert_ne!(labels[0], labels[1]);
+    }
+
+    #[test]
+    fn test_single_uses_one_skin() {
+        // the test skin is colorful, while the second skin is all black
+        let mut skin = test_skin();
+        skin.resize(2, 2);
+        let skins = vec![skin, BlockSkin::from_image(DynamicImage::new_rgb8(18, 2), "dark".to_string(), 1)];
+
+        let colors = vec![Rgba([0, 0, 0, 255]); 4];
+        assert_eq!(assign_skins(SkinMode::Single, 2, &colors, 2, 2, &skins), Some(vec![1; 4]));
+    }
+
+    #[test]
+    fn test_per_piece_has_no_regions() {
+        let colors = vec![Rgba([0, 0, 0, 255]); 4];
+        assert!(assign_skins(SkinMode::PerPiece, 2, &colors, 2, 2, &Vec::new()).is_none());
+    }
+}
@@ -0,0 +1,105 @@
+use super::piece::Piece;
+
+use rand::Rng;
+
+// collects the scored candidates for a single cell and picks the winner
+// with a zero epsilon, the first candidate with the lowest diff always wins
+// otherwise, every candidate within `epsilon` (relative to the best diff) is equally likely to win
+pub struct Candidates {
+    entries: Vec<(Piece, usize, f64)>,
+    best_diff: f64,
+    epsilon: f64,
+}
+
+impl Candidates {
+    pub fn new(epsilon: f64) -> Candidates {
+        Candidates {
+            entries: Vec::new(),
+            best_diff: f64::MAX,
+            epsilon,
+        }
+    }
+
+    pub fn push(&mut self, piece: Piece, skin_id: usize, diff: f64) {
+        if self.epsilon <= 0.0 {
+            // only the strict best needs to be remembered
+            if diff < self.best_diff {
+                self.entries.clear();
+                self.entries.push((piece, skin_id, diff));
+            }
+        } else {
+            self.entries.push((piece, skin_id, diff));
+        }
+        self.best_diff = self.best_diff.min(diff);
+    }
+
+    // candidates with a diff above this can't win, given the ones pushed so far
+    pub fn threshold(&self) -> f64 {
+        near_tie_threshold(self.best_diff, self.epsilon)
+    }
+
+    pub fn choose(self, rng: &mut impl Rng) -> Option<(Piece, usize)> {
+        let threshold = self.threshold();
+        let mut near_ties: Vec<_> = self.entries
+            .into_iter()
+            .filter(|(_, _, diff)| *diff <= threshold)
+            .collect();
+
+        if near_ties.is_empty() {
+            return None;
+        }
+        let index = if near_ties.len() == 1 { 0 } else { rng.gen_range(0..near_ties.len()) };
+        let (piece, skin_id, _) = near_ties.swap_remove(index);
+        Some((piece, skin_id))
+    }
+}
+
+// the highest diff that still ties with `best_diff` within `epsilon`
+pub fn near_tie_threshold(best_diff: f64, epsilon: f64) -> f64 {
+    best_diff + best_diff.abs() * epsilon
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::piece::Cell;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_exact_picks_first_best() {
+        let cell = Cell { x: 0, y: 0 };
+        let mut candidates = Candidates::new(0.0);
+        candidates.push(Piece::Gray(cell), 0, 2.0);
+        candidates.push(Piece::Black(cell), 0, 1.0);
+        candidates.push(Piece::Black(cell), 1, 1.0);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(candidates.choose(&mut rng), Some((Piece::Black(cell), 0)));
+    }
+
+    #[test]
+    fn test_near_ties_are_reproducible() {
+        let cell = Cell { x: 0, y: 0 };
+        let pick = |seed| {
+            let mut candidates = Candidates::new(0.1);
+            for skin_id in 0..8 {
+                candidates.push(Piece::Black(cell), skin_id, 100.0 + skin_id as f64);
+            }
+            candidates.push(Piece::Gray(cell), 0, 500.0);
+            candidates.choose(&mut StdRng::seed_from_u64(seed)).unwrap()
+        };
+
+        for seed in 0..16 {
+            let (piece, skin_id) = pick(seed);
+            assert_eq!(piece, Piece::Black(cell));
+            assert!(skin_id < 8);
+            assert_eq!(pick(seed), (piece, skin_id));
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let candidates = Candidates::new(0.5);
+        assert!(candidates.choose(&mut StdRng::seed_from_u64(0)).is_none());
+    }
+}
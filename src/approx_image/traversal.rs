@@ -0,0 +1,126 @@
+use super::piece::Cell;
+
+use std::collections::BinaryHeap;
+
+use rand::{seq::SliceRandom, Rng};
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Traversal {
+    // column by column from the right, each column from the bottom up
+    #[default]
+    Scan,
+    // like scan, but every other column is walked from the top down
+    Serpentine,
+    // cells closest to the center of the board first, spreading outwards
+    CenterOut,
+    // a shuffled order, seeded from `--seed`
+    Random,
+}
+
+// the cells left to approximate, popped in the order of a traversal
+// every cell gets a fixed rank up front, so cells pushed back in later passes keep their place in the order
+pub struct CellHeap {
+    heap: BinaryHeap<(usize, Cell)>,
+    ranks: Vec<usize>,
+    board_width: usize,
+}
+
+impl CellHeap {
+    // the rng is only drawn from by the random traversal
+    pub fn new(traversal: Traversal, board_width: usize, board_height: usize, rng: &mut impl Rng) -> CellHeap {
+        CellHeap {
+            heap: BinaryHeap::with_capacity(board_width * board_height),
+            ranks: ranks(traversal, board_width, board_height, rng),
+            board_width,
+        }
+    }
+
+    pub fn push(&mut self, cell: Cell) {
+        self.heap.push((self.ranks[cell.y * self.board_width + cell.x], cell));
+    }
+
+    pub fn pop(&mut self) -> Option<Cell> {
+        self.heap.pop().map(|(_, cell)| cell)
+    }
+}
+
+// the rank of each cell indexed by `y * board_width + x`, where higher ranks are popped first
+fn ranks(traversal: Traversal, board_width: usize, board_height: usize, rng: &mut impl Rng) -> Vec<usize> {
+    // matches `Cell`'s own ordering, which is what the heap used before traversals could be picked
+    let scan = |x: usize, y: usize| x * board_height + y;
+    let cells = || (0..board_height).flat_map(move |y| (0..board_width).map(move |x| (x, y)));
+
+    match traversal {
+        Traversal::Scan => cells().map(|(x, y)| scan(x, y)).collect(),
+        Traversal::Serpentine => cells()
+            .map(|(x, y)| {
+                let flipped = (board_width - 1 - x) % 2 == 1;
+                scan(x, if flipped { board_height - 1 - y } else { y })
+            })
+            .collect(),
+        Traversal::CenterOut => {
+            // distances are doubled so the center of an even board stays whole
+            let distance = |x: usize, y: usize| (2 * x + 1).abs_diff(board_width).pow(2) + (2 * y + 1).abs_diff(board_height).pow(2);
+            let mut order: Vec<_> = cells().collect();
+            order.sort_by_key(|&(x, y)| (std::cmp::Reverse(distance(x, y)), scan(x, y)));
+            rank_by_order(&order, board_width)
+        }
+        Traversal::Random => {
+            let mut order: Vec<_> = cells().collect();
+            order.shuffle(rng);
+            rank_by_order(&order, board_width)
+        }
+    }
+}
+
+// turns a list of cells from last to first popped into ranks
+fn rank_by_order(order: &[(usize, usize)], board_width: usize) -> Vec<usize> {
+    let mut ranks = vec![0; order.len()];
+    for (rank, (x, y)) in order.iter().enumerate() {
+        ranks[y * board_width + x] = rank;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn pop_all(traversal: Traversal, width: usize, height: usize, seed: u64) -> Vec<Cell> {
+        let mut heap = CellHeap::new(traversal, width, height, &mut StdRng::seed_from_u64(seed));
+        for y in 0..height {
+            for x in 0..width {
+                heap.push(Cell { x, y });
+            }
+        }
+        std::iter::from_fn(|| heap.pop()).collect()
+    }
+
+    #[test]
+    fn test_scan_matches_cell_order() {
+        let mut cells: Vec<_> = (0..4).flat_map(|y| (0..5).map(move |x| Cell { x, y })).collect();
+        cells.sort_by(|a, b| b.cmp(a));
+        assert_eq!(pop_all(Traversal::Scan, 5, 4, 0), cells);
+    }
+
+    #[test]
+    fn test_traversal_orders() {
+        let serpentine = pop_all(Traversal::Serpentine, 3, 3, 0);
+        assert_eq!(&serpentine[..6], &[
+            Cell { x: 2, y: 2 }, Cell { x: 2, y: 1 }, Cell { x: 2, y: 0 },
+            Cell { x: 1, y: 0 }, Cell { x: 1, y: 1 }, Cell { x: 1, y: 2 },
+        ]);
+
+        let center_out = pop_all(Traversal::CenterOut, 3, 3, 0);
+        assert_eq!(center_out[0], Cell { x: 1, y: 1 });
+        assert!(center_out[5..].iter().all(|cell| cell.x != 1 && cell.y != 1));
+
+        // every cell is visited once, and the same seed gives the same order
+        let mut random = pop_all(Traversal::Random, 4, 4, 7);
+        assert_eq!(random, pop_all(Traversal::Random, 4, 4, 7));
+        random.sort();
+        random.dedup();
+        assert_eq!(random.len(), 16);
+    }
+}
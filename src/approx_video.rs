@@ -1,145 +1,825 @@
-use crate::approx_image;
+use crate::approx_image::{self, FitMode};
+use crate::approx_image::draw::SkinnedBoard;
+use crate::approx_image::live_preview::LivePreview;
 use crate::cli::{Config, GlobalData};
-use crate::utils::{check_command_result, progress_bar};
+use crate::ffmpeg;
+use crate::utils::{check_command_result, progress_bar, CancelledError, PartialFailure, ProgressReporter};
 
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use ffmpeg_next::format;
+use ffmpeg_next::{codec, filter, format, frame, media, software, Dictionary, Packet, Rational};
+use image::{DynamicImage, GrayImage, Luma, RgbImage, RgbaImage};
 use rayon::prelude::*;
+use tempfile::TempDir;
+use thiserror::Error;
+use tracing::{debug, info, info_span, warn};
 
-const SOURCE_IMG_DIR: &str = "video_sources";
-const APPROX_IMG_DIR: &str = "video_approx";
-const AUDIO_PATH: &str = "video_approx/audio.wav";
+const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+pub const DEFAULT_CRF: u8 = 10;
 
-pub fn run(source: &Path, output: &Path, config: &Config, glob: &GlobalData, video_config: &VideoConfig) -> Result<()> {
-    let source_path = source.to_str().expect("failed to convert source path to string");
-    let output_path = output.to_str().expect("failed to convert output path to string");
+#[derive(Debug, Error)]
+pub enum VideoError {
+    #[error("Output file {0} already exists")]
+    OutputExists(PathBuf),
 
-    println!("Approximating video with {}x{} dimensions using {}x{} board", video_config.image_width, video_config.image_height, config.board_width, config.board_height);
-    println!("Using {} fps", video_config.fps);
+    #[error("No video stream found in {0}")]
+    MissingVideoStream(PathBuf),
 
-    // use ffmpeg to generate a directory full of images
-    // make sure those images correspond to the board dimenisions and blockskin dimensions
-    println!("Generating source images from {source_path}...");
-    let gen_image_command = Command::new("ffmpeg")
+    #[error("{0} encodes from frames uploaded to the device, which needs `--use-system-ffmpeg`")]
+    NeedsSystemFfmpeg(&'static str),
+}
+
+pub fn run(source: &Path, output: &Path, config: &Config, glob: &GlobalData, video_config: &VideoConfig) -> Result<VideoTimings> {
+    let mut timings = VideoTimings::default();
+    // the frame directories are removed however the run ends, including on errors and ctrl-c
+    let dirs = VideoDirs::new(video_config)?;
+    let _span = info_span!("approx_video", source = %source.display()).entered();
+    info!(
+        width = video_config.image_width,
+        height = video_config.image_height,
+        board_width = config.board_width,
+        board_height = config.board_height,
+        fps = video_config.fps,
+        frame_skip = video_config.frame_skip,
+        "approximating video"
+    );
+
+    let stage = info_span!("extract").entered();
+    info!("generating source images");
+    let start = Instant::now();
+    extract_frames(source, video_config, config.fit, &dirs.sources())?;
+
+    // the audio is copied straight from the source while encoding, or extracted to its own file for the ffmpeg command
+    let audio_path = dirs.audio();
+    let audio = if !video_config.has_audio {
+        info!("skipping audio, the output will be silent");
+        None
+    } else if video_config.use_system_ffmpeg {
+        extract_audio(source, &audio_path)?.then_some(audio_path.as_path())
+    } else {
+        Some(source)
+    };
+    timings.extract = start.elapsed();
+    drop(stage);
+
+    // approximate the source images
+    let stage = info_span!("approx").entered();
+    let start = Instant::now();
+    let all_frames = frame_paths(&dirs.sources())?;
+    let frames: Vec<_> = all_frames.iter().step_by(video_config.frame_skip).cloned().collect();
+    let num_frames = frames.len();
+    info!(num_frames, total_frames = all_frames.len(), "approximating source images");
+    let pb = progress_bar(num_frames)?;
+    pb.set_message("Approximating source images...");
+    let reporter = ProgressReporter::start(pb);
+    let failed_frames = AtomicUsize::new(0);
+    // frames are only shown in the live preview once they're done, rather than while they're being filled
+    let live_preview = config.live_preview.clone();
+    let config = &Config { live_preview: None, ..config.clone() };
+    let approx_frame = |source_path: &PathBuf, previous: Option<&SkinnedBoard>| {
+        let board = approx_frame(source_path, config, glob, previous, &dirs, video_config.heatmap.is_some(), live_preview.as_ref());
+        if let Err(e) = &board {
+            // a failed frame is replaced by its neighbor instead of aborting the whole video
+            failed_frames.fetch_add(1, Ordering::Relaxed);
+            warn!(frame = %source_path.display(), "failed to approximate frame: {e:#}");
+        }
+
+        // make sure the progress bar is updated
+        let frames_done = reporter.counter().inc();
+        debug!(frames_done, num_frames, "approximated frame");
+        board.ok()
+    };
+    match config.temporal_bias {
+        // each frame is seeded by the one before it, so frames have to be approximated in order
+        Some(_) => {
+            let mut previous = None;
+            for source_path in &frames {
+                if config.cancel.is_cancelled() {
+                    break;
+                }
+                if let Some(board) = approx_frame(source_path, previous.as_ref()) {
+                    previous = Some(board);
+                }
+            }
+        }
+        None => frames
+            .par_iter()
+            .for_each(|source_path| {
+                // skip the remaining frames once cancelled
+                if config.cancel.is_cancelled() {
+                    return;
+                }
+                approx_frame(source_path, None);
+            }),
+    }
+    let pb = reporter.stop();
+    if config.cancel.is_cancelled() {
+        pb.abandon_with_message("Cancelled approximating source images");
+        warn!("cancelled approximating source images");
+        return Err(CancelledError.into());
+    }
+    let failed_frames = failed_frames.into_inner();
+    timings.frames = pb.position() - failed_frames as u64;
+    pb.finish_with_message("Done approximating source images!");
+    if failed_frames == num_frames {
+        return Err(PartialFailure { failed: failed_frames, total: num_frames, items: "frames" }.into());
+    }
+    hold_missing_frames(&all_frames, &dirs.approx())?;
+    if video_config.heatmap.is_some() {
+        hold_missing_frames(&all_frames, &dirs.heatmap())?;
+    }
+    timings.approx = start.elapsed();
+    drop(stage);
+
+    // combine the approximated images and audio for a final video
+    let stage = info_span!("encode").entered();
+    info!("combining approximated images and audio");
+    let start = Instant::now();
+    encode(video_config, &dirs.approx(), audio, output)?;
+    if let Some(heatmap_path) = &video_config.heatmap {
+        info!(path = %heatmap_path.display(), "encoding diff heatmap");
+        encode(video_config, &dirs.heatmap(), None, heatmap_path)?;
+    }
+    timings.encode = start.elapsed();
+    drop(stage);
+
+    info!(output = %output.display(), "done");
+    timings.log();
+
+    // the output is complete, but with the failed frames held over
+    if failed_frames != 0 {
+        return Err(PartialFailure { failed: failed_frames, total: num_frames, items: "frames" }.into());
+    }
+    Ok(timings)
+}
+
+pub fn init(source: &Path, output: &Path, config: &mut Config, glob: &mut GlobalData, options: &VideoOptions) -> Result<VideoConfig> {
+    ffmpeg_next::init()?;
+
+    // make sure the output file is not there
+    if output.exists() {
+        Err(VideoError::OutputExists(output.to_path_buf()))?;
+    }
+
+    // load config
+    let mut video_config = VideoConfig::new(source)?;
+    if let Some(fps) = options.fps {
+        video_config.fps = fps;
+    }
+    video_config.frame_skip = usize::try_from(options.frame_skip)?.max(1);
+    video_config.encoder = options.encoder;
+    video_config.crf = options.crf;
+    video_config.preset.clone_from(&options.preset);
+    video_config.use_system_ffmpeg = options.use_system_ffmpeg;
+    video_config.has_audio &= !options.mute;
+    video_config.heatmap.clone_from(&options.heatmap);
+    video_config.keep_temp = options.keep_temp;
+    if video_config.encoder != VideoEncoder::Libx264 && ffmpeg_next::encoder::find_by_name(video_config.encoder.name()).is_none() {
+        warn!(encoder = video_config.encoder.name(), "encoder is not available, falling back to libx264");
+        video_config.encoder = VideoEncoder::Libx264;
+    }
+
+    // modify the config based on resized skins
+    (video_config.image_width, video_config.image_height) = approx_image::fit_small_source(video_config.image_width, video_config.image_height, config)?;
+    approx_image::fit_skins(&mut glob.skins, video_config.image_width, video_config.image_height, config)?;
+    video_config.image_width = glob.skin_width() * u32::try_from(config.board_width)?;
+    video_config.image_height = glob.skin_height() * u32::try_from(config.board_height)?;
+
+    Ok(video_config)
+}
+
+// use ffmpeg to generate a directory full of images
+// make sure those images correspond to the board dimensions and blockskin dimensions
+fn extract_frames(source: &Path, video_config: &VideoConfig, fit: FitMode, dir: &Path) -> Result<()> {
+    let filters = frame_filters(video_config, fit);
+    if !video_config.use_system_ffmpeg {
+        return decode_frames(source, &filters, dir);
+    }
+
+    let gen_image_command = ffmpeg::command()
         .arg("-i")
-        .arg(source_path)
+        .arg(source)
         .arg("-vf")
-        .arg(format!("fps={},scale={}x{}", video_config.fps, video_config.image_width, video_config.image_height))
+        .arg(filters)
         .arg("-start_number")
         .arg("0")
-        .arg(format!("{SOURCE_IMG_DIR}/%d.png"))
+        .arg(dir.join("%d.png"))
         .output()?;
-    check_command_result(&gen_image_command)?;
+    check_command_result(&gen_image_command)
+}
 
-    // use ffmpeg to generate the audio file
-    println!("Generating audio file from {source_path}...");
-    let gen_audio_command = Command::new("ffmpeg")
+// extracts the source's audio with the ffmpeg command for its encode, returning whether there is any to use
+// audio that ffmpeg can't extract is left out with a warning instead of failing the whole video
+fn extract_audio(source: &Path, audio_path: &Path) -> Result<bool> {
+    info!("generating audio file");
+    let gen_audio_command = ffmpeg::command()
         .arg("-i")
-        .arg(source_path)
-        .arg(AUDIO_PATH)
+        .arg(source)
+        .arg(audio_path)
         .output()?;
-    check_command_result(&gen_audio_command)?;
+    if let Err(e) = check_command_result(&gen_audio_command) {
+        warn!("failed to extract audio, the output will be silent: {e}");
+        return Ok(false);
+    }
+    Ok(true)
+}
 
-    // approximate the source images
-    let images: Vec<_> = fs::read_dir(SOURCE_IMG_DIR)?
+// resamples the source to the video's fps and fits it to the board's image size
+fn frame_filters(video_config: &VideoConfig, fit: FitMode) -> String {
+    let (width, height) = (video_config.image_width, video_config.image_height);
+    let scale = match fit {
+        FitMode::Stretch => format!("scale={width}x{height}"),
+        // keep the source's aspect ratio and pad the rest with black bars, centered
+        FitMode::Letterbox => format!("scale={width}:{height}:force_original_aspect_ratio=decrease,pad={width}:{height}:(ow-iw)/2:(oh-ih)/2"),
+    };
+    format!("fps={},{scale}", video_config.fps)
+}
+
+// decodes the source's frames with ffmpeg's libraries instead of the command line, running them through the same filters
+fn decode_frames(source: &Path, filters: &str, dir: &Path) -> Result<()> {
+    let mut input = format::input(source)?;
+    let stream = input.streams().best(media::Type::Video).ok_or(ffmpeg_next::Error::StreamNotFound)?;
+    let stream_index = stream.index();
+    let time_base = stream.time_base();
+    let mut decoder = codec::context::Context::from_parameters(stream.parameters())?.decoder().video()?;
+
+    // frames go into the "in" buffer and come out of the "out" sink filtered and converted to rgb
+    let mut graph = filter::Graph::new();
+    let pixel_format = decoder.format().descriptor().map_or("yuv420p", |descriptor| descriptor.name());
+    let args = format!(
+        "video_size={}x{}:pix_fmt={pixel_format}:time_base={time_base}:pixel_aspect={}",
+        decoder.width(),
+        decoder.height(),
+        decoder.aspect_ratio(),
+    );
+    graph.add(&filter::find("buffer").expect("ffmpeg is missing the buffer filter"), "in", &args)?;
+    graph.add(&filter::find("buffersink").expect("ffmpeg is missing the buffersink filter"), "out", "")?;
+    graph.output("in", 0)?.input("out", 0)?.parse(&format!("{filters},format=rgb24"))?;
+    graph.validate()?;
+
+    let mut frame_index = 0;
+    let mut decoded = frame::Video::empty();
+    let mut filtered = frame::Video::empty();
+    let mut drain = |decoder: &mut ffmpeg_next::decoder::Video, graph: &mut filter::Graph, eof: bool| -> Result<()> {
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let timestamp = decoded.timestamp();
+            decoded.set_pts(timestamp);
+            graph.get("in").expect("graph should have an input").source().add(&decoded)?;
+        }
+        if eof {
+            graph.get("in").expect("graph should have an input").source().flush()?;
+        }
+        while graph.get("out").expect("graph should have an output").sink().frame(&mut filtered).is_ok() {
+            save_frame(&filtered, frame_index, dir)?;
+            frame_index += 1;
+        }
+        Ok(())
+    };
+
+    for (stream, packet) in input.packets() {
+        if stream.index() == stream_index {
+            decoder.send_packet(&packet)?;
+            drain(&mut decoder, &mut graph, false)?;
+        }
+    }
+    decoder.send_eof()?;
+    drain(&mut decoder, &mut graph, true)
+}
+
+// saves an rgb24 frame, whose rows may be padded past the image's width
+fn save_frame(frame: &frame::Video, frame_index: usize, dir: &Path) -> Result<()> {
+    let (width, height) = (frame.width(), frame.height());
+    let row_len = width as usize * 3;
+    let pixels: Vec<u8> = frame
+        .data(0)
+        .chunks(frame.stride(0))
+        .take(height as usize)
+        .flat_map(|row| &row[..row_len])
+        .copied()
         .collect();
-    let pb = progress_bar(images.len())?;
-    pb.set_message("Approximating source images...");
-    images
-        .into_par_iter()
-        .for_each(|image| {
-            let source_path = image.expect("failed to read source image").path();
-            let source_path_without_dir = source_path.file_name().expect("failed to get source image path without directory");
-            let approx_path = format!("{}/{}", APPROX_IMG_DIR, source_path_without_dir.to_str().expect("failed to convert source image path to string"));
-
-            let source_img = image::open(source_path).expect("failed to load source image");
-            let approx_img = approx_image::approx(&source_img, config, glob).expect("failed to approximate image");
-            approx_img.save(approx_path).expect("failed to save approx image");
-
-            // make sure the progress bar is updated
-            pb.inc(1);
-        });
-    pb.finish_with_message("Done approximating source images!");
+    let img = RgbImage::from_raw(width, height, pixels).expect("frame should have enough pixels");
+    img.save(dir.join(format!("{frame_index}.png")))?;
+    Ok(())
+}
 
-    // combine the approximated images and audio for a final video
-    println!("Combining approximated images and audio...");
-    let combine_command = Command::new("ffmpeg")
+// encodes a directory of frames, with the audio if there is any, using the configured encoder
+fn encode(video_config: &VideoConfig, frames_dir: &Path, audio: Option<&Path>, output_path: &Path) -> Result<()> {
+    let encode_with = |encoder| {
+        if video_config.use_system_ffmpeg {
+            check_command_result(&encode_command(video_config, encoder, frames_dir, audio, output_path).output()?)
+        } else {
+            encode_frames(video_config, encoder, frames_dir, audio, output_path)
+        }
+    };
+    if let Err(e) = encode_with(video_config.encoder) {
+        // hardware encoders can be compiled in without a usable device, so retry on the cpu
+        if video_config.encoder == VideoEncoder::Libx264 {
+            return Err(e);
+        }
+        warn!(encoder = video_config.encoder.name(), "encoding failed, falling back to libx264: {e:#}");
+        encode_with(VideoEncoder::Libx264)?;
+    }
+    Ok(())
+}
+
+// encodes a directory of frames with ffmpeg's libraries instead of the command line
+// the best audio stream of `audio` is copied over as is, and cut off where the video ends
+fn encode_frames(video_config: &VideoConfig, encoder: VideoEncoder, frames_dir: &Path, audio: Option<&Path>, output_path: &Path) -> Result<()> {
+    if encoder == VideoEncoder::HevcVaapi {
+        Err(VideoError::NeedsSystemFfmpeg(encoder.name()))?;
+    }
+    let codec = ffmpeg_next::encoder::find_by_name(encoder.name()).ok_or(ffmpeg_next::Error::EncoderNotFound)?;
+    let (width, height) = (video_config.image_width, video_config.image_height);
+    let frame_rate = Rational(video_config.fps, 1);
+
+    let mut output = format::output(output_path)?;
+    let mut video_encoder = codec::context::Context::new_with_codec(codec).encoder().video()?;
+    video_encoder.set_width(width);
+    video_encoder.set_height(height);
+    video_encoder.set_format(format::Pixel::YUV420P);
+    video_encoder.set_frame_rate(Some(frame_rate));
+    video_encoder.set_time_base(frame_rate.invert());
+    if output.format().flags().contains(format::Flags::GLOBAL_HEADER) {
+        video_encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+    let mut options = Dictionary::new();
+    options.set(encoder.quality_option(), &video_config.crf.to_string());
+    if let Some(preset) = &video_config.preset {
+        options.set("preset", preset);
+    }
+    let mut video_encoder = video_encoder.open_with(options)?;
+    let video_index = {
+        let mut stream = output.add_stream(codec)?;
+        stream.set_parameters(&video_encoder);
+        stream.index()
+    };
+
+    let mut audio_input = audio.map(format::input).transpose()?;
+    let audio_stream = audio_input
+        .as_ref()
+        .and_then(|input| input.streams().best(media::Type::Audio))
+        .map(|stream| (stream.index(), stream.time_base(), stream.parameters()));
+    let mut audio_index = None;
+    if let Some((_, _, parameters)) = audio_stream.as_ref() {
+        let mut stream = output.add_stream(ffmpeg_next::encoder::find(codec::Id::None))?;
+        stream.set_parameters(parameters.clone());
+        audio_index = Some(stream.index());
+    }
+
+    // copied audio keeps the source container's codec tag, which some output containers refuse
+    if let Err(e) = output.write_header() {
+        if audio_index.is_none() {
+            return Err(e.into());
+        }
+        warn!("the audio can't be copied into the output, it will be silent: {e}");
+        drop(output);
+        return encode_frames(video_config, encoder, frames_dir, None, output_path);
+    }
+    let stream_time_base = |output: &format::context::Output, index: usize| output.stream(index).map_or(Rational(0, 1), |stream| stream.time_base());
+    let video_time_base = stream_time_base(&output, video_index);
+    let audio_time_base = audio_index.map(|index| stream_time_base(&output, index));
+
+    // only the audio packets up to the video written so far are copied, keeping the two interleaved
+    let mut audio_packets = match (audio_input.as_mut(), audio_stream) {
+        (Some(input), Some((stream_index, time_base, _))) => Some((
+            input.packets().filter(move |(stream, _)| stream.index() == stream_index).map(|(_, packet)| packet).peekable(),
+            time_base,
+        )),
+        _ => None,
+    };
+
+    let mut scaler = None;
+    for (index, frame_path) in frame_paths(frames_dir)?.iter().enumerate() {
+        let img = image::open(frame_path)?.into_rgb8();
+        let rgb = rgb_frame(&img);
+        if scaler.is_none() {
+            scaler = Some(software::scaling::Context::get(format::Pixel::RGB24, img.width(), img.height(), format::Pixel::YUV420P, width, height, software::scaling::Flags::BILINEAR)?);
+        }
+        // encoders can hold on to the frames they're sent, so each one gets its own
+        let mut yuv = frame::Video::empty();
+        scaler.as_mut().expect("scaler was just created").run(&rgb, &mut yuv)?;
+        yuv.set_pts(Some(i64::try_from(index)?));
+        video_encoder.send_frame(&yuv)?;
+        write_encoded(&mut video_encoder, &mut output, video_index, video_time_base)?;
+
+        let video_end = f64::from(u32::try_from(index + 1)?) / f64::from(video_config.fps);
+        if let (Some((packets, input_time_base)), Some(audio_index), Some(audio_time_base)) = (audio_packets.as_mut(), audio_index, audio_time_base) {
+            let input_time_base = *input_time_base;
+            while let Some(mut packet) = packets.next_if(|packet| packet.pts().is_none_or(|pts| pts_seconds(pts, input_time_base) < video_end)) {
+                packet.rescale_ts(input_time_base, audio_time_base);
+                packet.set_position(-1);
+                packet.set_stream(audio_index);
+                packet.write_interleaved(&mut output)?;
+            }
+        }
+    }
+    video_encoder.send_eof()?;
+    write_encoded(&mut video_encoder, &mut output, video_index, video_time_base)?;
+    output.write_trailer()?;
+    Ok(())
+}
+
+// copies an image into an rgb24 frame, whose rows may be padded past the image's width
+fn rgb_frame(img: &RgbImage) -> frame::Video {
+    let mut frame = frame::Video::new(format::Pixel::RGB24, img.width(), img.height());
+    let stride = frame.stride(0);
+    let row_len = img.width() as usize * 3;
+    for (frame_row, img_row) in frame.data_mut(0).chunks_mut(stride).zip(img.as_raw().chunks_exact(row_len)) {
+        frame_row[..row_len].copy_from_slice(img_row);
+    }
+    frame
+}
+
+// writes out every packet the encoder has finished so far
+fn write_encoded(encoder: &mut ffmpeg_next::encoder::video::Encoder, output: &mut format::context::Output, stream_index: usize, time_base: Rational) -> Result<()> {
+    let mut packet = Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(stream_index);
+        packet.rescale_ts(encoder.time_base(), time_base);
+        packet.write_interleaved(output)?;
+    }
+    Ok(())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn pts_seconds(pts: i64, time_base: Rational) -> f64 {
+    pts as f64 * f64::from(time_base)
+}
+
+// combines a directory of frames and the audio, if there is any, into the output with the given encoder
+fn encode_command(video_config: &VideoConfig, encoder: VideoEncoder, frames_dir: &Path, audio: Option<&Path>, output_path: &Path) -> Command {
+    let mut command = ffmpeg::command();
+    let mut filter = format!("scale={}:{}", video_config.image_width, video_config.image_height);
+    if encoder == VideoEncoder::HevcVaapi {
+        // vaapi encodes from frames uploaded to the device
+        command.arg("-vaapi_device").arg(VAAPI_DEVICE);
+        filter.push_str(",format=nv12,hwupload");
+    }
+    command
         .arg("-framerate")
         .arg(format!("{}", video_config.fps))
         .arg("-i")
-        .arg(format!("{APPROX_IMG_DIR}/%d.png"))
-        .arg("-i")
-        .arg(AUDIO_PATH)
+        .arg(frames_dir.join("%d.png"));
+    if let Some(audio_path) = audio {
+        command.arg("-i").arg(audio_path);
+    }
+    command
         .arg("-c:v")
-        .arg("libx264")
-        .arg("-crf")
-        .arg("10")
-        .arg("-vf")
-        .arg(format!("scale={}:{}", video_config.image_width, video_config.image_height))
-        .arg("-c:a")
-        .arg("aac")
-        .arg("-shortest")
-        .arg(output_path)
-        .output()?;
-    check_command_result(&combine_command)?;
+        .arg(encoder.name())
+        .arg(format!("-{}", encoder.quality_option()))
+        .arg(format!("{}", video_config.crf));
+    match (&video_config.preset, encoder) {
+        (Some(_), VideoEncoder::HevcVaapi) => warn!("hevc_vaapi has no presets, ignoring --preset"),
+        (Some(preset), _) => {
+            command.arg("-preset").arg(preset);
+        }
+        (None, _) => (),
+    }
+    command.arg("-vf").arg(filter);
+    if audio.is_some() {
+        command.arg("-c:a").arg("aac").arg("-shortest");
+    }
+    command.arg(output_path);
+    command
+}
+
+// where a source frame's counterpart is saved in another frame directory
+fn frame_path(dir: &Path, source_path: &Path) -> PathBuf {
+    let source_path_without_dir = source_path.file_name().expect("failed to get source image path without directory");
+    dir.join(source_path_without_dir)
+}
+
+fn approx_frame<'a>(source_path: &Path, config: &Config, glob: &'a GlobalData, previous: Option<&SkinnedBoard>, dirs: &VideoDirs, heatmap: bool, live_preview: Option<&LivePreview>) -> Result<SkinnedBoard<'a>> {
+    let mut source_img = approx_image::load::open(source_path)?;
+    approx_image::preprocess::apply(&mut source_img, config)?;
+    let board = approx_image::approx_board_seeded(&source_img, config, glob, previous)?;
+    let approx_img = approx_image::draw::draw(&board)?;
+    approx_img.save(frame_path(&dirs.approx(), source_path))?;
+    if heatmap {
+        diff_heatmap(&source_img, &approx_img, board.board_width(), board.board_height())?.save(frame_path(&dirs.heatmap(), source_path))?;
+    }
+    if let Some(live_preview) = live_preview {
+        live_preview.show(approx_img.into_rgba8());
+    }
+    Ok(board)
+}
 
-    cleanup()?;
+// shades each cell by the average per-channel difference between its blocks and the source underneath, so brighter cells match worse
+fn diff_heatmap(source_img: &DynamicImage, approx_img: &DynamicImage, board_width: usize, board_height: usize) -> Result<GrayImage> {
+    let (source, approx) = (source_img.to_rgb8(), approx_img.to_rgb8());
+    let cell_width = (source.width() / u32::try_from(board_width)?).max(1);
+    let cell_height = (source.height() / u32::try_from(board_height)?).max(1);
+    let cell_index = |x: u32, y: u32| (y / cell_height) as usize * board_width + (x / cell_width) as usize;
 
-    println!("Done!");
+    let mut totals = vec![(0u64, 0u64); board_width * board_height];
+    for (x, y, pixel) in source.enumerate_pixels() {
+        let Some((total, count)) = totals.get_mut(cell_index(x, y)) else {
+            continue;
+        };
+        for (channel, approx_channel) in pixel.0.iter().zip(approx.get_pixel(x, y).0) {
+            *total += u64::from(channel.abs_diff(approx_channel));
+            *count += 1;
+        }
+    }
 
+    let shades: Vec<u8> = totals
+        .into_iter()
+        .map(|(total, count)| u8::try_from(total / count.max(1)).unwrap_or(u8::MAX))
+        .collect();
+    Ok(GrayImage::from_fn(source.width(), source.height(), |x, y| {
+        Luma([shades.get(cell_index(x, y)).copied().unwrap_or(0)])
+    }))
+}
+
+// skipped and failed frames repeat the last approximated frame in `dir` so the output keeps its length and fps
+// frames before the first approximated one repeat it instead
+fn hold_missing_frames(all_frames: &[PathBuf], dir: &Path) -> Result<()> {
+    let approx_paths: Vec<_> = all_frames.iter().map(|path| frame_path(dir, path)).collect();
+    let Some(first) = approx_paths.iter().position(|path| path.exists()) else {
+        return Ok(());
+    };
+
+    let mut held = first;
+    for (index, path) in approx_paths.iter().enumerate() {
+        if path.exists() {
+            held = index;
+        } else {
+            fs::copy(&approx_paths[held], path)?;
+        }
+    }
     Ok(())
 }
 
-pub fn init(source: &Path, output: &Path, config: &Config, glob: &mut GlobalData) -> Result<VideoConfig> {
-    ffmpeg_next::init()?;
+// the extracted frames in playback order
+// frames are named by their index, so sort them numerically
+fn frame_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut frame_paths = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    frame_paths.sort_by_key(|path| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<usize>().ok())
+    });
+    Ok(frame_paths)
+}
 
-    // make sure the prerequisite directories exist and are empty
-    if Path::new(SOURCE_IMG_DIR).exists() {
-        fs::remove_dir_all(SOURCE_IMG_DIR)?;
+// a run's own working directory, so concurrent runs don't overwrite each other's frames
+// it's removed when dropped, unless `--keep-temp` is set for debugging
+struct VideoDirs {
+    root: PathBuf,
+    _temp: Option<TempDir>,
+}
+
+impl VideoDirs {
+    fn new(video_config: &VideoConfig) -> Result<VideoDirs> {
+        let temp = tempfile::Builder::new().prefix("image-to-tetris-").tempdir()?;
+        let (root, temp) = if video_config.keep_temp {
+            let root = temp.keep();
+            info!(path = %root.display(), "keeping temp files");
+            (root, None)
+        } else {
+            (temp.path().to_path_buf(), Some(temp))
+        };
+
+        let dirs = VideoDirs { root, _temp: temp };
+        fs::create_dir(dirs.sources())?;
+        fs::create_dir(dirs.approx())?;
+        if video_config.heatmap.is_some() {
+            fs::create_dir(dirs.heatmap())?;
+        }
+        Ok(dirs)
     }
-    if Path::new(APPROX_IMG_DIR).exists() {
-        fs::remove_dir_all(APPROX_IMG_DIR)?;
+
+    // frames extracted from the source
+    fn sources(&self) -> PathBuf {
+        self.root.join("sources")
     }
-    fs::create_dir(SOURCE_IMG_DIR)?;
-    fs::create_dir(APPROX_IMG_DIR)?;
 
-    // make sure the output file is not there
-    assert!(!output.exists(), "output file already exists");
+    fn approx(&self) -> PathBuf {
+        self.root.join("approx")
+    }
 
-    // load config
-    let mut video_config = VideoConfig::new(source)?;
+    fn heatmap(&self) -> PathBuf {
+        self.root.join("heatmap")
+    }
 
-    // modify the config based on resized skins
-    approx_image::draw::resize_skins(&mut glob.skins, video_config.image_width, video_config.image_height, config.board_width, config.board_height).unwrap();
-    video_config.image_width = glob.skin_width() * u32::try_from(config.board_width)?;
-    video_config.image_height = glob.skin_height() * u32::try_from(config.board_height)?;
+    fn audio(&self) -> PathBuf {
+        self.root.join("audio.wav")
+    }
+}
 
-    Ok(video_config)
+// wall-clock time spent in each stage of the video pipeline
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VideoTimings {
+    // extracting frames and audio from the source with ffmpeg
+    pub extract: Duration,
+    // approximating every extracted frame
+    pub approx: Duration,
+    // combining the approximated frames and audio into the output
+    pub encode: Duration,
+    // number of frames approximated
+    pub frames: u64,
 }
 
-fn cleanup() -> Result<()> {
-    fs::remove_dir_all(SOURCE_IMG_DIR)?;
-    fs::remove_dir_all(APPROX_IMG_DIR)?;
-    Ok(())
+impl VideoTimings {
+    pub fn total(&self) -> Duration {
+        self.extract + self.approx + self.encode
+    }
+
+    fn log(&self) {
+        info!(
+            extract = ?self.extract,
+            approx = ?self.approx,
+            encode = ?self.encode,
+            total = ?self.total(),
+            frames = self.frames,
+            "video timings"
+        );
+    }
 }
 
-// contains important video metadata
+// a single approximated frame and when it should be shown
+#[derive(Debug, Clone)]
+pub struct ApproxFrame {
+    pub image: RgbaImage,
+    pub timestamp: Duration,
+}
+
+// approximates a video frame by frame without encoding it, for applications that display the frames themselves
+// `init` must be called first so the skins match the video; the extracted frames are removed when the session is dropped
+pub struct ApproxVideoSession<'a> {
+    config: &'a Config,
+    glob: &'a GlobalData,
+    fps: i32,
+    frame_skip: usize,
+    frame_paths: Vec<PathBuf>,
+    _dirs: VideoDirs,
+}
+
+impl<'a> ApproxVideoSession<'a> {
+    pub fn new(source: &Path, config: &'a Config, glob: &'a GlobalData, video_config: &VideoConfig) -> Result<ApproxVideoSession<'a>> {
+        let dirs = VideoDirs::new(video_config)?;
+        extract_frames(source, video_config, config.fit, &dirs.sources())?;
+
+        Ok(ApproxVideoSession {
+            config,
+            glob,
+            fps: video_config.fps,
+            frame_skip: video_config.frame_skip,
+            frame_paths: frame_paths(&dirs.sources())?,
+            _dirs: dirs,
+        })
+    }
+
+    // the number of frames that are approximated, after skipping
+    pub fn num_frames(&self) -> usize {
+        self.frame_paths.len().div_ceil(self.frame_skip)
+    }
+
+    // lazily approximates every `frame_skip`th frame in order; stops early if the config is cancelled
+    pub fn frames(&self) -> impl Iterator<Item = Result<ApproxFrame>> + '_ {
+        self.frame_paths
+            .iter()
+            .enumerate()
+            .step_by(self.frame_skip)
+            .take_while(|_| !self.config.cancel.is_cancelled())
+            .map(|(index, path)| {
+                let mut source_img = approx_image::load::open(path)?;
+                approx_image::preprocess::apply(&mut source_img, self.config)?;
+                let approx_img = approx_image::approx(&source_img, self.config, self.glob)?;
+                Ok(ApproxFrame {
+                    image: approx_img.into_rgba8(),
+                    timestamp: Duration::from_secs_f64(index as f64 / f64::from(self.fps)),
+                })
+            })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum VideoEncoder {
+    #[default]
+    #[value(name = "libx264")]
+    Libx264,
+    #[value(name = "h264_nvenc")]
+    H264Nvenc,
+    #[value(name = "hevc_vaapi")]
+    HevcVaapi,
+}
+
+impl VideoEncoder {
+    // the encoder's name in ffmpeg
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoEncoder::Libx264 => "libx264",
+            VideoEncoder::H264Nvenc => "h264_nvenc",
+            VideoEncoder::HevcVaapi => "hevc_vaapi",
+        }
+    }
+
+    // each encoder spells its constant quality option differently
+    fn quality_option(self) -> &'static str {
+        match self {
+            VideoEncoder::Libx264 => "crf",
+            VideoEncoder::H264Nvenc => "cq",
+            VideoEncoder::HevcVaapi => "qp",
+        }
+    }
+}
+
+// user overrides for how the video is sampled and encoded
+#[derive(Debug, Clone)]
+pub struct VideoOptions {
+    // frames per second to extract and encode at, instead of the source's
+    pub fps: Option<i32>,
+    // only every nth frame is approximated
+    pub frame_skip: u32,
+    pub encoder: VideoEncoder,
+    // constant quality, lower is better
+    pub crf: u8,
+    pub preset: Option<String>,
+    // shell out to ffmpeg to extract frames and encode the output instead of doing both in process
+    pub use_system_ffmpeg: bool,
+    // leave the audio out of the output
+    pub mute: bool,
+    // where to write a video of each frame's per-cell diff, for seeing where the approximation struggles
+    pub heatmap: Option<PathBuf>,
+    // leave the run's temp directory behind, for debugging
+    pub keep_temp: bool,
+}
+
+impl Default for VideoOptions {
+    fn default() -> Self {
+        VideoOptions {
+            fps: None,
+            frame_skip: 1,
+            encoder: VideoEncoder::default(),
+            crf: DEFAULT_CRF,
+            preset: None,
+            use_system_ffmpeg: false,
+            mute: false,
+            heatmap: None,
+            keep_temp: false,
+        }
+    }
+}
+
+// ffmpeg reports container durations in microseconds
+const DURATION_TIME_BASE: f64 = 1_000_000.0;
+
+// what `--estimate` needs to know about a video, read without touching the frame directories
 #[derive(Debug, Clone, Copy)]
+pub struct VideoInfo {
+    pub width: u32,
+    pub height: u32,
+    pub fps: i32,
+    pub seconds: f64,
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub fn probe(path: &Path) -> Result<VideoInfo> {
+    ffmpeg_next::init()?;
+    let video_config = VideoConfig::new(path)?;
+    let seconds = format::input(path)?.duration().max(0) as f64 / DURATION_TIME_BASE;
+    Ok(VideoInfo {
+        width: video_config.image_width,
+        height: video_config.image_height,
+        fps: video_config.fps,
+        seconds,
+    })
+}
+
+// contains important video metadata
+#[derive(Debug, Clone)]
 pub struct VideoConfig {
     pub image_width: u32,
     pub image_height: u32,
     fps: i32,
+    frame_skip: usize,
+    encoder: VideoEncoder,
+    crf: u8,
+    preset: Option<String>,
+    use_system_ffmpeg: bool,
+    // whether the source has an audio stream to carry over
+    has_audio: bool,
+    heatmap: Option<PathBuf>,
+    keep_temp: bool,
 }
 
 impl VideoConfig {
     // loads video metadata
     fn new(path: &Path) -> Result<VideoConfig> {
         let source = format::input(path)?;
-        let input = source.streams().best(ffmpeg_next::media::Type::Video).expect("failed to find video stream");
+        let has_audio = source.streams().best(ffmpeg_next::media::Type::Audio).is_some();
+        let input = source
+            .streams()
+            .best(ffmpeg_next::media::Type::Video)
+            .ok_or_else(|| VideoError::MissingVideoStream(path.to_path_buf()))?;
         let fps = input.avg_frame_rate();
         let decoder = input.codec().decoder().video()?;
 
@@ -147,6 +827,14 @@ impl VideoConfig {
             image_width: decoder.width(),
             image_height: decoder.height(),
             fps: fps.numerator() / fps.denominator(),
+            frame_skip: 1,
+            encoder: VideoEncoder::default(),
+            crf: DEFAULT_CRF,
+            preset: None,
+            use_system_ffmpeg: false,
+            has_audio,
+            heatmap: None,
+            keep_temp: false,
         })
     }
 }
@@ -156,23 +844,93 @@ mod tests {
     use super::*;
     use approx_image::PrioritizeColor;
 
+    #[test]
+    fn test_diff_heatmap() {
+        let source = DynamicImage::from(RgbImage::from_pixel(4, 2, image::Rgb([100, 100, 100])));
+        let mut approx = source.to_rgb8();
+        for y in 0..2 {
+            for x in 2..4 {
+                approx.put_pixel(x, y, image::Rgb([130, 70, 100]));
+            }
+        }
+
+        // the right cell is off by 30 in two of its three channels
+        let heatmap = diff_heatmap(&source, &DynamicImage::from(approx), 2, 1).unwrap();
+        assert_eq!(heatmap.dimensions(), (4, 2));
+        assert!(heatmap.enumerate_pixels().all(|(x, _, p)| p[0] == if x < 2 { 0 } else { 20 }));
+    }
+
+    #[test]
+    fn test_encode_command_paths() {
+        let video_config = VideoConfig {
+            image_width: 64,
+            image_height: 32,
+            fps: 30,
+            frame_skip: 1,
+            encoder: VideoEncoder::Libx264,
+            crf: DEFAULT_CRF,
+            preset: None,
+            use_system_ffmpeg: false,
+            has_audio: true,
+            heatmap: None,
+            keep_temp: false,
+        };
+        let frames_dir = Path::new("run dir").join("approx");
+        let audio = Path::new("run dir").join("audio.wav");
+        let output = Path::new("out dir").join("video.mp4");
+
+        // paths are passed as single arguments, so spaces and platform separators survive
+        let command = encode_command(&video_config, VideoEncoder::Libx264, &frames_dir, Some(&audio), &output);
+        let args: Vec<_> = command.get_args().collect();
+        assert!(args.contains(&frames_dir.join("%d.png").as_os_str()));
+        assert!(args.contains(&audio.as_os_str()));
+        assert_eq!(args.last(), Some(&output.as_os_str()));
+    }
+
     #[test]
     #[ignore]
     fn test_run() {
         let source = Path::new("test_videos/blank_video.mkv");
         let output = Path::new("test_results/blank_video.mp4");
 
-        let config = Config {
+        let mut config = Config {
             board_width: 63,
             board_height: 35,
             prioritize_tetrominos: PrioritizeColor::No,
+            ..Config::default()
         };
 
-        let mut glob = GlobalData::new();
-        let video_config = init(&source, &output, &config, &mut glob).unwrap();
+        let mut glob = GlobalData::new().unwrap();
+        let video_config = init(&source, &output, &mut config, &mut glob, &VideoOptions::default()).unwrap();
         run(&source, &output, &config, &glob, &video_config).expect("failed to run video approximator");
 
         // remove output
         fs::remove_file(&output).unwrap();
     }
+
+    #[test]
+    #[ignore]
+    fn test_session_frames() {
+        let source = Path::new("test_videos/blank_video.mkv");
+        let output = Path::new("test_results/blank_video_session.mp4");
+
+        let mut config = Config {
+            board_width: 63,
+            board_height: 35,
+            ..Config::default()
+        };
+
+        let mut glob = GlobalData::new().unwrap();
+        let video_config = init(source, output, &mut config, &mut glob, &VideoOptions::default()).unwrap();
+        let session = ApproxVideoSession::new(source, &config, &glob, &video_config).unwrap();
+
+        let mut last_timestamp = None;
+        for frame in session.frames() {
+            let frame = frame.unwrap();
+            assert_eq!(frame.image.dimensions(), (video_config.image_width, video_config.image_height));
+            assert!(last_timestamp < Some(frame.timestamp));
+            last_timestamp = Some(frame.timestamp);
+        }
+        assert!(last_timestamp.is_some());
+    }
 }
\ No newline at end of file
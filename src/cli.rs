@@ -1,19 +1,128 @@
-use crate::approx_image::PrioritizeColor;
-use crate::approx_image::draw::{Skins, create_skins};
+use crate::approx_image::{FillStyle, FitMode, NoGarbage, PieceRatio, PrioritizeColor, SkinMode, SmallSource};
+use crate::approx_image::piece::parse_tetromino;
+use crate::approx_image::preprocess::parse_color;
+use crate::approx_image::detail::parse_detail_weight;
+use crate::approx_image::preview::PreviewConfig;
+use crate::utils::CancellationToken;
+use crate::approx_image::cost::PlacementCost;
+use crate::approx_image::board::AsciiFormat;
+use crate::approx_image::mask::MaskFill;
+use crate::approx_image::live_preview::LivePreview;
+use crate::approx_image::traversal::Traversal;
+use crate::approx_image::panels::parse_split;
+use crate::approx_image::render::OutputFormat;
+use crate::approx_image::draw::{Skins, create_skins, sort_skins};
+use crate::approx_image::{self, pattern_skin};
+use crate::approx_video::VideoEncoder;
 
+use std::collections::VecDeque;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use anyhow::Result;
 use clap::{Parser, Subcommand};
+use image::Rgba;
+use rand::{rngs::StdRng, SeedableRng};
 
 #[derive(Clone)]
 pub struct GlobalData {
     pub skins: Skins,
+    sized: SkinCache,
 }
 
-#[derive(Copy, Clone, Debug)]
+// copies of the skins resized for each skin size, shared between clones of the same `GlobalData`
+// only the most recently added sizes are kept, so a server fed many image sizes doesn't grow without bound
+#[derive(Clone, Default)]
+struct SkinCache(Arc<Mutex<VecDeque<(SkinSize, Skins)>>>);
+
+type SkinSize = (u32, u32);
+
+const MAX_CACHED_SIZES: usize = 16;
+
+impl SkinCache {
+    fn get(&self, size: SkinSize) -> Option<Skins> {
+        let sizes = self.0.lock().expect("skin cache lock poisoned");
+        sizes.iter().find(|(cached, _)| *cached == size).map(|(_, skins)| skins.clone())
+    }
+
+    fn insert(&self, size: SkinSize, skins: Skins) {
+        let mut sizes = self.0.lock().expect("skin cache lock poisoned");
+        // another thread may have resized the same size in the meantime
+        if sizes.iter().any(|(cached, _)| *cached == size) {
+            return;
+        }
+        if sizes.len() == MAX_CACHED_SIZES {
+            sizes.pop_front();
+        }
+        sizes.push_back((size, skins));
+    }
+}
+
+#[derive(Clone, Debug, Default)]
 pub struct Config {
     pub board_width: usize,
     pub board_height: usize,
     pub prioritize_tetrominos: PrioritizeColor,
+    pub fill_style: FillStyle,
+    pub tie_epsilon: f64,
+    pub seed: u64,
+    pub skin_mode: SkinMode,
+    pub skin_regions: Option<usize>,
+    pub duotone: Option<(Rgba<u8>, Rgba<u8>)>,
+    pub quantize_colors: Option<u16>,
+    pub brightness: Option<f64>,
+    pub contrast: Option<f64>,
+    pub gamma: Option<f64>,
+    pub preview: Option<PreviewConfig>,
+    pub live_preview: Option<LivePreview>,
+    pub legend: Option<PathBuf>,
+    pub board_out: Option<PathBuf>,
+    pub ascii_out: Option<PathBuf>,
+    pub ascii_format: AsciiFormat,
+    pub output_format: OutputFormat,
+    pub tiled: bool,
+    pub split: Option<(usize, usize)>,
+    pub deterministic: bool,
+    pub pattern_skin: bool,
+    pub temporal_bias: Option<f64>,
+    pub confidence_out: Option<PathBuf>,
+    pub piece_ratio: PieceRatio,
+    pub banned_pieces: Vec<char>,
+    pub stages: Vec<String>,
+    pub build_animation: Option<PathBuf>,
+    pub pieces_per_frame: Option<usize>,
+    pub fit: FitMode,
+    pub small_source: SmallSource,
+    pub playfields: bool,
+    pub no_floating: bool,
+    pub score: bool,
+    pub detail_weight: Option<f64>,
+    pub mask: Option<PathBuf>,
+    pub mask_fill: MaskFill,
+    pub no_garbage: Option<NoGarbage>,
+    // shades of gray garbage to choose from, where 1 is just the skin's gray
+    pub garbage_levels: u8,
+    pub no_prune: bool,
+    pub traversal: Traversal,
+    pub coarse_to_fine: bool,
+    // custom placement cost for library users; the default cost is used when unset
+    pub cost: Option<Arc<dyn PlacementCost>>,
+    pub cancel: CancellationToken,
+}
+
+// each part of a run that uses randomness draws from its own stream of `Config::seed`,
+// so one part using more or fewer numbers doesn't change what the others get
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RngStream {
+    TieBreak,
+    Traversal,
+}
+
+impl Config {
+    // a reproducible rng for one part of a run; the same seed and stream always give the same numbers
+    pub fn rng(&self, stream: RngStream) -> StdRng {
+        // tie-breaking was seeded from the seed alone before there were streams, which the first stream keeps
+        StdRng::seed_from_u64(self.seed ^ (stream as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -27,27 +136,355 @@ pub struct Cli {
     # [arg(short, long, default_value_t = false)]
     pub prioritize_tetrominos: bool,
 
+    /// how cells left unfilled by tetrominos are filled when prioritizing tetrominos; default is garbage
+    #[arg(long, value_enum)]
+    pub fill_style: Option<FillStyle>,
+
+    /// randomly break ties between pieces whose diffs are within this fraction of the best diff; default is 0 (no ties)
+    #[arg(long)]
+    pub tie_epsilon: Option<f64>,
+
+    /// seed used for any randomness, such as tie-breaking and the random traversal, so runs are reproducible; default is 0
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// how skins are mixed: one skin for the whole image, per piece, per color region, or per horizontal band; default is per-piece
+    #[arg(long, value_enum)]
+    pub skin_mode: Option<SkinMode>,
+
+    /// number of regions or bands used by the per-region and per-band skin modes; default is the number of skins
+    #[arg(long)]
+    pub skin_regions: Option<usize>,
+
+    /// how the source is fit to the board: stretch the cells, or keep the skins' aspect ratio and letterbox the source; default is stretch
+    #[arg(long, value_enum)]
+    pub fit: Option<FitMode>,
+
+    /// fill the gaps under floating pieces with garbage so the stack could be built in game; only matters with `--fill-style empty`
+    #[arg(long)]
+    pub no_floating: bool,
+
+    /// only use the 7 tetrominoes, never garbage; cells no tetromino fits in are left empty (`--no-garbage=empty`) or filled with overlapping tetrominoes, default is overlap
+    #[arg(long, value_enum, num_args = 0..=1, require_equals = true, default_missing_value = "overlap", conflicts_with = "no_floating")]
+    pub no_garbage: Option<NoGarbage>,
+
+    /// shades of gray garbage to pick from, made by blending the skin's gray block towards black, for smoother dark areas; default is 1
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=8))]
+    pub garbage_levels: Option<u8>,
+
+    /// score every candidate in full instead of skipping the ones whose average color already rules them out; the result is the same, only slower
+    #[arg(long)]
+    pub no_prune: bool,
+
+    /// the order cells are approximated in: column by column, serpentine, from the center out, or shuffled by `--seed`; default is scan
+    #[arg(long, value_enum)]
+    pub traversal: Option<Traversal>,
+
+    /// approximate a half resolution board first and keep each area of the full board to the skin picked for it there; much faster with many skins, at some cost to accuracy
+    #[arg(long)]
+    pub coarse_to_fine: bool,
+
+    /// show the board in a window while it's approximated, and each video frame once it's done; needs the `preview` feature and isn't supported on macOS
+    #[arg(long)]
+    pub preview: bool,
+
+    /// weigh the diff along the source's edges up to this much more than flat areas, so fine details like faces and text are matched more carefully; must be 0 or more, default is 0
+    #[arg(long, value_parser = parse_detail_weight)]
+    pub detail_weight: Option<f64>,
+
+    /// only approximate the parts of the source under white parts of this mask image, leaving the rest to `--mask-fill`
+    #[arg(long)]
+    pub mask: Option<PathBuf>,
+
+    /// what the masked out cells are filled with; default is garbage
+    #[arg(long, value_enum)]
+    pub mask_fill: Option<MaskFill>,
+
+    /// what to do when the source has fewer pixels than the board has cells; default is error
+    #[arg(long, value_enum)]
+    pub small_source: Option<SmallSource>,
+
+    /// constrain how often each tetromino is used; `standard` keeps counts even like a 7-bag; default is any
+    #[arg(long, value_enum)]
+    pub piece_ratio: Option<PieceRatio>,
+
+    /// comma-separated tetrominos that are never placed, e.g. `--ban-pieces S,Z`
+    #[arg(long, value_delimiter = ',', value_parser = parse_tetromino)]
+    pub ban_pieces: Vec<char>,
+
+    /// map the source onto two RRGGBB hex colors with dithering before approximating, e.g. `--duotone 1b1b3a ffd166`
+    #[arg(long, num_args = 2, value_names = ["DARK", "LIGHT"], value_parser = parse_color)]
+    pub duotone: Option<Vec<Rgba<u8>>>,
+
+    /// reduce the source to this many colors by median cut before approximating, which gives cleaner blocks on noisy photos
+    #[arg(long, value_parser = clap::value_parser!(u16).range(2..))]
+    pub quantize_colors: Option<u16>,
+
+    /// brighten the source by this fraction of white before approximating, or darken it if negative; default is 0
+    #[arg(long, allow_negative_numbers = true)]
+    pub brightness: Option<f64>,
+
+    /// scale the source's contrast around mid gray by this factor before approximating; skins have a narrow range, so slightly above 1 often helps; default is 1
+    #[arg(long)]
+    pub contrast: Option<f64>,
+
+    /// apply this gamma to the source before approximating, where above 1 brightens the midtones; default is 1
+    #[arg(long)]
+    pub gamma: Option<f64>,
+
+    /// log more detail; pass twice for trace-level logs
+    #[arg(short, long, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// only log warnings and errors
+    #[arg(short, long)]
+    pub quiet: bool,
+
+    /// log as newline-delimited json on stderr instead of text and progress bars; combine with `-v` for progress events
+    #[arg(long)]
+    pub log_json: bool,
+
+    /// don't draw progress bars, e.g. for ci logs; combine with `-v` for progress events
+    #[arg(long)]
+    pub no_progress: bool,
+
+    /// print the board, output size, and a predicted runtime for `approx-image` or `approx-video` instead of running it
+    #[arg(long)]
+    pub estimate: bool,
+
+    /// write a json summary of the run to this path, with stage timings, board size, piece and skin usage, scores, and outputs
+    #[arg(long)]
+    pub summary_out: Option<PathBuf>,
+
+    /// use a black and white skin with a pattern per piece type instead of the loaded skins, for printing in grayscale
+    #[arg(long)]
+    pub pattern_skin: bool,
+
+    /// sort skins by file name so identical inputs give identical outputs on every machine
+    #[arg(long)]
+    pub deterministic: bool,
+
     #[command(subcommand)]
     pub command: Commands
 }
 
 #[derive(Debug, Subcommand)]
 pub enum Commands {
-    /// runs approximation tests using images located in the `sources` directory; `board_width` is set to 100 if unspecified
-    Integration{board_width: Option<usize>},
+    /// runs approximation tests using every image in a directory and logs how close each approximation is
+    Integration{
+        /// directory of test images, which shouldn't contain other files; default is `sources`
+        dir: Option<PathBuf>,
+
+        /// board width for every image, with heights following each image's aspect ratio; default is 100
+        #[arg(long)]
+        board_width: Option<usize>,
+
+        /// fail if the average dssim across the images is above this, to catch quality regressions
+        #[arg(long)]
+        max_avg_dssim: Option<f64>,
+
+        /// write each image's dssim and similarity to this csv
+        #[arg(long)]
+        csv: Option<PathBuf>,
+    },
 
     /// approximates a single image using tetris blocks
-    ApproxImage{source: PathBuf, output: PathBuf, board_width: usize, board_height: usize},
+    ApproxImage{
+        /// source image, or `-` to read it from stdin
+        source: PathBuf,
+        /// where to save the approximation, or `-` to write it to stdout as a png
+        output: PathBuf,
+        board_width: usize,
+        board_height: usize,
+
+        /// save the approximation as a raster image in the format of the output's extension, an svg of the skins' blocks, or an svg of flat colors; svgs don't show the gaps between playfields; default is image
+        #[arg(long, value_enum)]
+        format: Option<OutputFormat>,
+
+        /// write the image as a png one row of blocks at a time instead of drawing it whole, for boards too big to fit in memory; always writes a png
+        #[arg(long, conflicts_with = "playfields")]
+        tiled: bool,
+
+        /// save the image as COLUMNSxROWS separate panels cut along block edges, e.g. `3x1` for three monitors; panels are named after the output with their row and column
+        #[arg(long, value_parser = parse_split, conflicts_with_all = ["playfields", "tiled", "format"])]
+        split: Option<(usize, usize)>,
+
+        /// write a low-res `preview.png` next to the output every time this percentage of cells is filled
+        #[arg(long)]
+        preview_every: Option<f64>,
+
+        /// write an svg legend of the skins and blocks used to this path
+        #[arg(long)]
+        legend: Option<PathBuf>,
+
+        /// write the finished board as json to this path so it can be re-rendered with `render-board`
+        #[arg(long)]
+        board_out: Option<PathBuf>,
+
+        /// write the finished board as text to this path, for sharing or diffing approximations
+        #[arg(long)]
+        ascii_out: Option<PathBuf>,
+
+        /// how `--ascii-out` writes the board, as a boxed grid of rows or a one-line run-length string; default is grid
+        #[arg(long, value_enum)]
+        ascii_format: Option<AsciiFormat>,
+
+        /// write an animation of the board being filled piece by piece, as a gif if the path ends in `.gif` or else as a directory of pngs
+        #[arg(long)]
+        build_animation: Option<PathBuf>,
+
+        /// number of pieces added in each frame of the build animation; default gives about 100 frames
+        #[arg(long)]
+        pieces_per_frame: Option<usize>,
+
+        /// comma-separated stages to run, from preprocess, approximate, stats, score, and export; default is all of them except score, in that order
+        #[arg(long, value_delimiter = ',')]
+        stages: Vec<String>,
+
+        /// write how well each cell's piece matched as a csv, or as a heatmap if the path ends in `.png`
+        #[arg(long)]
+        confidence_out: Option<PathBuf>,
+
+        /// split the board into standard 10x20 playfields that are filled separately, so the result can be recreated in game; the board must be a multiple of 10x20
+        #[arg(long)]
+        playfields: bool,
+
+        /// print the dssim and rgb hybrid similarity between the source and the result
+        #[arg(long)]
+        score: bool,
+    },
+
+    /// quickly checks that ffmpeg and the skins in `assets` work before starting long jobs
+    SelfTest,
+
+    /// keeps the skins loaded and approximates images posted to `/approx?width=W&height=H` over http, returning pngs
+    Serve{
+        /// address to listen on; default is 127.0.0.1:8080
+        #[arg(long)]
+        address: Option<String>,
+    },
+
+    /// approximates an image once for every combination of parameters and writes the scores and times to a csv
+    Sweep{
+        source: PathBuf,
+        output: PathBuf,
+
+        /// comma-separated board widths to try, with heights following the source's aspect ratio; default is 16,32,64
+        #[arg(long, value_delimiter = ',')]
+        board_widths: Vec<usize>,
+
+        /// comma-separated settings for prioritizing tetrominos to try; default is false,true
+        #[arg(long, value_delimiter = ',')]
+        prioritize: Vec<bool>,
+
+        /// comma-separated fill styles to try when prioritizing tetrominos; default is garbage
+        #[arg(long, value_delimiter = ',', value_enum)]
+        fill_styles: Vec<FillStyle>,
+    },
+
+    /// re-renders a board saved with `--board-out` using the skins in a directory
+    RenderBoard{
+        board: PathBuf,
+        output: PathBuf,
+        /// width and height of each rendered block in pixels
+        block_size: u32,
+
+        /// directory containing the skins named by the board; default is `assets`
+        #[arg(long)]
+        skins: Option<PathBuf>,
+    },
 
     /// approximates a single video using tetris blocks
-    ApproxVideo{source: PathBuf, output: PathBuf, board_width: usize, board_height: usize},
+    ApproxVideo{
+        source: PathBuf,
+        output: PathBuf,
+        board_width: usize,
+        board_height: usize,
+
+        /// reduce flicker by favoring the previous frame's pieces unless a replacement improves the diff by more than this fraction; frames are then approximated in order
+        #[arg(long)]
+        temporal_bias: Option<f64>,
+
+        /// frames per second to sample the source at and encode the output with; default is the source's
+        #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+        fps: Option<i32>,
+
+        /// only approximate every nth frame, repeating it in place of the skipped ones; default is 1
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        frame_skip: Option<u32>,
+
+        /// video encoder for the output, falling back to libx264 if it isn't available; default is libx264
+        #[arg(long, value_enum)]
+        encoder: Option<VideoEncoder>,
+
+        /// constant quality of the output, lower is better; default is 10
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=51))]
+        crf: Option<u8>,
+
+        /// encoder preset, such as slow or p7 for nvenc; default is the encoder's own
+        #[arg(long)]
+        preset: Option<String>,
+
+        /// extract frames and audio and encode the output with the ffmpeg command instead of the linked libraries
+        #[arg(long)]
+        use_system_ffmpeg: bool,
+
+        /// leave the audio out of the output; sources without audio are always silent
+        #[arg(long)]
+        mute: bool,
+
+        /// also write a video to this path where each cell is shaded by how far its blocks are from the source, brighter being worse
+        #[arg(long)]
+        heatmap_out: Option<PathBuf>,
+
+        /// leave the extracted and approximated frames in the run's temp directory instead of removing them, for debugging
+        #[arg(long)]
+        keep_temp: bool,
+    },
 }
 
 impl GlobalData {
-    pub fn new() -> GlobalData {
+    // loading skins reads from disk, so this is deliberately not `Default`
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Result<GlobalData> {
+        Ok(GlobalData::from_skins(create_skins()?))
+    }
+
+    pub fn from_skins(skins: Skins) -> GlobalData {
         GlobalData {
-            skins: create_skins(),
+            skins,
+            sized: SkinCache::default(),
+        }
+    }
+
+    // loads the skins, ordering them by file name in deterministic mode
+    pub fn load(config: &Config) -> Result<GlobalData> {
+        let mut glob = GlobalData::new()?;
+        if config.deterministic {
+            sort_skins(&mut glob.skins);
+        }
+        // the first loaded skin decides which piece types get the denser patterns
+        if config.pattern_skin {
+            glob.skins = vec![pattern_skin::pattern_skin(&glob.skins[0], 0)];
+        }
+        Ok(glob)
+    }
+
+    // a copy with the skins fit to the image like `fit_skins`, without modifying this one
+    // the skins are resized once per size and reused by later calls, even from other threads
+    pub fn fitted(&self, image_width: u32, image_height: u32, config: &Config) -> Result<GlobalData> {
+        let size = approx_image::fit_skin_size(&self.skins, image_width, image_height, config)?;
+        if let Some(skins) = self.sized.get(size) {
+            return Ok(GlobalData::from_skins(skins));
+        }
+
+        // resize outside the lock so other sizes aren't held up
+        let mut skins = self.skins.clone();
+        for skin in &mut skins {
+            skin.resize(size.0, size.1);
         }
+        self.sized.insert(size, skins.clone());
+        Ok(GlobalData::from_skins(skins))
     }
 
     pub fn skin_width(&self) -> u32 {
@@ -56,4 +493,51 @@ impl GlobalData {
     pub fn skin_height(&self) -> u32 {
         self.skins[0].height()
     }
-}
\ No newline at end of file
+}
+
+// global data holding only the test skin, with blocks of `block_size` pixels
+#[cfg(test)]
+pub(crate) fn test_glob(block_size: u32) -> GlobalData {
+    let mut skin = crate::approx_image::draw::test_skin();
+    skin.resize(block_size, block_size);
+    GlobalData::from_skins(vec![skin])
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng_streams() {
+        use rand::Rng;
+
+        let config = Config { seed: 3, ..Config::default() };
+        let draw = |stream| config.rng(stream).gen::<u64>();
+        assert_eq!(draw(RngStream::TieBreak), draw(RngStream::TieBreak));
+        assert_eq!(draw(RngStream::TieBreak), StdRng::seed_from_u64(3).gen::<u64>());
+        assert_ne!(draw(RngStream::TieBreak), draw(RngStream::Traversal));
+        assert_ne!(draw(RngStream::Traversal), Config { seed: 4, ..Config::default() }.rng(RngStream::Traversal).gen::<u64>());
+    }
+
+    #[test]
+    fn test_fitted_reuses_sizes() {
+        let glob = test_glob(8);
+        let config = Config { board_width: 10, board_height: 5, ..Config::default() };
+
+        let fitted = glob.fitted(40, 20, &config).unwrap();
+        assert_eq!((fitted.skin_width(), fitted.skin_height()), (4, 4));
+        assert_eq!(glob.skin_width(), 8);
+
+        // the same size comes from the cache, while a new size adds an entry
+        glob.clone().fitted(40, 20, &config).unwrap();
+        glob.fitted(80, 40, &config).unwrap();
+        assert_eq!(glob.sized.0.lock().unwrap().len(), 2);
+
+        // the oldest sizes are dropped once the cache is full
+        for scale in 3..u32::try_from(MAX_CACHED_SIZES).unwrap() + 3 {
+            glob.fitted(40 * scale, 20 * scale, &config).unwrap();
+        }
+        let sizes = glob.sized.0.lock().unwrap();
+        assert_eq!(sizes.len(), MAX_CACHED_SIZES);
+        assert!(sizes.iter().all(|(size, _)| *size != (4, 4)));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::approx_image::{self, approx_board, fit_image, fit_small_image, load};
+use crate::approx_video::{self, VideoOptions};
+use crate::cli::{Config, GlobalData};
+
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use tracing::{debug, info_span};
+
+// the benchmark approximates at most this many cells on each side, which is quick but still covers every piece
+const SAMPLE_CELLS: usize = 8;
+
+// what a run would do and roughly how long it would take, printed by `--estimate` instead of running
+#[derive(Debug, Clone, PartialEq)]
+pub struct Estimate {
+    pub source_width: u32,
+    pub source_height: u32,
+    pub board_width: usize,
+    pub board_height: usize,
+    pub skin_width: u32,
+    pub skin_height: u32,
+    pub skins: usize,
+    // frames that would be approximated, for videos
+    pub frames: Option<u64>,
+    pub runtime: Duration,
+}
+
+impl Estimate {
+    pub fn output_width(&self) -> u32 {
+        self.skin_width * u32::try_from(self.board_width).unwrap_or(u32::MAX)
+    }
+
+    pub fn output_height(&self) -> u32 {
+        self.skin_height * u32::try_from(self.board_height).unwrap_or(u32::MAX)
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "source: {}x{}", self.source_width, self.source_height)?;
+        writeln!(f, "board: {}x{} ({} cells)", self.board_width, self.board_height, self.board_width * self.board_height)?;
+        writeln!(f, "skins: {} at {}x{}", self.skins, self.skin_width, self.skin_height)?;
+        writeln!(f, "output: {}x{}", self.output_width(), self.output_height())?;
+        if let Some(frames) = self.frames {
+            writeln!(f, "frames: {frames}")?;
+        }
+        write!(f, "estimated runtime: {:.1}s", self.runtime.as_secs_f64())
+    }
+}
+
+// fits the source like a real run would, then times a small sample of the board to predict the whole run
+pub fn image(source: &Path, config: &Config, glob: &GlobalData) -> Result<Estimate> {
+    let _span = info_span!("estimate", source = %source.display()).entered();
+    let mut source_img = load::open(source).context("could not load source image")?;
+    let (source_width, source_height) = source_img.dimensions();
+
+    let mut config = config.clone();
+    fit_small_image(&mut source_img, &mut config).context("source image does not fit the board")?;
+    let glob = glob.fitted(source_img.width(), source_img.height(), &config)?;
+    fit_image(&mut source_img, glob.skin_width(), glob.skin_height(), &config);
+
+    // sample the middle of the source, where there's usually the most going on
+    let (sample_width, sample_height) = (config.board_width.min(SAMPLE_CELLS), config.board_height.min(SAMPLE_CELLS));
+    let crop_width = glob.skin_width() * u32::try_from(sample_width)?;
+    let crop_height = glob.skin_height() * u32::try_from(sample_height)?;
+    let sample = source_img.crop_imm(
+        (source_img.width() - crop_width) / 2 / glob.skin_width() * glob.skin_width(),
+        (source_img.height() - crop_height) / 2 / glob.skin_height() * glob.skin_height(),
+        crop_width,
+        crop_height,
+    );
+    let per_cell = time_per_cell(&sample, sample_width, sample_height, &config, &glob)?;
+
+    Ok(Estimate {
+        source_width,
+        source_height,
+        board_width: config.board_width,
+        board_height: config.board_height,
+        skin_width: glob.skin_width(),
+        skin_height: glob.skin_height(),
+        skins: glob.skins.len(),
+        frames: None,
+        runtime: per_cell * u32::try_from(config.board_width * config.board_height)?,
+    })
+}
+
+// reads the video's length and frame rate, and times a sample board of the fitted skin size
+// frames are approximated in parallel, so the runtime is spread over the thread pool
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+pub fn video(source: &Path, config: &Config, glob: &GlobalData, options: &VideoOptions) -> Result<Estimate> {
+    let _span = info_span!("estimate", source = %source.display()).entered();
+    let info = approx_video::probe(source)?;
+
+    let mut config = config.clone();
+    let (image_width, image_height) = approx_image::fit_small_source(info.width, info.height, &mut config)?;
+    let glob = glob.fitted(image_width, image_height, &config)?;
+
+    let fps = options.fps.unwrap_or(info.fps).max(1);
+    let frames = (info.seconds * f64::from(fps) / f64::from(options.frame_skip.max(1))).ceil() as u64;
+
+    // frames aren't decoded for an estimate, so a gradient stands in for one
+    let (sample_width, sample_height) = (config.board_width.min(SAMPLE_CELLS), config.board_height.min(SAMPLE_CELLS));
+    let sample = sample_image(glob.skin_width() * u32::try_from(sample_width)?, glob.skin_height() * u32::try_from(sample_height)?);
+    let per_cell = time_per_cell(&sample, sample_width, sample_height, &config, &glob)?;
+    let per_frame = per_cell * u32::try_from(config.board_width * config.board_height)?;
+    let runtime = per_frame.mul_f64(frames as f64 / rayon::current_num_threads() as f64);
+
+    Ok(Estimate {
+        source_width: info.width,
+        source_height: info.height,
+        board_width: config.board_width,
+        board_height: config.board_height,
+        skin_width: glob.skin_width(),
+        skin_height: glob.skin_height(),
+        skins: glob.skins.len(),
+        frames: Some(frames),
+        runtime,
+    })
+}
+
+// approximates a sample board with the run's settings and returns the time it took per cell
+fn time_per_cell(sample: &DynamicImage, sample_width: usize, sample_height: usize, config: &Config, glob: &GlobalData) -> Result<Duration> {
+    // outputs and masks are sized for the whole board, so the sample leaves them out
+    let sample_config = Config {
+        board_width: sample_width,
+        board_height: sample_height,
+        preview: None,
+        live_preview: None,
+        confidence_out: None,
+        mask: None,
+        ..config.clone()
+    };
+    let start = Instant::now();
+    approx_board(sample, &sample_config, glob)?;
+    let elapsed = start.elapsed();
+    debug!(?elapsed, cells = sample_width * sample_height, "timed sample board");
+    Ok(elapsed / u32::try_from(sample_width * sample_height)?.max(1))
+}
+
+fn sample_image(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::from(RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 255 / width.max(1)) as u8, (y * 255 / height.max(1)) as u8, 128, 255])
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_glob;
+
+    #[test]
+    fn test_image_estimate() {
+        let glob = test_glob(4);
+        let config = Config { board_width: 24, board_height: 16, ..Config::default() };
+
+        let estimate = image(Path::new("examples/sunset_1280.jpg"), &config, &glob).unwrap();
+        assert_eq!((estimate.board_width, estimate.board_height), (24, 16));
+        assert_eq!((estimate.output_width(), estimate.output_height()), (estimate.skin_width * 24, estimate.skin_height * 16));
+        assert!(estimate.runtime > Duration::ZERO);
+        assert!(estimate.to_string().contains("384 cells"));
+    }
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+
+// the ffmpeg binary's file name on this platform
+pub const FFMPEG_BINARY: &str = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+
+// points at a specific ffmpeg binary, for installs that aren't on the PATH
+pub const FFMPEG_ENV: &str = "FFMPEG_PATH";
+
+// every call to the ffmpeg command line starts here, so they all find the same binary
+pub fn command() -> Command {
+    Command::new(locate())
+}
+
+// finds the ffmpeg binary, preferring `FFMPEG_PATH`, then the first match on the PATH
+pub fn locate() -> PathBuf {
+    locate_in(env::var_os(FFMPEG_ENV), env::var_os("PATH"))
+}
+
+// falls back to the bare binary name, so the os can still resolve it and errors name what was run
+fn locate_in(ffmpeg_path: Option<OsString>, path_var: Option<OsString>) -> PathBuf {
+    if let Some(ffmpeg_path) = ffmpeg_path.filter(|path| !path.is_empty()) {
+        return PathBuf::from(ffmpeg_path);
+    }
+    path_var
+        .iter()
+        .flat_map(env::split_paths)
+        .map(|dir| dir.join(FFMPEG_BINARY))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(FFMPEG_BINARY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_locate_in() {
+        let dir = env::temp_dir().join("image_to_tetris_ffmpeg_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(FFMPEG_BINARY), "").unwrap();
+        let missing = dir.join("missing");
+        let path_var = env::join_paths([missing.as_path(), dir.as_path()]).unwrap();
+
+        assert_eq!(locate_in(None, Some(path_var.clone())), dir.join(FFMPEG_BINARY));
+        assert_eq!(locate_in(Some("custom/ffmpeg".into()), Some(path_var.clone())), PathBuf::from("custom/ffmpeg"));
+        assert_eq!(locate_in(Some(OsString::new()), Some(path_var)), dir.join(FFMPEG_BINARY));
+        assert_eq!(locate_in(None, Some(missing.into_os_string())), PathBuf::from(FFMPEG_BINARY));
+    }
+}
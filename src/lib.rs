@@ -0,0 +1,10 @@
+pub mod approx_image;
+pub mod approx_video;
+pub mod cli;
+pub mod estimate;
+pub mod ffmpeg;
+pub mod self_test;
+pub mod serve;
+pub mod summary;
+pub mod sweep;
+pub mod utils;
@@ -1,52 +1,211 @@
-mod approx_image;
-mod approx_video;
-mod cli;
-mod utils;
-
+use image_to_tetris::{approx_image, approx_video, cli, estimate, self_test, serve, summary, sweep, utils};
+use utils::{CancellationToken, CancelledError, PartialFailure};
 use approx_image::PrioritizeColor;
-use approx_image::integration_test;
+use approx_image::live_preview::LivePreview;
+use approx_image::preview::PreviewConfig;
+use approx_image::{board_dump, integration_test, load};
 use cli::{Config, GlobalData};
+use summary::RunSummary;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Instant;
 
+use anyhow::Result;
 use clap::Parser;
+use tracing::{error, info};
 
-fn main() {
+fn main() -> ExitCode {
     let cli = cli::Cli::parse();
+    utils::init_logging(cli.verbose, cli.quiet, cli.log_json);
+    if cli.no_progress {
+        utils::hide_progress();
+    }
 
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e:#}");
+            exit_code(&e)
+        }
+    }
+}
+
+// batch runs that only partly failed still wrote their output, so they get a different code than outright failures
+fn exit_code(e: &anyhow::Error) -> ExitCode {
+    if e.is::<PartialFailure>() {
+        ExitCode::from(2)
+    } else if e.is::<CancelledError>() {
+        ExitCode::from(130)
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn run(cli: cli::Cli) -> Result<()> {
+    let start = Instant::now();
     let threads = cli.threads.unwrap_or(4);
-    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().expect("failed to build thread pool");
-    println!("Using {threads} threads");
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()?;
+    info!(threads, "using threads");
+
+    let cancel = CancellationToken::default();
+    utils::cancel_on_ctrl_c(cancel.clone())?;
 
     let prioritize_tetrominos = if cli.prioritize_tetrominos {PrioritizeColor::Yes} else {PrioritizeColor::No};
-    println!("Prioritizing tetrominos: {}", cli.prioritize_tetrominos);
+    info!(prioritize_tetrominos = cli.prioritize_tetrominos, "prioritizing tetrominos");
 
-    // a global skins will be copied by each thread to prevent needing IO to recreate skins for each thread
-    let mut glob = GlobalData::new();
+    // options shared by every subcommand; board dimensions are filled in per command
+    let base_config = Config {
+        prioritize_tetrominos,
+        fill_style: cli.fill_style.unwrap_or_default(),
+        tie_epsilon: cli.tie_epsilon.unwrap_or(0.0),
+        seed: cli.seed.unwrap_or(0),
+        skin_mode: cli.skin_mode.unwrap_or_default(),
+        skin_regions: cli.skin_regions,
+        duotone: cli.duotone.map(|colors| (colors[0], colors[1])),
+        quantize_colors: cli.quantize_colors,
+        brightness: cli.brightness,
+        contrast: cli.contrast,
+        gamma: cli.gamma,
+        deterministic: cli.deterministic,
+        pattern_skin: cli.pattern_skin,
+        piece_ratio: cli.piece_ratio.unwrap_or_default(),
+        banned_pieces: cli.ban_pieces,
+        fit: cli.fit.unwrap_or_default(),
+        small_source: cli.small_source.unwrap_or_default(),
+        no_floating: cli.no_floating,
+        detail_weight: cli.detail_weight,
+        mask: cli.mask,
+        mask_fill: cli.mask_fill.unwrap_or_default(),
+        no_garbage: cli.no_garbage,
+        garbage_levels: cli.garbage_levels.unwrap_or(1),
+        no_prune: cli.no_prune,
+        traversal: cli.traversal.unwrap_or_default(),
+        coarse_to_fine: cli.coarse_to_fine,
+        live_preview: if cli.preview { Some(LivePreview::open()?) } else { None },
+        cancel,
+        ..Config::default()
+    };
 
-    match cli.command {
-        cli::Commands::Integration {board_width} => {
+    // a global skins will be copied by each thread to prevent needing IO to recreate skins for each thread
+    // it is only loaded by the commands that approximate, since rendering a saved board loads its own skins
+    let mut summary = match cli.command {
+        cli::Commands::Integration { dir, board_width, max_avg_dssim, csv } => {
             let config = Config {
                 board_width: board_width.unwrap_or(100),
                 board_height: 0, // height doesn't matter here since it will be auto-scaled
-                prioritize_tetrominos,
+                ..base_config
             };
-            integration_test::run("sources", &config, &glob).expect("failed to run integration test");
+            let dir = dir.unwrap_or_else(|| PathBuf::from(integration_test::DEFAULT_DIR));
+            integration_test::run(&dir, max_avg_dssim, csv.as_deref(), &config, &GlobalData::load(&config)?)?;
+            let mut summary = RunSummary::new("integration");
+            summary.outputs.extend(csv);
+            summary
         },
-        cli::Commands::ApproxImage { source, output, board_width, board_height } => {
+        cli::Commands::ApproxImage { source, output, board_width, board_height, format, tiled, split, preview_every, legend, board_out, ascii_out, ascii_format, confidence_out, stages, build_animation, pieces_per_frame, playfields, score } => {
+            let preview = preview_every.map(|every_percent| PreviewConfig {
+                path: output.with_file_name("preview.png"),
+                every_percent,
+            });
             let config = Config {
                 board_width,
                 board_height,
-                prioritize_tetrominos,
+                preview,
+                legend,
+                board_out,
+                ascii_out,
+                ascii_format: ascii_format.unwrap_or_default(),
+                output_format: format.unwrap_or_default(),
+                tiled,
+                split,
+                confidence_out,
+                stages,
+                build_animation,
+                pieces_per_frame,
+                playfields,
+                score,
+                ..base_config
             };
-            approx_image::run(&source, &output, &config, &mut glob);
+            if cli.estimate {
+                println!("{}", estimate::image(&source, &config, &GlobalData::load(&config)?)?);
+                return Ok(());
+            }
+            let summary = approx_image::run(&source, &output, &config, &GlobalData::load(&config)?)?;
+            if let Some(score) = summary.score {
+                // keep stdout clean when the image itself is written there
+                let line = format!("dssim: {:.6}, similarity: {:.6}", score.dssim, score.similarity);
+                if load::is_stdio(&output) {
+                    eprintln!("{line}");
+                } else {
+                    println!("{line}");
+                }
+            }
+            summary
         }
-        cli::Commands::ApproxVideo { source, output, board_width, board_height} => {
-            let config = Config {
+        cli::Commands::ApproxVideo { source, output, board_width, board_height, temporal_bias, fps, frame_skip, encoder, crf, preset, use_system_ffmpeg, mute, heatmap_out, keep_temp } => {
+            let mut config = Config {
                 board_width,
                 board_height,
-                prioritize_tetrominos,
+                temporal_bias,
+                ..base_config
             };
-            let video_config = approx_video::init(&source, &output, &config, &mut glob).unwrap();
-            approx_video::run(&source, &output, &config, &glob, &video_config).expect("failed to run approximation video");
+            let mut glob = GlobalData::load(&config)?;
+            let options = approx_video::VideoOptions {
+                fps,
+                frame_skip: frame_skip.unwrap_or(1),
+                encoder: encoder.unwrap_or_default(),
+                crf: crf.unwrap_or(approx_video::DEFAULT_CRF),
+                preset,
+                use_system_ffmpeg,
+                mute,
+                heatmap: heatmap_out,
+                keep_temp,
+            };
+            if cli.estimate {
+                println!("{}", estimate::video(&source, &config, &glob, &options)?);
+                return Ok(());
+            }
+            let video_config = approx_video::init(&source, &output, &mut config, &mut glob, &options)?;
+            let timings = approx_video::run(&source, &output, &config, &glob, &video_config)?;
+
+            let mut summary = RunSummary::new("approx-video");
+            summary.board_width = Some(config.board_width);
+            summary.board_height = Some(config.board_height);
+            summary.stage("extract", timings.extract);
+            summary.stage("approximate", timings.approx);
+            summary.stage("encode", timings.encode);
+            summary.frames = Some(timings.frames);
+            summary.output(&output);
+            summary.outputs.extend(options.heatmap);
+            summary
         }
+        cli::Commands::SelfTest => {
+            self_test::run(&base_config)?;
+            RunSummary::new("self-test")
+        }
+        cli::Commands::Serve { address } => {
+            serve::run(address.as_deref().unwrap_or(serve::DEFAULT_ADDRESS), &base_config, &GlobalData::load(&base_config)?)?;
+            RunSummary::new("serve")
+        }
+        cli::Commands::Sweep { source, output, board_widths, prioritize, fill_styles } => {
+            sweep::run(&source, &output, &board_widths, &prioritize, &fill_styles, &base_config, &GlobalData::load(&base_config)?)?;
+            let mut summary = RunSummary::new("sweep");
+            summary.output(&output);
+            summary
+        }
+        cli::Commands::RenderBoard { board, output, block_size, skins } => {
+            let skins_dir = skins.unwrap_or_else(|| PathBuf::from("assets"));
+            board_dump::run(&board, &output, &skins_dir, block_size)?;
+            let mut summary = RunSummary::new("render-board");
+            summary.output(&output);
+            summary
+        }
+    };
+
+    if let Some(summary_path) = &cli.summary_out {
+        summary.total_seconds = start.elapsed().as_secs_f64();
+        summary.save(summary_path)?;
+        info!(path = %summary_path.display(), "saved run summary");
     }
+    Ok(())
 }
@@ -0,0 +1,106 @@
+use crate::approx_image::{self, draw::resize_skins, integration_test::diff_images_dssim, resize_image};
+use crate::cli::{Config, GlobalData};
+use crate::ffmpeg::{self, FFMPEG_ENV};
+use crate::utils::check_command_result;
+
+use std::io::Cursor;
+
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+use thiserror::Error;
+use tracing::{info, info_span};
+
+const BOARD_WIDTH: usize = 8;
+const BOARD_HEIGHT: usize = 8;
+const IMAGE_SIZE: u32 = 64;
+
+// a blocky approximation of the test image should never be this far off
+const MAX_DSSIM: f64 = 0.6;
+
+#[derive(Debug, Error)]
+pub enum SelfTestError {
+    #[error("ffmpeg could not be run; make sure it is installed and on the PATH, or set {FFMPEG_ENV} to its path: {0}")]
+    Ffmpeg(String),
+
+    #[error("no skins found; make sure the `assets` directory contains at least one skin png")]
+    MissingSkins,
+
+    #[error("approximated image has dimensions {0}x{1}, expected {2}x{3}")]
+    WrongDimensions(u32, u32, u32, u32),
+
+    #[error("approximation scored a dssim diff of {0}, expected at most {MAX_DSSIM}")]
+    PoorScore(f64),
+}
+
+// runs a quick end-to-end check of the install before starting long jobs
+pub fn run(config: &Config) -> Result<()> {
+    let _span = info_span!("self_test").entered();
+
+    // ffmpeg is only needed by the video pipeline, but it's the most common missing piece
+    let ffmpeg = ffmpeg::command()
+        .arg("-version")
+        .output()
+        .map_err(|e| SelfTestError::Ffmpeg(e.to_string()))?;
+    check_command_result(&ffmpeg).map_err(|e| SelfTestError::Ffmpeg(e.to_string()))?;
+    info!("found ffmpeg");
+
+    let has_skins = std::fs::read_dir("assets").is_ok_and(|mut files| {
+        files.any(|file| file.is_ok_and(|file| file.path().extension().is_some_and(|ext| ext == "png")))
+    });
+    if !has_skins {
+        Err(SelfTestError::MissingSkins)?;
+    }
+    let mut glob = GlobalData::load(config)?;
+    info!(num_skins = glob.skins.len(), "loaded skins");
+
+    // approximate a tiny image and make sure the result survives a png round trip
+    let mut source_img = test_image();
+    resize_skins(&mut glob.skins, IMAGE_SIZE, IMAGE_SIZE, BOARD_WIDTH, BOARD_HEIGHT)?;
+    resize_image(&mut source_img, glob.skin_width(), glob.skin_height(), BOARD_WIDTH, BOARD_HEIGHT);
+    let config = Config {
+        board_width: BOARD_WIDTH,
+        board_height: BOARD_HEIGHT,
+        ..config.clone()
+    };
+    let approx_img = approx_image::approx(&source_img, &config, &glob)?;
+
+    let mut encoded = Vec::new();
+    approx_img.write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)?;
+    let decoded = image::load_from_memory(&encoded)?;
+    let (width, height) = decoded.dimensions();
+    if (width, height) != source_img.dimensions() {
+        Err(SelfTestError::WrongDimensions(width, height, source_img.width(), source_img.height()))?;
+    }
+
+    let diff = diff_images_dssim(&decoded, &source_img);
+    if diff > MAX_DSSIM {
+        Err(SelfTestError::PoorScore(diff))?;
+    }
+    info!(diff, "approximated test image");
+
+    info!("self test passed");
+    Ok(())
+}
+
+// a small diagonal gradient, generated so the test doesn't depend on any files besides the skins
+fn test_image() -> DynamicImage {
+    let img = RgbaImage::from_fn(IMAGE_SIZE, IMAGE_SIZE, |x, y| {
+        let r = u8::try_from(x * 255 / (IMAGE_SIZE - 1)).expect("gradient should be in range");
+        let b = u8::try_from(y * 255 / (IMAGE_SIZE - 1)).expect("gradient should be in range");
+        Rgba([r, 128, b, 255])
+    });
+    DynamicImage::ImageRgba8(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_is_gradient() {
+        let img = test_image();
+        assert_eq!(img.dimensions(), (IMAGE_SIZE, IMAGE_SIZE));
+        assert_eq!(img.get_pixel(0, 0), Rgba([0, 128, 0, 255]));
+        assert_eq!(img.get_pixel(IMAGE_SIZE - 1, IMAGE_SIZE - 1), Rgba([255, 128, 255, 255]));
+    }
+}
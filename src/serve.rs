@@ -0,0 +1,229 @@
+use crate::approx_image;
+use crate::cli::{Config, GlobalData};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use tracing::{info, info_span, warn};
+
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+// larger request bodies are refused before they're read
+const MAX_BODY_BYTES: usize = 64 * 1024 * 1024;
+// the request line and headers together are cut off past this
+const MAX_HEADER_BYTES: u64 = 64 * 1024;
+
+// boards wider or taller than this are refused, since the search and output grow with the board
+const MAX_BOARD_SIZE: usize = 1024;
+
+// connections past this many at once are turned away with a 503 instead of getting their own thread
+const MAX_HANDLERS: usize = 16;
+
+// clients that stall for this long while sending or receiving are dropped, so they can't hold a handler forever
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// how often the listener checks for a cancel while no connections are coming in
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+// keeps the skins loaded and approximates images posted over http, so repeated calls skip loading and resizing skins
+// `POST /approx?width=W&height=H` with an encoded image as the body returns the approximation as a png,
+// using the global flags for everything but the board size; `GET /health` checks that the server is up
+pub fn run(address: &str, config: &Config, glob: &GlobalData) -> Result<()> {
+    let listener = TcpListener::bind(address)?;
+    // accepting without blocking lets a cancel stop the server even when it's idle
+    listener.set_nonblocking(true)?;
+    info!(address, "serving approximations");
+
+    // each connection gets its own thread, so a large image doesn't hold up small ones
+    // requests that are already being handled finish before the server stops
+    let active = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        while !config.cancel.is_cancelled() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = prepare(&stream) {
+                        warn!("failed to set up connection: {e}");
+                        continue;
+                    }
+                    if active.fetch_add(1, Ordering::SeqCst) >= MAX_HANDLERS {
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        if let Err(e) = write_response(&stream, &Response::text(503, "too many requests are being handled, try again later")) {
+                            warn!("failed to turn away request: {e:#}");
+                        }
+                        continue;
+                    }
+                    let active = &active;
+                    scope.spawn(move || {
+                        if let Err(e) = handle(&stream, config, glob) {
+                            warn!("failed to handle request: {e:#}");
+                        }
+                        active.fetch_sub(1, Ordering::SeqCst);
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_INTERVAL),
+                Err(e) => warn!("failed to accept connection: {e}"),
+            }
+        }
+    });
+    info!("stopped serving");
+    Ok(())
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn text(status: u16, text: &str) -> Response {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{text}\n").into_bytes(),
+        }
+    }
+}
+
+// accepted connections inherit the listener's non-blocking mode, which handlers don't want
+fn prepare(stream: &TcpStream) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))
+}
+
+// reads a single request off the connection and writes back the response, closing the connection afterwards
+fn handle(stream: &TcpStream, config: &Config, glob: &GlobalData) -> Result<()> {
+    let mut head = BufReader::new(stream).take(MAX_HEADER_BYTES);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default().to_string(), parts.next().unwrap_or_default().to_string());
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut reader = head.into_inner();
+
+    let _span = info_span!("request", method, target).entered();
+    let response = if content_length > MAX_BODY_BYTES {
+        Response::text(413, &format!("request body is over {MAX_BODY_BYTES} bytes"))
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        respond(&method, &target, &body, config, glob)
+    };
+    info!(status = response.status, bytes = response.body.len(), "handled request");
+    write_response(stream, &response)
+}
+
+fn write_response(mut stream: &TcpStream, response: &Response) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn respond(method: &str, target: &str, body: &[u8], config: &Config, glob: &GlobalData) -> Response {
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    match (method, path) {
+        ("GET", "/health") => Response::text(200, "ok"),
+        ("POST", "/approx") => {
+            let (board_width, board_height) = match (query_param(query, "width"), query_param(query, "height")) {
+                (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+                _ => return Response::text(400, "expected positive `width` and `height` query parameters"),
+            };
+            if board_width > MAX_BOARD_SIZE || board_height > MAX_BOARD_SIZE {
+                return Response::text(400, &format!("`width` and `height` can be at most {MAX_BOARD_SIZE}"));
+            }
+            let config = Config { board_width, board_height, ..config.clone() };
+            match approx_image::approx_bytes(body, &config, glob) {
+                Ok(png) => Response { status: 200, content_type: "image/png", body: png },
+                Err(e) => Response::text(422, &format!("{e:#}")),
+            }
+        }
+        (_, "/health" | "/approx") => Response::text(405, "method not allowed"),
+        _ => Response::text(404, "not found"),
+    }
+}
+
+// the value of a numeric query parameter, if it's there and parses
+fn query_param(query: &str, name: &str) -> Option<usize> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        422 => "Unprocessable Entity",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::test_glob;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param("width=16&height=9", "height"), Some(9));
+        assert_eq!(query_param("width=16", "height"), None);
+        assert_eq!(query_param("width=abc", "width"), None);
+    }
+
+    #[test]
+    fn test_respond() {
+        let glob = test_glob(4);
+        let config = Config::default();
+
+        let mut source = Vec::new();
+        image::DynamicImage::from(image::RgbImage::from_pixel(40, 20, image::Rgb([200, 40, 40])))
+            .write_to(&mut Cursor::new(&mut source), image::ImageFormat::Png)
+            .unwrap();
+
+        let response = respond("POST", "/approx?width=4&height=2", &source, &config, &glob);
+        assert_eq!((response.status, response.content_type), (200, "image/png"));
+        assert_eq!(image::load_from_memory(&response.body).unwrap().width() % 4, 0);
+
+        assert_eq!(respond("POST", "/approx?width=4", &source, &config, &glob).status, 400);
+        assert_eq!(respond("POST", &format!("/approx?width={}&height=2", MAX_BOARD_SIZE + 1), &source, &config, &glob).status, 400);
+        assert_eq!(respond("POST", "/approx?width=4&height=2", b"not an image", &config, &glob).status, 422);
+        assert_eq!(respond("GET", "/approx", &[], &config, &glob).status, 405);
+        assert_eq!(respond("GET", "/health", &[], &config, &glob).status, 200);
+        assert_eq!(respond("GET", "/", &[], &config, &glob).status, 404);
+    }
+}
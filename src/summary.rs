@@ -0,0 +1,112 @@
+use crate::approx_image::board::EMPTY_CELL;
+use crate::approx_image::draw::SkinnedBoard;
+use crate::approx_image::integration_test::ImageScore;
+use crate::approx_image::piece::Cell;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+
+// block names in the same order as `SkinnedBoard::piece_counts`
+const BLOCK_NAMES: [&str; 9] = ["black", "gray", "i", "o", "t", "l", "j", "s", "z"];
+
+// a machine-readable record of a run, written with `--summary-out` so pipelines can track quality and speed over time
+// fields that don't apply to a command are left null
+#[derive(Debug, Default, Serialize)]
+pub struct RunSummary {
+    pub command: String,
+    pub board_width: Option<usize>,
+    pub board_height: Option<usize>,
+    // time spent in each stage, in the order they ran
+    pub stages: Vec<StageTime>,
+    pub total_seconds: f64,
+    // pieces of each block type on the board
+    pub piece_counts: Option<BTreeMap<&'static str, usize>>,
+    // filled cells using each skin, by skin name
+    pub skin_usage: Option<BTreeMap<String, usize>>,
+    pub score: Option<ImageScore>,
+    pub frames: Option<u64>,
+    // every file written by the run
+    pub outputs: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageTime {
+    pub name: String,
+    pub seconds: f64,
+}
+
+impl RunSummary {
+    pub fn new(command: &str) -> RunSummary {
+        RunSummary {
+            command: command.to_string(),
+            ..RunSummary::default()
+        }
+    }
+
+    pub fn stage(&mut self, name: &str, elapsed: Duration) {
+        self.stages.push(StageTime { name: name.to_string(), seconds: elapsed.as_secs_f64() });
+    }
+
+    pub fn output(&mut self, path: &Path) {
+        self.outputs.push(path.to_path_buf());
+    }
+
+    // records the board's size and how its pieces and skins were used
+    pub fn record_board(&mut self, board: &SkinnedBoard) -> Result<()> {
+        self.board_width = Some(board.board_width());
+        self.board_height = Some(board.board_height());
+        self.piece_counts = Some(BLOCK_NAMES.into_iter().zip(board.piece_counts()).collect());
+
+        let mut skin_usage: BTreeMap<String, usize> = BTreeMap::new();
+        for y in 0..board.board_height() {
+            for x in 0..board.board_width() {
+                let cell = Cell { x, y };
+                if board.board().get(&cell)? != EMPTY_CELL {
+                    *skin_usage.entry(board.get_skin(board.get_cells_skin(&cell)).name().to_string()).or_default() += 1;
+                }
+            }
+        }
+        self.skin_usage = Some(skin_usage);
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::approx_image::draw::test_skin;
+    use crate::approx_image::piece::{Orientation, Piece};
+
+    #[test]
+    fn test_record_board() {
+        let mut skin = test_skin();
+        skin.resize(4, 4);
+        let skins = vec![skin];
+
+        let mut board = SkinnedBoard::new(4, 2, &skins);
+        board.place(&Piece::I(Cell { x: 0, y: 1 }, Orientation::North), 0).unwrap();
+        board.place(&Piece::Gray(Cell { x: 0, y: 0 }), 0).unwrap();
+
+        let mut summary = RunSummary::new("approx-image");
+        summary.record_board(&board).unwrap();
+        summary.stage("approximate", Duration::from_millis(1500));
+
+        let json: serde_json::Value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["board_width"], 4);
+        assert_eq!(json["piece_counts"]["i"], 1);
+        assert_eq!(json["piece_counts"]["gray"], 1);
+        assert_eq!(json["skin_usage"]["HqGYC5G - Imgur"], 5);
+        assert_eq!(json["stages"][0]["seconds"], 1.5);
+        assert!(json["score"].is_null());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::approx_image::{FillStyle, PrioritizeColor, integration_test::score_image};
+use crate::cli::{Config, GlobalData};
+use crate::utils::CancelledError;
+
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use tracing::{info, info_span, warn};
+
+// parameters tried when none are given
+const DEFAULT_BOARD_WIDTHS: [usize; 3] = [16, 32, 64];
+const DEFAULT_PRIORITIZE: [bool; 2] = [false, true];
+const DEFAULT_FILL_STYLES: [FillStyle; 1] = [FillStyle::Garbage];
+
+// approximates the source once for every combination of parameters, writing each one's scores and time to a csv
+// board heights follow the source's aspect ratio, like in the integration test
+pub fn run(source: &Path, output: &Path, board_widths: &[usize], prioritize: &[bool], fill_styles: &[FillStyle], config: &Config, glob: &GlobalData) -> Result<()> {
+    let _span = info_span!("sweep", source = %source.display()).entered();
+    let board_widths = if board_widths.is_empty() { &DEFAULT_BOARD_WIDTHS[..] } else { board_widths };
+    let prioritize = if prioritize.is_empty() { &DEFAULT_PRIORITIZE[..] } else { prioritize };
+    let fill_styles = if fill_styles.is_empty() { &DEFAULT_FILL_STYLES[..] } else { fill_styles };
+
+    let mut writer = csv::Writer::from_path(output)?;
+    writer.write_record(["board_width", "prioritize_tetrominos", "fill_style", "dssim", "similarity", "seconds"])?;
+    for &board_width in board_widths {
+        for &prioritize_tetrominos in prioritize {
+            // fill styles only apply when prioritizing tetrominos, so the rest would be repeats
+            let fill_styles = if prioritize_tetrominos { fill_styles } else { &fill_styles[..1] };
+            for &fill_style in fill_styles {
+                let case_config = Config {
+                    board_width,
+                    prioritize_tetrominos: if prioritize_tetrominos { PrioritizeColor::Yes } else { PrioritizeColor::No },
+                    fill_style,
+                    ..config.clone()
+                };
+
+                let start = Instant::now();
+                let score = score_image(source, &case_config, glob)?;
+                let seconds = start.elapsed().as_secs_f64();
+                // a cancelled case was cut short, so only the finished ones are kept
+                if config.cancel.is_cancelled() {
+                    writer.flush()?;
+                    warn!(output = %output.display(), "cancelled, saved the finished sweep cases");
+                    return Err(CancelledError.into());
+                }
+                info!(board_width, prioritize_tetrominos, ?fill_style, dssim = score.dssim, seconds, "finished sweep case");
+
+                let fill_style = if prioritize_tetrominos {
+                    fill_style.to_possible_value().map_or_else(String::new, |value| value.get_name().to_string())
+                } else {
+                    String::new()
+                };
+                writer.write_record([
+                    board_width.to_string(),
+                    prioritize_tetrominos.to_string(),
+                    fill_style,
+                    score.dssim.to_string(),
+                    score.similarity.to_string(),
+                    seconds.to_string(),
+                ])?;
+            }
+        }
+    }
+    writer.flush()?;
+    info!(output = %output.display(), "saved sweep results");
+    Ok(())
+}
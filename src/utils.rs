@@ -1,6 +1,25 @@
+use std::io::{self, Write};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use thiserror::Error;
+use tracing::{warn, Level};
+
+// how often the reporter thread pushes counts to its progress bar
+const REPORT_INTERVAL: Duration = Duration::from_millis(100);
+
+// set when logs are emitted as json, in which case progress bars would corrupt the output
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
+// set by `--no-progress`, for ci logs and other non-interactive output
+static NO_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+// every progress bar is drawn through this so that concurrent bars and log lines don't clobber each other
+static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
 
 #[derive(Error, Debug)]
 pub enum CommandError {
@@ -8,6 +27,49 @@ pub enum CommandError {
     Default(String),
 }
 
+#[derive(Error, Debug)]
+#[error("operation was cancelled")]
+pub struct CancelledError;
+
+// returned by batch runs that finished after skipping the items that failed, so the caller can still report a failure
+#[derive(Error, Debug)]
+#[error("{failed} of {total} {items} failed")]
+pub struct PartialFailure {
+    pub failed: usize,
+    pub total: usize,
+    pub items: &'static str,
+}
+
+// shared flag that lets embedding applications abort long-running work
+// clones share the same flag, so cancelling any clone cancels all of them
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// cancels `token` on the first ctrl-c so work can wind down, save what it has, and clean up after itself
+// a second ctrl-c exits right away
+pub fn cancel_on_ctrl_c(token: CancellationToken) -> Result<()> {
+    ctrlc::set_handler(move || {
+        if token.is_cancelled() {
+            std::process::exit(130);
+        }
+        warn!("cancelling, press ctrl-c again to quit immediately");
+        token.cancel();
+    })?;
+    Ok(())
+}
+
 pub fn check_command_result(result: &std::process::Output) -> Result<()> {
     match result.status.code() {
         Some(0) => Ok(()),
@@ -15,11 +77,137 @@ pub fn check_command_result(result: &std::process::Output) -> Result<()> {
     }
 }
 
+// installs the global log subscriber
+// by default info and above are logged; each `verbose` level adds debug then trace, while `quiet` only keeps warnings and errors
+pub fn init_logging(verbose: u8, quiet: bool, json: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(|| ProgressWriter);
+    if json {
+        LOG_JSON.store(true, Ordering::Relaxed);
+        builder.json().with_current_span(true).init();
+    } else {
+        builder.without_time().with_target(false).init();
+    }
+}
+
+// hides every progress bar created from now on
+pub fn hide_progress() {
+    NO_PROGRESS.store(true, Ordering::Relaxed);
+}
+
+fn progress() -> &'static MultiProgress {
+    PROGRESS.get_or_init(MultiProgress::new)
+}
+
+// writes log lines above the progress bars instead of through them
+struct ProgressWriter;
+
+impl Write for ProgressWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        progress().suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+// progress bars are hidden when logging json or with `--no-progress`; progress is reported through debug events instead
+// visible bars are stacked together, so several can run at once
 pub fn progress_bar(pb_len: usize) -> Result<ProgressBar> {
+    progress_bar_with(pb_len, LOG_JSON.load(Ordering::Relaxed) || NO_PROGRESS.load(Ordering::Relaxed))
+}
+
+fn progress_bar_with(pb_len: usize, hidden: bool) -> Result<ProgressBar> {
+    if hidden {
+        return Ok(ProgressBar::hidden());
+    }
+
     let spinner_style = ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")?
         .tick_chars("##-");
     let pb = ProgressBar::new(u64::try_from(pb_len)?);
     pb.set_style(spinner_style.clone());
-    Ok(pb)
+    Ok(progress().add(pb))
+}
+
+// counts finished work from any number of threads without locking
+#[derive(Clone, Debug, Default)]
+pub struct ProgressCounter {
+    done: Arc<AtomicU64>,
+}
+
+impl ProgressCounter {
+    pub fn inc(&self) -> u64 {
+        self.done.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn get(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
 }
 
+// owns a progress bar and updates it from a single thread at a fixed interval
+// workers only bump the shared counter, so the bar's internal lock is never contended
+pub struct ProgressReporter {
+    pb: ProgressBar,
+    counter: ProgressCounter,
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    pub fn start(pb: ProgressBar) -> ProgressReporter {
+        let counter = ProgressCounter::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (pb, counter, stop) = (pb.clone(), counter.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    pb.set_position(counter.get());
+                    thread::sleep(REPORT_INTERVAL);
+                }
+            })
+        };
+        ProgressReporter { pb, counter, stop, handle }
+    }
+
+    pub fn counter(&self) -> &ProgressCounter {
+        &self.counter
+    }
+
+    // stops the reporter thread and returns the progress bar with its final count
+    pub fn stop(self) -> ProgressBar {
+        self.stop.store(true, Ordering::Relaxed);
+        self.handle.join().expect("progress reporter thread panicked");
+        self.pb.set_position(self.counter.get());
+        self.pb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_reporter_counts_every_increment() {
+        let reporter = ProgressReporter::start(ProgressBar::hidden());
+        (0..1000).into_par_iter().for_each(|_| {
+            reporter.counter().inc();
+        });
+        assert_eq!(reporter.stop().position(), 1000);
+    }
+
+    #[test]
+    fn test_hide_progress() {
+        assert!(progress_bar_with(10, true).unwrap().is_hidden());
+    }
+}